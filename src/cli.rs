@@ -42,6 +42,9 @@ pub struct Cli {
     /// Serve the LLM API and WebAPP
     #[clap(long, value_name = "ADDRESS")]
     pub serve: Option<Option<String>>,
+    /// Serve roles, RAGs and macros as a MCP server over stdio
+    #[clap(long)]
+    pub mcp: bool,
     /// Execute commands in natural language
     #[clap(short = 'e', long)]
     pub execute: bool,
@@ -51,6 +54,9 @@ pub struct Cli {
     /// Include files, directories, or URLs
     #[clap(short = 'f', long, value_name = "FILE")]
     pub file: Vec<String>,
+    /// Constrain the reply to the JSON schema in the file
+    #[clap(long, value_name = "FILE")]
+    pub schema: Option<String>,
     /// Turn off stream mode
     #[clap(short = 'S', long)]
     pub no_stream: bool,
@@ -60,6 +66,9 @@ pub struct Cli {
     /// Display information
     #[clap(long)]
     pub info: bool,
+    /// Show token usage and cost
+    #[clap(long)]
+    pub usage: bool,
     /// Sync models updates
     #[clap(long)]
     pub sync_models: bool,
@@ -72,6 +81,12 @@ pub struct Cli {
     /// List all sessions
     #[clap(long)]
     pub list_sessions: bool,
+    /// Search saved sessions and messages for the query
+    #[clap(long, value_name = "QUERY")]
+    pub search_history: Option<String>,
+    /// Re-run a logged chat request (line number in the chat log, or 'last') against the current model
+    #[clap(long, value_name = "ID")]
+    pub replay: Option<String>,
     /// List all agents
     #[clap(long)]
     pub list_agents: bool,
@@ -89,7 +104,7 @@ pub struct Cli {
 impl Cli {
     pub fn text(&self) -> Result<Option<String>> {
         let mut stdin_text = String::new();
-        if !stdin().is_terminal() {
+        if !stdin().is_terminal() && !self.mcp {
             let _ = stdin()
                 .read_to_string(&mut stdin_text)
                 .context("Invalid stdin pipe")?;
@@ -171,14 +171,9 @@ impl Client for BedrockClient {
 }
 
 async fn chat_completions(builder: RequestBuilder) -> Result<ChatCompletionsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let data: Value = res.json().await?;
 
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
-
     debug!("non-stream-data: {data}");
     extract_chat_completions(&data)
 }
@@ -187,18 +182,11 @@ async fn chat_completions_streaming(
     builder: RequestBuilder,
     handler: &mut SseHandler,
 ) -> Result<()> {
-    let res = builder.send().await?;
-    let status = res.status();
-    if !status.is_success() {
-        let data: Value = res.json().await?;
-        catch_error(&data, status.as_u16())?;
-        bail!("Invalid response data: {data}");
-    }
+    let res = send_request(builder).await?;
 
     let mut function_name = String::new();
     let mut function_arguments = String::new();
     let mut function_id = String::new();
-    let mut reasoning_state = 0;
 
     let mut stream = res.bytes_stream();
     let mut buffer = BytesMut::new();
@@ -241,39 +229,45 @@ async fn chat_completions_streaming(
                                 }
                             }
                         }
+                        "metadata" => {
+                            handler.usage(
+                                data["usage"]["inputTokens"].as_u64(),
+                                data["usage"]["outputTokens"].as_u64(),
+                            );
+                        }
                         "contentBlockDelta" => {
                             if let Some(text) = data["delta"]["text"].as_str() {
                                 handler.text(text)?;
-                            } else if let Some(text) =
-                                data["delta"]["reasoningContent"]["text"].as_str()
+                            } else if let Some(reasoning) =
+                                data["delta"]["reasoningContent"].as_object()
                             {
-                                if reasoning_state == 0 {
-                                    handler.text("<think>\n")?;
-                                    reasoning_state = 1;
+                                if let Some(text) = json_str_from_map(reasoning, "text") {
+                                    handler.thinking(text)?;
+                                } else if let Some(signature) =
+                                    json_str_from_map(reasoning, "signature")
+                                {
+                                    handler.thinking_signature(signature);
+                                } else if let Some(data) =
+                                    json_str_from_map(reasoning, "redactedContent")
+                                {
+                                    handler.redacted_thinking(data);
                                 }
-                                handler.text(text)?;
                             } else if let Some(input) = data["delta"]["toolUse"]["input"].as_str() {
                                 function_arguments.push_str(input);
                             }
                         }
-                        "contentBlockStop" => {
-                            if reasoning_state == 1 {
-                                handler.text("\n</think>\n\n")?;
-                                reasoning_state = 0;
-                            }
-                            if !function_name.is_empty() {
-                                if function_arguments.is_empty() {
-                                    function_arguments = String::from("{}");
-                                }
-                                let arguments: Value = function_arguments.parse().with_context(|| {
-                                    format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                                })?;
-                                handler.tool_call(ToolCall::new(
-                                    function_name.clone(),
-                                    arguments,
-                                    Some(function_id.clone()),
-                                ))?;
+                        "contentBlockStop" if !function_name.is_empty() => {
+                            if function_arguments.is_empty() {
+                                function_arguments = String::from("{}");
                             }
+                            let arguments: Value = function_arguments.parse().with_context(|| {
+                                format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
+                            })?;
+                            handler.tool_call(ToolCall::new(
+                                function_name.clone(),
+                                arguments,
+                                Some(function_id.clone()),
+                            ))?;
                         }
                         _ => {}
                     }
@@ -294,14 +288,9 @@ async fn chat_completions_streaming(
 }
 
 async fn embeddings(builder: RequestBuilder) -> Result<EmbeddingsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let data: Value = res.json().await?;
 
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
-
     let res_body: EmbeddingsResBody =
         serde_json::from_value(data).context("Invalid embeddings data")?;
     Ok(res_body.embeddings)
@@ -319,6 +308,8 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
         top_p,
         functions,
         stream: _,
+        response_format: _,
+        reasoning_effort,
     } = data;
 
     let system_message = extract_system_message(&mut messages);
@@ -330,7 +321,7 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
         .into_iter()
         .enumerate()
         .flat_map(|(i, message)| {
-            let Message { role, content } = message;
+            let Message { role, content, .. } = message;
             match content {
                 MessageContent::Text(text) if role.is_assistant() && i != messages_len - 1 => {
                     vec![json!({ "role": role, "content": [ { "text": strip_think_tag(&text) } ] })]
@@ -378,10 +369,26 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
                     })]
                 }
                 MessageContent::ToolCalls(MessageContentToolCalls {
-                    tool_results, text, ..
+                    tool_results,
+                    text,
+                    thinking,
+                    ..
                 }) => {
                     let mut assistant_parts = vec![];
                     let mut user_parts = vec![];
+                    for block in thinking.into_iter().filter(|v| v.is_from(model)) {
+                        match (block.redacted, block.signature) {
+                            (Some(data), _) => assistant_parts.push(json!({
+                                "reasoningContent": { "redactedContent": data }
+                            })),
+                            (None, Some(signature)) => assistant_parts.push(json!({
+                                "reasoningContent": {
+                                    "reasoningText": { "text": block.text, "signature": signature }
+                                }
+                            })),
+                            _ => {}
+                        }
+                    }
                     if !text.is_empty() {
                         assistant_parts.push(json!({
                             "text": text,
@@ -443,11 +450,28 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
     if let Some(v) = model.max_tokens_param() {
         body["inferenceConfig"]["maxTokens"] = v.into();
     }
-    if let Some(v) = temperature {
-        body["inferenceConfig"]["temperature"] = v.into();
-    }
-    if let Some(v) = top_p {
-        body["inferenceConfig"]["topP"] = v.into();
+    match reasoning_effort {
+        Some(v) => {
+            let budget_tokens = v.budget_tokens();
+            body["additionalModelRequestFields"] = json!({
+                "thinking": { "type": "enabled", "budget_tokens": budget_tokens }
+            });
+            let max_tokens = body["inferenceConfig"]["maxTokens"]
+                .as_u64()
+                .unwrap_or_default();
+            if max_tokens <= budget_tokens {
+                body["inferenceConfig"]["maxTokens"] =
+                    (budget_tokens + max_tokens.max(4096)).into();
+            }
+        }
+        None => {
+            if let Some(v) = temperature {
+                body["inferenceConfig"]["temperature"] = v.into();
+            }
+            if let Some(v) = top_p {
+                body["inferenceConfig"]["topP"] = v.into();
+            }
+        }
     }
     if let Some(functions) = functions {
         let tools: Vec<_> = functions
@@ -473,7 +497,7 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
 
 fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
     let mut text = String::new();
-    let mut reasoning = None;
+    let mut thinking = vec![];
     let mut tool_calls = vec![];
     if let Some(array) = data["output"]["message"]["content"].as_array() {
         for item in array {
@@ -486,8 +510,18 @@ fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
                 item["reasoningContent"]["reasoningText"].as_object()
             {
                 if let Some(text) = json_str_from_map(reasoning_text, "text") {
-                    reasoning = Some(text.to_string());
+                    thinking.push(ThinkingBlock {
+                        text: text.to_string(),
+                        signature: json_str_from_map(reasoning_text, "signature")
+                            .map(|v| v.to_string()),
+                        ..Default::default()
+                    });
                 }
+            } else if let Some(data) = item["reasoningContent"]["redactedContent"].as_str() {
+                thinking.push(ThinkingBlock {
+                    redacted: Some(data.to_string()),
+                    ..Default::default()
+                });
             } else if let Some(tool_use) = item["toolUse"].as_object() {
                 if let (Some(id), Some(name), Some(input)) = (
                     json_str_from_map(tool_use, "toolUseId"),
@@ -504,16 +538,13 @@ fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
         }
     }
 
-    if let Some(reasoning) = reasoning {
-        text = format!("<think>\n{reasoning}\n</think>\n\n{text}")
-    }
-
     if text.is_empty() && tool_calls.is_empty() {
         bail!("Invalid response data: {data}");
     }
 
     let output = ChatCompletionsOutput {
         text,
+        thinking,
         tool_calls,
         id: None,
         input_tokens: data["usage"]["inputTokens"].as_u64(),
@@ -0,0 +1,178 @@
+use super::{ChatCompletionsData, ChatCompletionsOutput, Model, ThinkingBlock, ToolCall};
+
+use crate::config::Config;
+use crate::utils::sha256;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    fs::{create_dir_all, read_to_string, remove_file, write},
+    path::PathBuf,
+};
+
+const CHAT_DIR_NAME: &str = "chat";
+const EMBEDDINGS_DIR_NAME: &str = "embeddings";
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    /// Seconds a cached response stays valid, 0 keeps it forever
+    pub ttl: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ttl: 7 * 24 * 3600,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry<T> {
+    created_at: i64,
+    value: T,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedOutput {
+    text: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    thinking: Vec<ThinkingBlock>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ToolCall>,
+}
+
+/// An on-disk cache of chat completions and embeddings, keyed on the request and the model.
+#[derive(Debug)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: u64,
+}
+
+impl ResponseCache {
+    pub fn new(config: &Config) -> Option<Self> {
+        if !config.cache.enabled {
+            return None;
+        }
+        Some(Self {
+            dir: Config::cache_dir(),
+            ttl: config.cache.ttl,
+        })
+    }
+
+    /// The `stream` flag is left out, so streamed and non-streamed requests share entries.
+    pub fn chat_key(model: &Model, data: &ChatCompletionsData) -> String {
+        let value = json!({
+            "model": model.id(),
+            "max_tokens": model.max_tokens_param(),
+            "messages": data.messages,
+            "temperature": data.temperature,
+            "top_p": data.top_p,
+            "functions": data.functions,
+            "response_format": data.response_format,
+            "reasoning_effort": data.reasoning_effort,
+        });
+        sha256(&value.to_string())
+    }
+
+    pub fn get_chat(&self, key: &str) -> Option<ChatCompletionsOutput> {
+        let CachedOutput {
+            text,
+            thinking,
+            tool_calls,
+        } = self.get(CHAT_DIR_NAME, key)?;
+        Some(ChatCompletionsOutput {
+            text,
+            thinking,
+            tool_calls,
+            ..Default::default()
+        })
+    }
+
+    pub fn set_chat(&self, key: &str, output: &ChatCompletionsOutput) {
+        let value = CachedOutput {
+            text: output.text.clone(),
+            thinking: output.thinking.clone(),
+            tool_calls: output.tool_calls.clone(),
+        };
+        self.set(CHAT_DIR_NAME, key, value);
+    }
+
+    pub fn embedding_key(model: &Model, text: &str, query: bool) -> String {
+        let value = json!({ "model": model.id(), "query": query, "text": text });
+        sha256(&value.to_string())
+    }
+
+    pub fn get_embedding(&self, key: &str) -> Option<Vec<f32>> {
+        self.get(EMBEDDINGS_DIR_NAME, key)
+    }
+
+    pub fn set_embedding(&self, key: &str, embedding: &[f32]) {
+        self.set(EMBEDDINGS_DIR_NAME, key, embedding);
+    }
+
+    fn get<T: DeserializeOwned>(&self, kind: &str, key: &str) -> Option<T> {
+        let path = self.dir.join(kind).join(format!("{key}.json"));
+        let content = read_to_string(&path).ok()?;
+        let entry: CacheEntry<T> = serde_json::from_str(&content).ok()?;
+        if self.ttl > 0 && Utc::now().timestamp() - entry.created_at > self.ttl as i64 {
+            let _ = remove_file(&path);
+            return None;
+        }
+        debug!("Cache hit: {}", path.display());
+        Some(entry.value)
+    }
+
+    fn set<T: Serialize>(&self, kind: &str, key: &str, value: T) {
+        let entry = CacheEntry {
+            created_at: Utc::now().timestamp(),
+            value,
+        };
+        if let Err(err) = self.write(kind, key, &entry) {
+            warn!("{err:#}");
+        }
+    }
+
+    fn write<T: Serialize>(&self, kind: &str, key: &str, entry: &CacheEntry<T>) -> Result<()> {
+        let dir = self.dir.join(kind);
+        create_dir_all(&dir)
+            .with_context(|| format!("Failed to create cache dir '{}'", dir.display()))?;
+        let path = dir.join(format!("{key}.json"));
+        write(&path, serde_json::to_string(entry)?)
+            .with_context(|| format!("Failed to write cache '{}'", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_key_ignores_stream() {
+        let model = Model::new("openai", "gpt-4o");
+        let mut data = ChatCompletionsData {
+            messages: vec![],
+            temperature: Some(0.0),
+            top_p: None,
+            functions: None,
+            stream: false,
+            response_format: None,
+            reasoning_effort: None,
+        };
+        let key = ResponseCache::chat_key(&model, &data);
+        data.stream = true;
+        assert_eq!(key, ResponseCache::chat_key(&model, &data));
+        data.temperature = Some(0.5);
+        assert_ne!(key, ResponseCache::chat_key(&model, &data));
+        let other_model = Model::new("claude", "claude-3-5-haiku-latest");
+        assert_ne!(
+            ResponseCache::embedding_key(&model, "hello", false),
+            ResponseCache::embedding_key(&other_model, "hello", false)
+        );
+    }
+}
@@ -9,6 +9,8 @@ use serde_json::{json, Value};
 
 const API_BASE: &str = "https://api.anthropic.com/v1";
 
+const RESPONSE_FORMAT_TOOL: &str = "json_response";
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct ClaudeConfig {
     pub name: Option<String>,
@@ -64,12 +66,8 @@ pub async fn claude_chat_completions(
     builder: RequestBuilder,
     _model: &Model,
 ) -> Result<ChatCompletionsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
     debug!("non-stream-data: {data}");
     claude_extract_chat_completions(&data)
 }
@@ -82,14 +80,28 @@ pub async fn claude_chat_completions_streaming(
     let mut function_name = String::new();
     let mut function_arguments = String::new();
     let mut function_id = String::new();
-    let mut reasoning_state = 0;
     let handle = |message: SseMmessage| -> Result<bool> {
         let data: Value = serde_json::from_str(&message.data)?;
         debug!("stream-data: {data}");
         if let Some(typ) = data["type"].as_str() {
             match typ {
+                "message_start" => {
+                    let usage = &data["message"]["usage"];
+                    handler.usage(
+                        usage["input_tokens"].as_u64(),
+                        usage["output_tokens"].as_u64(),
+                    );
+                }
+                "message_delta" => {
+                    handler.usage(None, data["usage"]["output_tokens"].as_u64());
+                }
                 "content_block_start" => {
-                    if let (Some("tool_use"), Some(name), Some(id)) = (
+                    if let (Some("redacted_thinking"), Some(data)) = (
+                        data["content_block"]["type"].as_str(),
+                        data["content_block"]["data"].as_str(),
+                    ) {
+                        handler.redacted_thinking(data);
+                    } else if let (Some("tool_use"), Some(name), Some(id)) = (
                         data["content_block"]["type"].as_str(),
                         data["content_block"]["name"].as_str(),
                         data["content_block"]["id"].as_str(),
@@ -114,11 +126,9 @@ pub async fn claude_chat_completions_streaming(
                     if let Some(text) = data["delta"]["text"].as_str() {
                         handler.text(text)?;
                     } else if let Some(text) = data["delta"]["thinking"].as_str() {
-                        if reasoning_state == 0 {
-                            handler.text("<think>\n")?;
-                            reasoning_state = 1;
-                        }
-                        handler.text(text)?;
+                        handler.thinking(text)?;
+                    } else if let Some(signature) = data["delta"]["signature"].as_str() {
+                        handler.thinking_signature(signature);
                     } else if let (true, Some(partial_json)) = (
                         !function_name.is_empty(),
                         data["delta"]["partial_json"].as_str(),
@@ -127,11 +137,10 @@ pub async fn claude_chat_completions_streaming(
                     }
                 }
                 "content_block_stop" => {
-                    if reasoning_state == 1 {
-                        handler.text("\n</think>\n\n")?;
-                        reasoning_state = 0;
-                    }
-                    if !function_name.is_empty() {
+                    if function_name == RESPONSE_FORMAT_TOOL {
+                        handler.text(&function_arguments)?;
+                        function_name.clear();
+                    } else if !function_name.is_empty() {
                         let arguments: Value = if function_arguments.is_empty() {
                             json!({})
                         } else {
@@ -165,6 +174,8 @@ pub fn claude_build_chat_completions_body(
         top_p,
         functions,
         stream,
+        response_format,
+        reasoning_effort,
     } = data;
 
     let system_message = extract_system_message(&mut messages);
@@ -176,7 +187,7 @@ pub fn claude_build_chat_completions_body(
         .into_iter()
         .enumerate()
         .flat_map(|(i, message)| {
-            let Message { role, content } = message;
+            let Message { role, content, .. } = message;
             match content {
                 MessageContent::Text(text) if role.is_assistant() && i != messages_len - 1 => {
                     vec![json!({ "role": role, "content": strip_think_tag(&text) })]
@@ -220,10 +231,28 @@ pub fn claude_build_chat_completions_body(
                     })]
                 }
                 MessageContent::ToolCalls(MessageContentToolCalls {
-                    tool_results, text, ..
+                    tool_results,
+                    text,
+                    thinking,
+                    ..
                 }) => {
                     let mut assistant_parts = vec![];
                     let mut user_parts = vec![];
+                    // Signed thinking must lead the turn, blocks of other models would not verify
+                    for block in thinking.into_iter().filter(|v| v.is_from(model)) {
+                        match (block.redacted, block.signature) {
+                            (Some(data), _) => assistant_parts.push(json!({
+                                "type": "redacted_thinking",
+                                "data": data,
+                            })),
+                            (None, Some(signature)) => assistant_parts.push(json!({
+                                "type": "thinking",
+                                "thinking": block.text,
+                                "signature": signature,
+                            })),
+                            _ => {}
+                        }
+                    }
                     if !text.is_empty() {
                         assistant_parts.push(json!({
                             "type": "text",
@@ -275,11 +304,24 @@ pub fn claude_build_chat_completions_body(
     if let Some(v) = model.max_tokens_param() {
         body["max_tokens"] = v.into();
     }
-    if let Some(v) = temperature {
-        body["temperature"] = v.into();
-    }
-    if let Some(v) = top_p {
-        body["top_p"] = v.into();
+    match reasoning_effort {
+        Some(v) => {
+            // Thinking rejects sampling overrides and needs room for the answer beyond the budget.
+            let budget_tokens = v.budget_tokens();
+            body["thinking"] = json!({ "type": "enabled", "budget_tokens": budget_tokens });
+            let max_tokens = body["max_tokens"].as_u64().unwrap_or_default();
+            if max_tokens <= budget_tokens {
+                body["max_tokens"] = (budget_tokens + max_tokens.max(4096)).into();
+            }
+        }
+        None => {
+            if let Some(v) = temperature {
+                body["temperature"] = v.into();
+            }
+            if let Some(v) = top_p {
+                body["top_p"] = v.into();
+            }
+        }
     }
     if stream {
         body["stream"] = true.into();
@@ -296,19 +338,53 @@ pub fn claude_build_chat_completions_body(
             })
             .collect();
     }
+    // Claude has no JSON mode, a forced tool call carries the reply instead.
+    if let Some(schema) = response_format.filter(|v| v["type"] == "object") {
+        let tool = json!({
+            "name": RESPONSE_FORMAT_TOOL,
+            "description": "Respond to the user with a JSON value.",
+            "input_schema": schema,
+        });
+        // With other tools around, forcing a tool would rule out a plain answer, so it is left to the model.
+        match body["tools"].as_array_mut() {
+            Some(tools) => tools.push(tool),
+            None => {
+                body["tools"] = json!([tool]);
+                body["tool_choice"] = json!({ "type": "tool", "name": RESPONSE_FORMAT_TOOL });
+            }
+        }
+        // Forced tool use is not allowed while thinking, the reply is validated locally then.
+        if reasoning_effort.is_some() {
+            if let Some(obj) = body.as_object_mut() {
+                obj.remove("tool_choice");
+            }
+        }
+    }
     Ok(body)
 }
 
 pub fn claude_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
     let mut text = String::new();
-    let mut reasoning = None;
+    let mut thinking = vec![];
     let mut tool_calls = vec![];
     if let Some(list) = data["content"].as_array() {
         for item in list {
             match item["type"].as_str() {
                 Some("thinking") => {
                     if let Some(v) = item["thinking"].as_str() {
-                        reasoning = Some(v.to_string());
+                        thinking.push(ThinkingBlock {
+                            text: v.to_string(),
+                            signature: item["signature"].as_str().map(|v| v.to_string()),
+                            ..Default::default()
+                        });
+                    }
+                }
+                Some("redacted_thinking") => {
+                    if let Some(v) = item["data"].as_str() {
+                        thinking.push(ThinkingBlock {
+                            redacted: Some(v.to_string()),
+                            ..Default::default()
+                        });
                     }
                 }
                 Some("text") => {
@@ -319,6 +395,9 @@ pub fn claude_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
                         text.push_str(v);
                     }
                 }
+                Some("tool_use") if item["name"] == RESPONSE_FORMAT_TOOL => {
+                    text.push_str(&item["input"].to_string());
+                }
                 Some("tool_use") => {
                     if let (Some(name), Some(input), Some(id)) = (
                         item["name"].as_str(),
@@ -336,16 +415,13 @@ pub fn claude_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
             }
         }
     }
-    if let Some(reasoning) = reasoning {
-        text = format!("<think>\n{reasoning}\n</think>\n\n{text}")
-    }
-
     if text.is_empty() && tool_calls.is_empty() {
         bail!("Invalid response data: {data}");
     }
 
     let output = ChatCompletionsOutput {
         text: text.to_string(),
+        thinking,
         tool_calls,
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["input_tokens"].as_u64(),
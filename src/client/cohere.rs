@@ -53,6 +53,16 @@ fn prepare_chat_completions(
         if let Some(top_p) = obj.remove("top_p") {
             obj.insert("p".to_string(), top_p);
         }
+        obj.remove("reasoning_effort");
+        if let Some(response_format) = obj.remove("response_format") {
+            obj.insert(
+                "response_format".to_string(),
+                json!({
+                    "type": "json_object",
+                    "json_schema": response_format["json_schema"]["schema"],
+                }),
+            );
+        }
     }
 
     let mut request_data = RequestData::new(url, body);
@@ -109,12 +119,8 @@ async fn chat_completions(
     builder: RequestBuilder,
     _model: &Model,
 ) -> Result<ChatCompletionsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
 
     debug!("non-stream-data: {data}");
     extract_chat_completions(&data)
@@ -179,6 +185,13 @@ async fn chat_completions_streaming(
                     function_arguments.clear();
                     function_id.clear();
                 }
+                "message-end" => {
+                    let usage = &data["delta"]["usage"]["billed_units"];
+                    handler.usage(
+                        usage["input_tokens"].as_u64(),
+                        usage["output_tokens"].as_u64(),
+                    );
+                }
                 _ => {}
             }
         }
@@ -189,12 +202,8 @@ async fn chat_completions_streaming(
 }
 
 async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
     let res_body: EmbeddingsResBody =
         serde_json::from_value(data).context("Invalid embeddings data")?;
     Ok(res_body.embeddings.float)
@@ -250,6 +259,7 @@ fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["billed_units"]["input_tokens"].as_u64(),
         output_tokens: data["usage"]["billed_units"]["output_tokens"].as_u64(),
+        ..Default::default()
     };
     Ok(output)
 }
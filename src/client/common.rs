@@ -1,7 +1,7 @@
 use super::*;
 
 use crate::{
-    config::{Config, GlobalConfig, Input},
+    config::{Config, GlobalConfig, Input, RetryConfig, RoleLike, Usage},
     function::{eval_tool_calls, FunctionDeclaration, ToolCall, ToolResult},
     render::render_stream,
     utils::*,
@@ -11,13 +11,13 @@ use anyhow::{bail, Context, Result};
 use fancy_regex::Regex;
 use indexmap::IndexMap;
 use inquire::{
-    list_option::ListOption, required, validator::Validation, MultiSelect, Select, Text,
+    list_option::ListOption, required, validator::Validation, Confirm, MultiSelect, Select, Text,
 };
 use reqwest::{Client as ReqwestClient, RequestBuilder};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::sync::LazyLock;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::unbounded_channel;
 
 const MODELS_YAML: &str = include_str!("../../models.yaml");
@@ -32,6 +32,8 @@ static EMBEDDING_MODEL_RE: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r"((^|/)(bge-|e5-|uae-|gte-|text-)|embed|multilingual|minilm)").unwrap()
 });
 
+const RESPONSE_FORMAT_MAX_RETRIES: usize = 2;
+
 static ESCAPE_SLASH_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?<!\\)/").unwrap());
 
 #[async_trait::async_trait]
@@ -70,46 +72,333 @@ pub trait Client: Sync + Send {
             let content = input.echo_messages();
             return Ok(ChatCompletionsOutput::new(&content));
         }
-        let client = self.build_client()?;
+        Config::load_mcp_servers(self.global_config(), input.role().use_tools().as_deref()).await;
         let data = input.prepare_completion_data(self.model(), false)?;
-        self.chat_completions_inner(&client, data)
-            .await
-            .with_context(|| "Failed to call chat-completions api")
+        self.send_chat_completions(Some(&input), data).await
+    }
+
+    /// Sends the request data through the response cache, the budget, the chat log and the
+    /// response format check, `input` is None for the requests of the server.
+    async fn send_chat_completions(
+        &self,
+        input: Option<&Input>,
+        data: ChatCompletionsData,
+    ) -> Result<ChatCompletionsOutput> {
+        let client = self.build_client()?;
+        let cache = ResponseCache::new(&self.global_config().read())
+            .map(|v| (v, ResponseCache::chat_key(self.model(), &data)));
+        if let Some(output) = cache.as_ref().and_then(|(cache, key)| cache.get_chat(key)) {
+            self.log_cached_chat(input, &data, &output);
+            return Ok(output);
+        }
+        let input_tokens = self.model().total_tokens(&data.messages);
+        self.guard_budget(input, input_tokens)?;
+        let mut output = self
+            .send_logged(&client, input, data.clone(), input_tokens)
+            .await?;
+        // Only a reply that matches the response format goes into the cache
+        if let Some(schema) = data.response_format.clone() {
+            output = self
+                .repair_response_format(&client, input, &data, &schema, output)
+                .await?;
+        }
+        if let Some((cache, key)) = &cache {
+            cache.set_chat(key, &output);
+        }
+        Ok(output)
+    }
+
+    /// Asks the model again with the validation error until the reply matches the schema.
+    async fn repair_response_format(
+        &self,
+        client: &ReqwestClient,
+        input: Option<&Input>,
+        data: &ChatCompletionsData,
+        schema: &Value,
+        mut output: ChatCompletionsOutput,
+    ) -> Result<ChatCompletionsOutput> {
+        let mut feedback_messages = vec![];
+        for retry in 0.. {
+            if !output.tool_calls.is_empty() {
+                break;
+            }
+            match parse_response_format(&output.text, schema) {
+                Ok(text) => {
+                    output.text = text;
+                    break;
+                }
+                Err(err) if retry < RESPONSE_FORMAT_MAX_RETRIES => {
+                    debug!("invalid response format: {err:#}");
+                    feedback_messages.push(Message::new(
+                        MessageRole::Assistant,
+                        MessageContent::Text(output.text.clone()),
+                    ));
+                    feedback_messages.push(Message::new(
+                        MessageRole::User,
+                        MessageContent::Text(format!(
+                            "Your reply is invalid: {err:#}. Respond again with only a JSON value that matches the schema."
+                        )),
+                    ));
+                    let mut data = data.clone();
+                    data.messages.extend(feedback_messages.iter().cloned());
+                    let input_tokens = self.model().total_tokens(&data.messages);
+                    self.guard_budget(input, input_tokens)?;
+                    output = self.send_logged(client, input, data, input_tokens).await?;
+                }
+                Err(err) => bail!("The reply does not match the response format: {err:#}"),
+            }
+        }
+        Ok(output)
     }
 
     async fn chat_completions_streaming(
         &self,
         input: &Input,
         handler: &mut SseHandler,
+    ) -> Result<()> {
+        if self.global_config().read().dry_run {
+            let content = input.echo_messages();
+            let ret = handler.text(&content);
+            handler.done();
+            return ret;
+        }
+        Config::load_mcp_servers(self.global_config(), input.role().use_tools().as_deref()).await;
+        let data = input.prepare_completion_data(self.model(), true)?;
+        self.send_chat_completions_streaming(Some(input), data, handler)
+            .await
+    }
+
+    /// The streaming version of `send_chat_completions`, ends the handler when it returns.
+    async fn send_chat_completions_streaming(
+        &self,
+        input: Option<&Input>,
+        data: ChatCompletionsData,
+        handler: &mut SseHandler,
     ) -> Result<()> {
         let abort_signal = handler.abort();
-        let input = input.clone();
-        tokio::select! {
+        let mut input_tokens = None;
+        let ret = tokio::select! {
             ret = async {
-                if self.global_config().read().dry_run {
-                    let content = input.echo_messages();
-                    handler.text(&content)?;
+                let client = self.build_client()?;
+                let cache = ResponseCache::new(&self.global_config().read())
+                    .map(|v| (v, ResponseCache::chat_key(self.model(), &data)));
+                if let Some(output) = cache.as_ref().and_then(|(cache, key)| cache.get_chat(key)) {
+                    self.log_cached_chat(input, &data, &output);
+                    handler.thinking_blocks(output.thinking)?;
+                    handler.text(&output.text)?;
+                    for call in output.tool_calls {
+                        handler.tool_call(call)?;
+                    }
                     return Ok(());
                 }
-                let client = self.build_client()?;
-                let data = input.prepare_completion_data(self.model(), true)?;
-                self.chat_completions_streaming_inner(&client, handler, data).await
+                let tokens = self.model().total_tokens(&data.messages);
+                self.guard_budget(input, tokens)?;
+                input_tokens = Some(tokens);
+                let log_data = self.chat_log_data(&data);
+                let schema = data.response_format.clone();
+                let started = Instant::now();
+                self.chat_completions_streaming_inner(&client, handler, data)
+                    .await
+                    .with_context(|| "Failed to call chat-completions api")?;
+                let latency = started.elapsed();
+                record_model_latency(self.model(), latency);
+                let output = handler.to_output();
+                if let Some(data) = log_data {
+                    let usage = self.output_usage(&output, tokens);
+                    self.global_config().read().log_chat(
+                        input,
+                        self.model(),
+                        &data,
+                        &output,
+                        usage,
+                        latency,
+                    );
+                }
+                // The reply is already on screen, so it can only be checked, not repaired
+                if let Some(schema) = schema {
+                    if output.tool_calls.is_empty() {
+                        if let Err(err) = parse_response_format(&output.text, &schema) {
+                            bail!("The reply does not match the response format: {err:#}");
+                        }
+                    }
+                }
+                if let Some((cache, key)) = &cache {
+                    cache.set_chat(key, &output);
+                }
+                Ok(())
             } => {
                 handler.done();
-                ret.with_context(|| "Failed to call chat-completions api")
+                ret
             }
             _ = wait_abort_signal(&abort_signal) => {
                 handler.done();
                 Ok(())
             },
+        };
+        if let Some(input_tokens) = input_tokens {
+            self.record_usage(input, &handler.to_output(), input_tokens);
         }
+        ret
     }
 
-    async fn embeddings(&self, data: &EmbeddingsData) -> Result<Vec<Vec<f32>>> {
+    fn guard_budget(&self, input: Option<&Input>, input_tokens: usize) -> Result<()> {
+        if input.is_some_and(|v| v.budget_confirmed()) {
+            return Ok(());
+        }
+        let config = self.global_config().read();
+        let session_usage = input
+            .and_then(|v| v.session(&config.session))
+            .map(|v| *v.usage());
+        if let Some(reason) =
+            config.check_budget(self.model(), session_usage.as_ref(), input_tokens)
+        {
+            bail!("{reason}");
+        }
+        Ok(())
+    }
+
+    /// Sends the messages as they are, used to replay a logged request against another model.
+    async fn replay_chat_completions(
+        &self,
+        data: ChatCompletionsData,
+    ) -> Result<(ChatCompletionsOutput, Usage, Duration)> {
         let client = self.build_client()?;
-        self.embeddings_inner(&client, data)
+        let input_tokens = self.model().total_tokens(&data.messages);
+        self.guard_budget(None, input_tokens)?;
+        let log_data = self.chat_log_data(&data);
+        let started = Instant::now();
+        let output = self
+            .chat_completions_inner(&client, data)
             .await
-            .context("Failed to call embeddings api")
+            .with_context(|| "Failed to call chat-completions api")?;
+        let latency = started.elapsed();
+        record_model_latency(self.model(), latency);
+        let usage = self.record_usage(None, &output, input_tokens);
+        if let Some(data) = log_data {
+            self.global_config().read().log_chat(
+                None,
+                self.model(),
+                &data,
+                &output,
+                usage,
+                latency,
+            );
+        }
+        Ok((output, usage, latency))
+    }
+
+    /// Sends one request and records its latency, usage and chat log entry.
+    async fn send_logged(
+        &self,
+        client: &ReqwestClient,
+        input: Option<&Input>,
+        data: ChatCompletionsData,
+        input_tokens: usize,
+    ) -> Result<ChatCompletionsOutput> {
+        let log_data = self.chat_log_data(&data);
+        let started = Instant::now();
+        let output = self
+            .chat_completions_inner(client, data)
+            .await
+            .with_context(|| "Failed to call chat-completions api")?;
+        let latency = started.elapsed();
+        record_model_latency(self.model(), latency);
+        let usage = self.record_usage(input, &output, input_tokens);
+        if let Some(data) = log_data {
+            self.global_config().read().log_chat(
+                input,
+                self.model(),
+                &data,
+                &output,
+                usage,
+                latency,
+            );
+        }
+        Ok(output)
+    }
+
+    fn chat_log_data(&self, data: &ChatCompletionsData) -> Option<ChatCompletionsData> {
+        if self.global_config().read().chat_log {
+            Some(data.clone())
+        } else {
+            None
+        }
+    }
+
+    fn log_cached_chat(
+        &self,
+        input: Option<&Input>,
+        data: &ChatCompletionsData,
+        output: &ChatCompletionsOutput,
+    ) {
+        let config = self.global_config().read();
+        if config.chat_log {
+            config.log_cached_chat(input, self.model(), data, output);
+        }
+    }
+
+    /// Falls back to estimated token counts when the provider reports no usage.
+    fn output_usage(&self, output: &ChatCompletionsOutput, input_tokens: usize) -> Usage {
+        let input_tokens = output.input_tokens.unwrap_or(input_tokens as u64);
+        let output_tokens = output
+            .output_tokens
+            .unwrap_or_else(|| estimate_token_length(&output.text) as u64);
+        Usage::new(self.model(), input_tokens, output_tokens)
+    }
+
+    fn record_usage(
+        &self,
+        input: Option<&Input>,
+        output: &ChatCompletionsOutput,
+        input_tokens: usize,
+    ) -> Usage {
+        let usage = self.output_usage(output, input_tokens);
+        self.global_config()
+            .write()
+            .record_usage(input, self.model(), usage);
+        usage
+    }
+
+    async fn embeddings(&self, data: &EmbeddingsData) -> Result<Vec<Vec<f32>>> {
+        let client = self.build_client()?;
+        let Some(cache) = ResponseCache::new(&self.global_config().read()) else {
+            return self
+                .embeddings_inner(&client, data)
+                .await
+                .context("Failed to call embeddings api");
+        };
+        // Only the texts without a cached embedding are sent
+        let keys: Vec<String> = data
+            .texts
+            .iter()
+            .map(|text| ResponseCache::embedding_key(self.model(), text, data.query))
+            .collect();
+        let mut embeddings: Vec<Option<Vec<f32>>> =
+            keys.iter().map(|key| cache.get_embedding(key)).collect();
+        let (missing_indexes, missing_texts): (Vec<usize>, Vec<String>) = embeddings
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.is_none())
+            .map(|(i, _)| (i, data.texts[i].clone()))
+            .unzip();
+        if !missing_texts.is_empty() {
+            let missing_data = EmbeddingsData::new(missing_texts, data.query);
+            let output = self
+                .embeddings_inner(&client, &missing_data)
+                .await
+                .context("Failed to call embeddings api")?;
+            if output.len() != missing_indexes.len() {
+                bail!(
+                    "Invalid embeddings output, expected {} embeddings",
+                    missing_indexes.len()
+                );
+            }
+            for (i, embedding) in missing_indexes.into_iter().zip(output) {
+                cache.set_embedding(&keys[i], &embedding);
+                embeddings[i] = Some(embedding);
+            }
+        }
+        Ok(embeddings.into_iter().flatten().collect())
     }
 
     async fn rerank(&self, data: &RerankData) -> Result<RerankOutput> {
@@ -277,18 +566,114 @@ impl RequestData {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+fn parse_response_format(text: &str, schema: &Value) -> Result<String> {
+    let text = strip_think_tag(text);
+    let text = extract_code_block(&text).trim();
+    let value: Value = serde_json::from_str(text).context("The reply is not valid JSON")?;
+    validate_json_schema(schema, &value)?;
+    Ok(text.to_string())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionsData {
     pub messages: Vec<Message>,
     pub temperature: Option<f64>,
     pub top_p: Option<f64>,
     pub functions: Option<Vec<FunctionDeclaration>>,
     pub stream: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+}
+
+/// How hard a model should think before answering, either a level or an explicit token budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+    Budget(u64),
+}
+
+impl ReasoningEffort {
+    pub fn budget_tokens(&self) -> u64 {
+        match self {
+            ReasoningEffort::Low => 1024,
+            ReasoningEffort::Medium => 4096,
+            ReasoningEffort::High => 16384,
+            ReasoningEffort::Budget(v) => *v,
+        }
+    }
+
+    pub fn level(&self) -> &'static str {
+        match self {
+            ReasoningEffort::Low => "low",
+            ReasoningEffort::Medium => "medium",
+            ReasoningEffort::High => "high",
+            ReasoningEffort::Budget(v) if *v <= 2048 => "low",
+            ReasoningEffort::Budget(v) if *v <= 8192 => "medium",
+            ReasoningEffort::Budget(_) => "high",
+        }
+    }
+}
+
+impl std::str::FromStr for ReasoningEffort {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "minimal" | "low" => Ok(ReasoningEffort::Low),
+            "medium" => Ok(ReasoningEffort::Medium),
+            "high" => Ok(ReasoningEffort::High),
+            _ => match s.parse::<u64>() {
+                Ok(v) if v > 0 => Ok(ReasoningEffort::Budget(v)),
+                _ => bail!(
+                    "Invalid reasoning effort '{s}', expect low, medium, high or a token budget"
+                ),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for ReasoningEffort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReasoningEffort::Budget(v) => write!(f, "{v}"),
+            _ => write!(f, "{}", self.level()),
+        }
+    }
+}
+
+impl Serialize for ReasoningEffort {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ReasoningEffort::Budget(v) => serializer.serialize_u64(*v),
+            _ => serializer.serialize_str(self.level()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ReasoningEffort {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u64),
+            Text(String),
+        }
+        let value = match Raw::deserialize(deserializer)? {
+            Raw::Number(v) => v.to_string(),
+            Raw::Text(v) => v,
+        };
+        value.parse().map_err(serde::de::Error::custom)
+    }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct ChatCompletionsOutput {
     pub text: String,
+    pub thinking: Vec<ThinkingBlock>,
     pub tool_calls: Vec<ToolCall>,
     pub id: Option<String>,
     pub input_tokens: Option<u64>,
@@ -403,27 +788,44 @@ pub async fn create_openai_compatible_client_config(
     Ok(Some((model, clients)))
 }
 
+/// Sets the model that answered on `input`, so the follow-ups and the records use it.
 pub async fn call_chat_completions(
-    input: &Input,
+    input: &mut Input,
     print: bool,
     extract_code: bool,
     client: &dyn Client,
     abort_signal: AbortSignal,
-) -> Result<(String, Vec<ToolResult>)> {
-    let ret = abortable_run_with_spinner(
-        client.chat_completions(input.clone()),
-        "Generating",
-        abort_signal,
-    )
-    .await;
+) -> Result<(String, Vec<ThinkingBlock>, Vec<ToolResult>)> {
+    let confirmed_input = &confirm_budget(input, client)?;
+    let mut retry = RetryState::new(client, input.role().fallback_models());
+    let ret = loop {
+        let ret = abortable_run_with_spinner(
+            retry.client().chat_completions(confirmed_input.clone()),
+            "Generating",
+            abort_signal.clone(),
+        )
+        .await;
+        match ret {
+            Err(err) if retry.next(&err, &abort_signal).await? => continue,
+            ret => break ret,
+        }
+    };
+    let client = retry.client();
+    if ret.is_ok() {
+        input.set_model(client.model());
+    }
 
     match ret {
         Ok(ret) => {
             let ChatCompletionsOutput {
                 mut text,
+                thinking,
                 tool_calls,
                 ..
             } = ret;
+            if print && !extract_code {
+                client.global_config().read().print_thinking(&thinking);
+            }
             if !text.is_empty() {
                 if extract_code {
                     text = extract_code_block(&strip_think_tag(&text)).to_string();
@@ -432,46 +834,199 @@ pub async fn call_chat_completions(
                     client.global_config().read().print_markdown(&text)?;
                 }
             }
-            Ok((text, eval_tool_calls(client.global_config(), tool_calls)?))
+            Ok((
+                text,
+                thinking,
+                eval_tool_calls(client.global_config(), tool_calls, abort_signal).await?,
+            ))
         }
         Err(err) => Err(err),
     }
 }
 
+/// Sets the model that answered on `input`, like `call_chat_completions`.
 pub async fn call_chat_completions_streaming(
-    input: &Input,
+    input: &mut Input,
     client: &dyn Client,
     abort_signal: AbortSignal,
-) -> Result<(String, Vec<ToolResult>)> {
-    let (tx, rx) = unbounded_channel();
-    let mut handler = SseHandler::new(tx, abort_signal.clone());
+) -> Result<(String, Vec<ThinkingBlock>, Vec<ToolResult>)> {
+    let confirmed_input = &confirm_budget(input, client)?;
+    let mut retry = RetryState::new(client, input.role().fallback_models());
+    loop {
+        let client = retry.client();
+        let (tx, rx) = unbounded_channel();
+        let mut handler = SseHandler::new(tx, abort_signal.clone());
+
+        let (send_ret, render_ret) = tokio::join!(
+            client.chat_completions_streaming(confirmed_input, &mut handler),
+            render_stream(rx, client.global_config(), abort_signal.clone()),
+        );
+
+        if handler.abort().aborted() {
+            bail!("Aborted.");
+        }
 
-    let (send_ret, render_ret) = tokio::join!(
-        client.chat_completions_streaming(input, &mut handler),
-        render_stream(rx, client.global_config(), abort_signal.clone()),
-    );
+        render_ret?;
 
-    if handler.abort().aborted() {
-        bail!("Aborted.");
+        let (text, thinking, tool_calls) = handler.take();
+        match send_ret {
+            Ok(_) => {
+                if !text.is_empty() && !text.ends_with('\n') {
+                    println!();
+                }
+                input.set_model(client.model());
+                return Ok((
+                    text,
+                    thinking,
+                    eval_tool_calls(client.global_config(), tool_calls, abort_signal).await?,
+                ));
+            }
+            Err(err) => {
+                // Output already printed can't be taken back, so only a silent failure is retried
+                if !text.is_empty() || !thinking.is_empty() {
+                    println!();
+                    return Err(err);
+                }
+                if !retry.next(&err, &abort_signal).await? {
+                    return Err(err);
+                }
+            }
+        }
     }
+}
 
-    render_ret?;
+/// Retries a failed request with backoff, then moves on to the fallback models.
+pub struct RetryState<'a> {
+    client: &'a dyn Client,
+    fallback_client: Option<Box<dyn Client>>,
+    fallback_models: Vec<String>,
+    config: RetryConfig,
+    retries: usize,
+}
 
-    let (text, tool_calls) = handler.take();
-    match send_ret {
-        Ok(_) => {
-            if !text.is_empty() && !text.ends_with('\n') {
-                println!();
-            }
-            Ok((text, eval_tool_calls(client.global_config(), tool_calls)?))
+impl<'a> RetryState<'a> {
+    /// Uses the configured `fallback_models` when the role has none.
+    pub fn new(client: &'a dyn Client, role_fallback_models: &[String]) -> Self {
+        let config = client.global_config().read();
+        let fallback_models = match role_fallback_models {
+            [] => &config.fallback_models,
+            v => v,
+        };
+        let model_id = client.model().id();
+        Self {
+            client,
+            fallback_client: None,
+            fallback_models: fallback_models
+                .iter()
+                .rev()
+                .filter(|v| **v != model_id)
+                .cloned()
+                .collect(),
+            config: config.retry,
+            retries: 0,
         }
-        Err(err) => {
-            if !text.is_empty() {
-                println!();
+    }
+
+    pub fn client(&self) -> &dyn Client {
+        self.fallback_client.as_deref().unwrap_or(self.client)
+    }
+
+    /// Waits for the next attempt, returns false when the error should be reported.
+    pub async fn next(&mut self, err: &anyhow::Error, abort_signal: &AbortSignal) -> Result<bool> {
+        let Some(retry_after) = retryable_error(err) else {
+            return Ok(false);
+        };
+        if abort_signal.aborted() {
+            return Ok(false);
+        }
+        let model_id = self.client().model().id();
+        let reason = err.root_cause().to_string();
+        if self.retries < self.config.max_retries {
+            let delay = retry_after.unwrap_or_else(|| {
+                let factor = 2u64.saturating_pow(self.retries as u32);
+                Duration::from_secs(self.config.initial_delay.saturating_mul(factor))
+            });
+            if delay <= Duration::from_secs(self.config.max_delay) {
+                self.retries += 1;
+                report_retry(&format!(
+                    "{model_id} failed: {reason}. Retrying in {}s ({}/{})",
+                    delay.as_secs(),
+                    self.retries,
+                    self.config.max_retries
+                ));
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = wait_abort_signal(abort_signal) => return Ok(false),
+                }
+                return Ok(true);
             }
-            Err(err)
         }
+        let config = self.client.global_config();
+        while let Some(fallback) = self.fallback_models.pop() {
+            let model = match Model::retrieve_model(&config.read(), &fallback, ModelType::Chat) {
+                Ok(model) => model,
+                Err(err) => {
+                    warn!("Skip the fallback model '{fallback}', {err}");
+                    continue;
+                }
+            };
+            report_retry(&format!(
+                "{model_id} failed: {reason}. Falling back to {fallback}"
+            ));
+            self.fallback_client = Some(init_client(config, Some(model))?);
+            self.retries = 0;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+/// Returns the `Retry-After` delay, if any, of an error worth retrying.
+fn retryable_error(err: &anyhow::Error) -> Option<Option<Duration>> {
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<ApiError>() {
+            return err.is_retryable().then_some(err.retry_after);
+        }
+        if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+            return (err.is_connect() || err.is_timeout() || err.is_request()).then_some(None);
+        }
+        if let Some(reqwest_eventsource::Error::Transport(_)) =
+            cause.downcast_ref::<reqwest_eventsource::Error>()
+        {
+            return Some(None);
+        }
+    }
+    None
+}
+
+fn report_retry(message: &str) {
+    warn!("{message}");
+    eprintln!("{}", warning_text(&format!("⚠️ {message}")));
+}
+
+/// In the REPL, a request over budget can still be sent after confirmation.
+fn confirm_budget(input: &Input, client: &dyn Client) -> Result<Input> {
+    let mut input = input.clone();
+    let config = client.global_config();
+    if !config.read().working_mode.is_repl() || !*IS_STDOUT_TERMINAL {
+        return Ok(input);
+    }
+    let input_tokens = client.model().total_tokens(&input.build_messages()?);
+    let reason = {
+        let config = config.read();
+        let session_usage = input.session(&config.session).map(|v| *v.usage());
+        config.check_budget(client.model(), session_usage.as_ref(), input_tokens)
+    };
+    if let Some(reason) = reason {
+        let ans = Confirm::new(&format!("{reason}. Send anyway?"))
+            .with_default(false)
+            .prompt()?;
+        if !ans {
+            bail!("{reason}");
+        }
+        input.set_budget_confirmed();
     }
+    Ok(input)
 }
 
 pub fn noop_prepare_embeddings<T>(_client: &T, _data: &EmbeddingsData) -> Result<RequestData> {
@@ -490,46 +1045,107 @@ pub async fn noop_rerank(_builder: RequestBuilder, _model: &Model) -> Result<Rer
     bail!("The client doesn't support rerank api")
 }
 
-pub fn catch_error(data: &Value, status: u16) -> Result<()> {
-    if (200..300).contains(&status) {
-        return Ok(());
+/// An error response of a provider API.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: u16,
+    pub message: String,
+    pub retry_after: Option<Duration>,
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl ApiError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.status, 408 | 429) || self.status >= 500
+    }
+}
+
+/// Sends the request, a response with an error status becomes an [`ApiError`].
+pub async fn send_request(builder: RequestBuilder) -> Result<reqwest::Response> {
+    let res = builder.send().await?;
+    if res.status().is_success() {
+        return Ok(res);
+    }
+    Err(response_error(res).await)
+}
+
+pub async fn response_error(res: reqwest::Response) -> anyhow::Error {
+    let status = res.status().as_u16();
+    let retry_after = res
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_retry_after);
+    let text = match res.text().await {
+        Ok(text) => text,
+        Err(err) => return err.into(),
+    };
+    debug!("Invalid response, status: {status}, data: {text}");
+    let message = match text.parse::<Value>() {
+        Ok(data) => extract_error_message(&data, status),
+        Err(_) => format!("Invalid response data: {text} (status: {status})"),
+    };
+    ApiError {
+        status,
+        message,
+        retry_after,
+    }
+    .into()
+}
+
+/// Parses `Retry-After` given in seconds or as an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
     }
-    debug!("Invalid response, status: {status}, data: {data}");
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let secs = (date.timestamp() - chrono::Utc::now().timestamp()).max(0);
+    Some(Duration::from_secs(secs as u64))
+}
+
+fn extract_error_message(data: &Value, status: u16) -> String {
     if let Some(error) = data["error"].as_object() {
         if let (Some(typ), Some(message)) = (
             json_str_from_map(error, "type"),
             json_str_from_map(error, "message"),
         ) {
-            bail!("{message} (type: {typ})");
+            return format!("{message} (type: {typ})");
         } else if let (Some(typ), Some(message)) = (
             json_str_from_map(error, "code"),
             json_str_from_map(error, "message"),
         ) {
-            bail!("{message} (code: {typ})");
+            return format!("{message} (code: {typ})");
         }
     } else if let Some(error) = data["errors"][0].as_object() {
         if let (Some(code), Some(message)) = (
             error.get("code").and_then(|v| v.as_u64()),
             json_str_from_map(error, "message"),
         ) {
-            bail!("{message} (status: {code})")
+            return format!("{message} (status: {code})");
         }
     } else if let Some(error) = data[0]["error"].as_object() {
         if let (Some(status), Some(message)) = (
             json_str_from_map(error, "status"),
             json_str_from_map(error, "message"),
         ) {
-            bail!("{message} (status: {status})")
+            return format!("{message} (status: {status})");
         }
     } else if let (Some(detail), Some(status)) = (data["detail"].as_str(), data["status"].as_i64())
     {
-        bail!("{detail} (status: {status})");
+        return format!("{detail} (status: {status})");
     } else if let Some(error) = data["error"].as_str() {
-        bail!("{error}");
+        return error.to_string();
     } else if let Some(message) = data["message"].as_str() {
-        bail!("{message}");
+        return message.to_string();
     }
-    bail!("Invalid response data: {data} (status: {status})");
+    format!("Invalid response data: {data} (status: {status})")
 }
 
 pub fn json_str_from_map<'a>(
@@ -674,3 +1290,44 @@ fn prompt_input_string(
     let text = text.prompt()?;
     Ok(text)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retryable_error() {
+        let api_error = |status, retry_after| -> anyhow::Error {
+            ApiError {
+                status,
+                message: "failed".into(),
+                retry_after,
+            }
+            .into()
+        };
+        assert_eq!(retryable_error(&api_error(500, None)), Some(None));
+        assert_eq!(
+            retryable_error(&api_error(429, parse_retry_after("7"))),
+            Some(Some(Duration::from_secs(7)))
+        );
+        assert_eq!(retryable_error(&api_error(400, None)), None);
+        assert_eq!(
+            retryable_error(&api_error(503, None).context("Failed to call chat-completions api")),
+            Some(None)
+        );
+        assert_eq!(retryable_error(&anyhow::anyhow!("Aborted.")), None);
+    }
+
+    #[test]
+    fn test_reasoning_effort() {
+        let value: ReasoningEffort = "high".parse().unwrap();
+        assert_eq!(value.budget_tokens(), 16384);
+        let value: ReasoningEffort = serde_json::from_value(json!(3000)).unwrap();
+        assert_eq!(value, ReasoningEffort::Budget(3000));
+        assert_eq!(value.level(), "medium");
+        assert_eq!(json!(value), json!(3000));
+        assert_eq!(json!(ReasoningEffort::Low), json!("low"));
+        assert!("0".parse::<ReasoningEffort>().is_err());
+        assert!("max".parse::<ReasoningEffort>().is_err());
+    }
+}
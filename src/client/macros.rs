@@ -74,6 +74,7 @@ macro_rules! register_client {
 
         pub fn init_client(config: &$crate::config::GlobalConfig, model: Option<$crate::client::Model>) -> anyhow::Result<Box<dyn Client>> {
             let model = model.unwrap_or_else(|| config.read().model.clone());
+            let model = model.reselect(&config.read()).unwrap_or(model);
             None
             $(.or_else(|| $client::init(config, &model)))+
             .ok_or_else(|| {
@@ -115,22 +116,30 @@ macro_rules! register_client {
             names.iter().collect()
         }
 
-        static ALL_MODELS: std::sync::OnceLock<Vec<$crate::client::Model>> = std::sync::OnceLock::new();
+        static ALL_MODELS: std::sync::RwLock<Option<&'static [$crate::client::Model]>> = std::sync::RwLock::new(None);
 
         pub fn list_all_models(config: &$crate::config::Config) -> Vec<&'static $crate::client::Model> {
-            let models = ALL_MODELS.get_or_init(|| {
-                config
-                    .clients
-                    .iter()
-                    .flat_map(|v| match v {
-                        $(ClientConfig::$config(c) => $client::list_models(c),)+
-                        ClientConfig::Unknown => vec![],
-                    })
-                    .collect()
-            });
+            if let Some(models) = *ALL_MODELS.read().unwrap() {
+                return models.iter().collect();
+            }
+            let models: Vec<_> = config
+                .clients
+                .iter()
+                .flat_map(|v| match v {
+                    $(ClientConfig::$config(c) => $client::list_models(c),)+
+                    ClientConfig::Unknown => vec![],
+                })
+                .collect();
+            let models: &'static [$crate::client::Model] = Box::leak(models.into_boxed_slice());
+            *ALL_MODELS.write().unwrap() = Some(models);
             models.iter().collect()
         }
 
+        /// Lists the models again on the next call, after the clients got new models.
+        pub fn reset_all_models() {
+            *ALL_MODELS.write().unwrap() = None;
+        }
+
         pub fn list_models(config: &$crate::config::Config, model_type: $crate::client::ModelType) -> Vec<&'static $crate::client::Model> {
             list_all_models(config).into_iter().filter(|v| v.model_type() == model_type).collect()
         }
@@ -181,22 +190,32 @@ macro_rules! impl_client_trait {
             async fn chat_completions_inner(
                 &self,
                 client: &reqwest::Client,
-                data: $crate::client::ChatCompletionsData,
+                mut data: $crate::client::ChatCompletionsData,
             ) -> anyhow::Result<$crate::client::ChatCompletionsOutput> {
+                if !self.model().supports_reasoning() {
+                    data.reasoning_effort = None;
+                }
                 let request_data = $prepare_chat_completions(self, data)?;
                 let builder = self.request_builder(client, request_data);
-                $chat_completions(builder, self.model()).await
+                let mut output = $chat_completions(builder, self.model()).await?;
+                $crate::client::ThinkingBlock::set_source(&mut output.thinking, self.model());
+                Ok(output)
             }
 
             async fn chat_completions_streaming_inner(
                 &self,
                 client: &reqwest::Client,
                 handler: &mut $crate::client::SseHandler,
-                data: $crate::client::ChatCompletionsData,
+                mut data: $crate::client::ChatCompletionsData,
             ) -> Result<()> {
+                if !self.model().supports_reasoning() {
+                    data.reasoning_effort = None;
+                }
                 let request_data = $prepare_chat_completions(self, data)?;
                 let builder = self.request_builder(client, request_data);
-                $chat_completions_streaming(builder, handler, self.model()).await
+                $chat_completions_streaming(builder, handler, self.model()).await?;
+                handler.set_thinking_source(self.model());
+                Ok(())
             }
 
             async fn embeddings_inner(
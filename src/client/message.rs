@@ -3,11 +3,14 @@ use super::Model;
 use crate::{function::ToolResult, multiline_text, utils::dimmed_text};
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Message {
     pub role: MessageRole,
     pub content: MessageContent,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
 }
 
 impl Default for Message {
@@ -15,13 +18,18 @@ impl Default for Message {
         Self {
             role: MessageRole::User,
             content: MessageContent::Text(String::new()),
+            pinned: false,
         }
     }
 }
 
 impl Message {
     pub fn new(role: MessageRole, content: MessageContent) -> Self {
-        Self { role, content }
+        Self {
+            role,
+            content,
+            pinned: false,
+        }
     }
 
     pub fn merge_system(&mut self, system: MessageContent) {
@@ -183,21 +191,65 @@ pub struct MessageContentToolCalls {
     pub tool_results: Vec<ToolResult>,
     pub text: String,
     pub sequence: bool,
+    /// Claude rejects a tool-use turn whose signed thinking blocks are missing, so they are kept.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub thinking: Vec<ThinkingBlock>,
 }
 
 impl MessageContentToolCalls {
-    pub fn new(tool_results: Vec<ToolResult>, text: String) -> Self {
+    pub fn new(tool_results: Vec<ToolResult>, text: String, thinking: Vec<ThinkingBlock>) -> Self {
         Self {
             tool_results,
             text,
             sequence: false,
+            thinking,
         }
     }
 
-    pub fn merge(&mut self, tool_results: Vec<ToolResult>, _text: String) {
+    pub fn merge(
+        &mut self,
+        tool_results: Vec<ToolResult>,
+        _text: String,
+        thinking: Vec<ThinkingBlock>,
+    ) {
         self.tool_results.extend(tool_results);
         self.text.clear();
         self.sequence = true;
+        self.thinking.extend(thinking);
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ThinkingBlock {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// The encrypted payload of a block the provider redacted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redacted: Option<String>,
+    /// The client and the model that wrote the block, signatures only verify with them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Gemini signs a function call rather than the thinking, this is the id of that call
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl ThinkingBlock {
+    pub fn is_from(&self, model: &Model) -> bool {
+        self.provider.as_deref() == Some(model.client_name())
+            && self.model.as_deref() == Some(model.real_name())
+    }
+
+    /// Marks the blocks that have no source yet as written by `model`.
+    pub fn set_source(blocks: &mut [ThinkingBlock], model: &Model) {
+        for block in blocks.iter_mut().filter(|v| v.provider.is_none()) {
+            block.provider = Some(model.client_name().to_string());
+            block.model = Some(model.real_name().to_string());
+        }
     }
 }
 
@@ -211,10 +263,10 @@ pub fn patch_messages(messages: &mut Vec<Message>, model: &Model) {
         } else {
             messages.insert(
                 0,
-                Message {
-                    role: MessageRole::System,
-                    content: MessageContent::Text(prefix.to_string()),
-                },
+                Message::new(
+                    MessageRole::System,
+                    MessageContent::Text(prefix.to_string()),
+                ),
             );
         }
     }
@@ -226,6 +278,25 @@ pub fn patch_messages(messages: &mut Vec<Message>, model: &Model) {
     }
 }
 
+pub fn patch_response_format(messages: &mut Vec<Message>, schema: &Value) {
+    let instructions =
+        format!("Respond only with a JSON value that matches the following JSON schema:\n{schema}");
+    match messages.first_mut() {
+        Some(Message {
+            role: MessageRole::System,
+            content: MessageContent::Text(text),
+            ..
+        }) => {
+            text.push_str("\n\n");
+            text.push_str(&instructions);
+        }
+        _ => messages.insert(
+            0,
+            Message::new(MessageRole::System, MessageContent::Text(instructions)),
+        ),
+    }
+}
+
 pub fn extract_system_message(messages: &mut Vec<Message>) -> Option<String> {
     if messages[0].role.is_system() {
         let system_message = messages.remove(0);
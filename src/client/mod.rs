@@ -1,4 +1,5 @@
 mod access_token;
+mod cache;
 mod common;
 mod message;
 #[macro_use]
@@ -7,6 +8,7 @@ mod model;
 mod stream;
 
 pub use crate::function::ToolCall;
+pub use cache::*;
 pub use common::*;
 pub use message::*;
 pub use model::*;
@@ -31,8 +33,11 @@ register_client!(
     ),
     (vertexai, "vertexai", VertexAIConfig, VertexAIClient),
     (bedrock, "bedrock", BedrockConfig, BedrockClient),
+    (ollama, "ollama", OllamaConfig, OllamaClient),
 );
 
+pub use ollama::{load_ollama_models, pull_ollama_model};
+
 pub const OPENAI_COMPATIBLE_PROVIDERS: [(&str, &str); 19] = [
     ("ai21", "https://api.ai21.com/studio/v1"),
     (
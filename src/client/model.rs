@@ -8,17 +8,29 @@ use crate::config::Config;
 use crate::utils::{estimate_token_length, strip_think_tag};
 
 use anyhow::{bail, Result};
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::fmt::Display;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::{create_dir_all, read_to_string, write},
+    path::Path,
+    sync::LazyLock,
+    time::Duration,
+};
 
 const PER_MESSAGES_TOKENS: usize = 5;
 const BASIS_TOKENS: usize = 2;
 
+static MODEL_GROUP_STATES: LazyLock<Mutex<ModelGroupStates>> =
+    LazyLock::new(|| Mutex::new(ModelGroupStates::load(&Config::model_groups_file())));
+
 #[derive(Debug, Clone)]
 pub struct Model {
     client_name: String,
     data: ModelData,
+    group: Option<String>,
 }
 
 impl Default for Model {
@@ -32,6 +44,7 @@ impl Model {
         Self {
             client_name: client_name.into(),
             data: ModelData::new(name),
+            group: None,
         }
     }
 
@@ -41,11 +54,23 @@ impl Model {
             .map(|v| Model {
                 client_name: client_name.to_string(),
                 data: v.clone(),
+                group: None,
             })
             .collect()
     }
 
     pub fn retrieve_model(config: &Config, model_id: &str, model_type: ModelType) -> Result<Self> {
+        if let Some(group) = config.model_groups.get(model_id) {
+            return group.select(config, model_id, model_type, false);
+        }
+        Self::retrieve_model_inner(config, model_id, model_type)
+    }
+
+    fn retrieve_model_inner(
+        config: &Config,
+        model_id: &str,
+        model_type: ModelType,
+    ) -> Result<Self> {
         let models = list_all_models(config);
         let (client_name, model_name) = match model_id.split_once(':') {
             Some((client_name, model_name)) => {
@@ -100,6 +125,23 @@ impl Model {
         &self.client_name
     }
 
+    /// The id to store in roles, sessions and agents, which keeps a group balanced.
+    pub fn alias_or_id(&self) -> String {
+        self.group.clone().unwrap_or_else(|| self.id())
+    }
+
+    /// Picks the member of the model group that serves the next request,
+    /// keeping the max tokens set by the user.
+    pub fn reselect(&self, config: &Config) -> Option<Self> {
+        let name = self.group.as_deref()?;
+        let group = config.model_groups.get(name)?;
+        let mut model = group.select(config, name, self.model_type(), true).ok()?;
+        if self.data.require_max_tokens {
+            model.set_max_tokens(self.data.max_output_tokens, true);
+        }
+        Some(model)
+    }
+
     pub fn name(&self) -> &str {
         &self.data.name
     }
@@ -191,6 +233,14 @@ impl Model {
         self.data.no_stream
     }
 
+    pub fn supports_response_format(&self) -> bool {
+        self.data.supports_response_format
+    }
+
+    pub fn supports_reasoning(&self) -> bool {
+        self.data.supports_reasoning
+    }
+
     pub fn no_system_message(&self) -> bool {
         self.data.no_system_message
     }
@@ -319,6 +369,10 @@ pub struct ModelData {
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     pub supports_function_calling: bool,
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub supports_response_format: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub supports_reasoning: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     no_stream: bool,
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     no_system_message: bool,
@@ -397,6 +451,205 @@ impl ModelType {
     }
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelGroup {
+    #[serde(default)]
+    pub strategy: ModelGroupStrategy,
+    pub models: Vec<ModelGroupMember>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ModelGroupMember {
+    Id(String),
+    Weighted {
+        id: String,
+        #[serde(default = "default_weight")]
+        weight: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ModelGroupStrategy {
+    #[default]
+    RoundRobin,
+    Weighted,
+    LowestLatency,
+    Cheapest,
+}
+
+/// The round-robin positions and latencies, kept in `model_groups.json` so that they last across runs.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ModelGroupStates {
+    groups: HashMap<String, ModelGroupState>,
+    latencies: HashMap<String, Duration>,
+}
+
+impl ModelGroupStates {
+    fn load(path: &Path) -> Self {
+        read_to_string(path)
+            .ok()
+            .and_then(|v| serde_json::from_str(&v).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, path: &Path) {
+        if let Some(parent) = path.parent() {
+            let _ = create_dir_all(parent);
+        }
+        if let Ok(data) = serde_json::to_string(self) {
+            let _ = write(path, data);
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ModelGroupState {
+    next: usize,
+    current_weights: Vec<i64>,
+}
+
+impl ModelGroupMember {
+    pub fn id(&self) -> &str {
+        match self {
+            ModelGroupMember::Id(id) => id,
+            ModelGroupMember::Weighted { id, .. } => id,
+        }
+    }
+
+    fn weight(&self) -> u32 {
+        match self {
+            ModelGroupMember::Id(_) => 1,
+            ModelGroupMember::Weighted { weight, .. } => *weight,
+        }
+    }
+}
+
+impl ModelGroup {
+    pub fn description(&self) -> String {
+        let ids: Vec<&str> = self.models.iter().map(|v| v.id()).collect();
+        format!("{:?}: {}", self.strategy, ids.join(", "))
+    }
+
+    /// Only a pick made with `advance` moves the round-robin and weighted states forward.
+    fn select(
+        &self,
+        config: &Config,
+        name: &str,
+        model_type: ModelType,
+        advance: bool,
+    ) -> Result<Model> {
+        let mut candidates = vec![];
+        for member in &self.models {
+            if config.model_groups.contains_key(member.id()) {
+                bail!(
+                    "Model group '{name}' cannot contain the model group '{}'",
+                    member.id()
+                );
+            }
+            if let Ok(model) = Model::retrieve_model_inner(config, member.id(), model_type) {
+                candidates.push((model, member.weight()));
+            }
+        }
+        if candidates.is_empty() {
+            bail!("No available {model_type} model in the model group '{name}'");
+        }
+        let index = match self.strategy {
+            ModelGroupStrategy::RoundRobin => {
+                let mut states = MODEL_GROUP_STATES.lock();
+                let state = states.groups.entry(name.to_string()).or_default();
+                let index = state.next % candidates.len();
+                if advance {
+                    state.next = index + 1;
+                    states.save(&Config::model_groups_file());
+                }
+                index
+            }
+            ModelGroupStrategy::Weighted => {
+                let mut states = MODEL_GROUP_STATES.lock();
+                let state = states.groups.entry(name.to_string()).or_default();
+                let weights: Vec<i64> = candidates.iter().map(|(_, v)| *v as i64).collect();
+                if advance {
+                    let index = smooth_weighted_select(&mut state.current_weights, &weights);
+                    states.save(&Config::model_groups_file());
+                    index
+                } else {
+                    smooth_weighted_select(&mut state.current_weights.clone(), &weights)
+                }
+            }
+            ModelGroupStrategy::LowestLatency => {
+                // Models without measurements go first so that every model gets measured
+                let states = MODEL_GROUP_STATES.lock();
+                let latencies = &states.latencies;
+                let mut index = 0;
+                let mut lowest = None;
+                for (i, (model, _)) in candidates.iter().enumerate() {
+                    let latency = latencies.get(&model.id()).copied().unwrap_or_default();
+                    if lowest.is_none_or(|v| latency < v) {
+                        index = i;
+                        lowest = Some(latency);
+                    }
+                }
+                index
+            }
+            ModelGroupStrategy::Cheapest => {
+                let mut index = 0;
+                let mut lowest = None;
+                for (i, (model, _)) in candidates.iter().enumerate() {
+                    let Some(price) = model.data.input_price else {
+                        continue;
+                    };
+                    if lowest.is_none_or(|v| price < v) {
+                        index = i;
+                        lowest = Some(price);
+                    }
+                }
+                index
+            }
+        };
+        let mut model = candidates.swap_remove(index).0;
+        model.group = Some(name.to_string());
+        Ok(model)
+    }
+}
+
+/// Smooth weighted round-robin, spreads the picks of a heavy member instead of bunching them.
+fn smooth_weighted_select(current_weights: &mut Vec<i64>, weights: &[i64]) -> usize {
+    if current_weights.len() != weights.len() {
+        *current_weights = vec![0; weights.len()];
+    }
+    let total: i64 = weights.iter().sum();
+    let mut index = 0;
+    for (i, weight) in weights.iter().enumerate() {
+        current_weights[i] += weight;
+        if current_weights[i] > current_weights[index] {
+            index = i;
+        }
+    }
+    current_weights[index] -= total;
+    index
+}
+
+/// Keeps a moving average of the response time of each model.
+pub fn record_model_latency(model: &Model, latency: Duration) {
+    let mut states = MODEL_GROUP_STATES.lock();
+    let value = match states.latencies.get(&model.id()) {
+        Some(v) => (v.mul_f64(0.7)) + latency.mul_f64(0.3),
+        None => latency,
+    };
+    states.latencies.insert(model.id(), value);
+    if model.group.is_some() {
+        states.save(&Config::model_groups_file());
+    }
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
 fn stringify_option_value<T>(value: &Option<T>) -> String
 where
     T: std::fmt::Display,
@@ -406,3 +659,17 @@ where
         None => "-".to_string(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smooth_weighted_select() {
+        let mut current_weights = vec![];
+        let picks: Vec<usize> = (0..7)
+            .map(|_| smooth_weighted_select(&mut current_weights, &[5, 1, 1]))
+            .collect();
+        assert_eq!(picks, [0, 0, 1, 0, 2, 0, 0]);
+    }
+}
@@ -0,0 +1,733 @@
+use super::*;
+
+use crate::config::GlobalConfig;
+use crate::utils::{
+    abortable_run_with_spinner_rx, set_proxy, strip_think_tag, AbortSignal, Spinner,
+};
+
+use anyhow::{bail, Context, Result};
+use futures_util::future::join_all;
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+const API_BASE: &str = "http://localhost:11434";
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct OllamaConfig {
+    pub name: Option<String>,
+    pub api_base: Option<String>,
+    pub api_key: Option<String>,
+    /// How long the model stays loaded after a request, e.g. "10m", or -1 to keep it loaded
+    pub keep_alive: Option<Value>,
+    /// The context window size, Ollama's default is much smaller than most models support
+    pub num_ctx: Option<usize>,
+    #[serde(default)]
+    pub models: Vec<ModelData>,
+    pub patch: Option<RequestPatch>,
+    pub extra: Option<ExtraConfig>,
+}
+
+impl OllamaClient {
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptAction<'static>; 1] =
+        [("api_base", "API Base", Some("e.g. http://localhost:11434"))];
+
+    fn get_api_base(&self) -> String {
+        api_base(&self.config)
+    }
+}
+
+impl_client_trait!(
+    OllamaClient,
+    (
+        prepare_chat_completions,
+        chat_completions,
+        chat_completions_streaming
+    ),
+    (prepare_embeddings, embeddings),
+    (noop_prepare_rerank, noop_rerank),
+);
+
+fn prepare_chat_completions(
+    self_: &OllamaClient,
+    data: ChatCompletionsData,
+) -> Result<RequestData> {
+    let url = format!("{}/api/chat", self_.get_api_base());
+    let body = build_chat_completions_body(data, &self_.model, &self_.config)?;
+
+    let mut request_data = RequestData::new(url, body);
+
+    if let Ok(api_key) = self_.get_api_key() {
+        request_data.bearer_auth(api_key);
+    }
+
+    Ok(request_data)
+}
+
+fn prepare_embeddings(self_: &OllamaClient, data: &EmbeddingsData) -> Result<RequestData> {
+    let url = format!("{}/api/embed", self_.get_api_base());
+    let mut body = json!({
+        "model": self_.model.real_name(),
+        "input": data.texts,
+    });
+    if let Some(v) = &self_.config.keep_alive {
+        body["keep_alive"] = v.clone();
+    }
+    if let Some(v) = self_.config.num_ctx {
+        body["options"] = json!({ "num_ctx": v });
+    }
+
+    let mut request_data = RequestData::new(url, body);
+
+    if let Ok(api_key) = self_.get_api_key() {
+        request_data.bearer_auth(api_key);
+    }
+
+    Ok(request_data)
+}
+
+async fn chat_completions(
+    builder: RequestBuilder,
+    _model: &Model,
+) -> Result<ChatCompletionsOutput> {
+    let res = send_request(builder).await?;
+    let data: Value = res.json().await?;
+    debug!("non-stream-data: {data}");
+    extract_chat_completions(&data)
+}
+
+async fn chat_completions_streaming(
+    builder: RequestBuilder,
+    handler: &mut SseHandler,
+    _model: &Model,
+) -> Result<()> {
+    let res = send_request(builder).await?;
+    let handle = |value: &str| -> Result<()> {
+        let data: Value = serde_json::from_str(value)?;
+        debug!("stream-data: {data}");
+        if let Some(error) = data["error"].as_str() {
+            bail!("{error}");
+        }
+        if let Some(text) = data["message"]["thinking"].as_str() {
+            handler.thinking(text)?;
+        }
+        if let Some(text) = data["message"]["content"].as_str() {
+            handler.text(text)?;
+        }
+        for call in extract_tool_calls(&data) {
+            handler.tool_call(call)?;
+        }
+        if data["done"].as_bool().unwrap_or_default() {
+            handler.usage(
+                data["prompt_eval_count"].as_u64(),
+                data["eval_count"].as_u64(),
+            );
+        }
+        Ok(())
+    };
+    json_stream(res.bytes_stream(), handle).await
+}
+
+async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
+    let res = send_request(builder).await?;
+    let data: Value = res.json().await?;
+    let res_body: EmbeddingsResBody =
+        serde_json::from_value(data).context("Invalid embeddings data")?;
+    Ok(res_body.embeddings)
+}
+
+#[derive(Deserialize)]
+struct EmbeddingsResBody {
+    embeddings: Vec<Vec<f32>>,
+}
+
+fn build_chat_completions_body(
+    data: ChatCompletionsData,
+    model: &Model,
+    config: &OllamaConfig,
+) -> Result<Value> {
+    let ChatCompletionsData {
+        messages,
+        temperature,
+        top_p,
+        functions,
+        stream,
+        response_format,
+        reasoning_effort,
+    } = data;
+
+    let messages_len = messages.len();
+    let mut list = vec![];
+    for (i, message) in messages.into_iter().enumerate() {
+        let Message { role, content, .. } = message;
+        match content {
+            MessageContent::Text(text) if role.is_assistant() && i != messages_len - 1 => {
+                list.push(json!({ "role": role, "content": strip_think_tag(&text) }));
+            }
+            MessageContent::Text(text) => list.push(json!({ "role": role, "content": text })),
+            MessageContent::Array(parts) => {
+                let mut texts = vec![];
+                let mut images = vec![];
+                for part in parts {
+                    match part {
+                        MessageContentPart::Text { text } => texts.push(text),
+                        MessageContentPart::ImageUrl {
+                            image_url: ImageUrl { url },
+                        } => match url.split_once(";base64,") {
+                            Some((_, data)) if url.starts_with("data:") => {
+                                images.push(data.to_string())
+                            }
+                            _ => bail!("The model does not support non-data URL images"),
+                        },
+                    }
+                }
+                list.push(json!({ "role": role, "content": texts.join("\n\n"), "images": images }));
+            }
+            MessageContent::ToolCalls(MessageContentToolCalls {
+                tool_results, text, ..
+            }) => {
+                let tool_calls: Vec<Value> = tool_results
+                    .iter()
+                    .map(|tool_result| {
+                        json!({
+                            "function": {
+                                "name": tool_result.call.name,
+                                "arguments": tool_result.call.arguments,
+                            },
+                        })
+                    })
+                    .collect();
+                list.push(json!({
+                    "role": MessageRole::Assistant,
+                    "content": text,
+                    "tool_calls": tool_calls,
+                }));
+                for tool_result in tool_results {
+                    list.push(json!({
+                        "role": "tool",
+                        "content": tool_result.output.to_string(),
+                        "tool_name": tool_result.call.name,
+                    }));
+                }
+            }
+        }
+    }
+
+    // Ollama streams by default
+    let mut body = json!({
+        "model": &model.real_name(),
+        "messages": list,
+        "stream": stream,
+    });
+
+    let mut options = json!({});
+    if let Some(v) = model.max_tokens_param() {
+        options["num_predict"] = v.into();
+    }
+    if let Some(v) = temperature {
+        options["temperature"] = v.into();
+    }
+    if let Some(v) = top_p {
+        options["top_p"] = v.into();
+    }
+    if let Some(v) = config.num_ctx {
+        options["num_ctx"] = v.into();
+    }
+    if options.as_object().is_some_and(|v| !v.is_empty()) {
+        body["options"] = options;
+    }
+    if let Some(v) = &config.keep_alive {
+        body["keep_alive"] = v.clone();
+    }
+    // Ollama takes no token budget, thinking is just switched on
+    if reasoning_effort.is_some() {
+        body["think"] = true.into();
+    }
+    if let Some(functions) = functions {
+        body["tools"] = functions
+            .iter()
+            .map(|v| {
+                json!({
+                    "type": "function",
+                    "function": v,
+                })
+            })
+            .collect();
+    }
+    if let Some(schema) = response_format {
+        body["format"] = schema;
+    }
+    Ok(body)
+}
+
+fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
+    let text = data["message"]["content"].as_str().unwrap_or_default();
+    let tool_calls = extract_tool_calls(data);
+    if text.is_empty() && tool_calls.is_empty() {
+        bail!("Invalid response data: {data}");
+    }
+    let thinking = data["message"]["thinking"]
+        .as_str()
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            vec![ThinkingBlock {
+                text: v.to_string(),
+                ..Default::default()
+            }]
+        })
+        .unwrap_or_default();
+    let output = ChatCompletionsOutput {
+        text: text.to_string(),
+        thinking,
+        tool_calls,
+        id: None,
+        input_tokens: data["prompt_eval_count"].as_u64(),
+        output_tokens: data["eval_count"].as_u64(),
+    };
+    Ok(output)
+}
+
+fn extract_tool_calls(data: &Value) -> Vec<ToolCall> {
+    let Some(calls) = data["message"]["tool_calls"].as_array() else {
+        return vec![];
+    };
+    calls
+        .iter()
+        .filter_map(|call| {
+            let function = &call["function"];
+            let name = function["name"].as_str()?;
+            let arguments = match &function["arguments"] {
+                Value::String(v) => serde_json::from_str(v).ok()?,
+                v => v.clone(),
+            };
+            let id = call["id"].as_str().map(|v| v.to_string());
+            Some(ToolCall::new(name.to_string(), arguments, id))
+        })
+        .collect()
+}
+
+fn api_base(config: &OllamaConfig) -> String {
+    let api_base = config
+        .api_base
+        .clone()
+        .or_else(|| {
+            let env_name = format!("{}_API_BASE", OllamaClient::name(config)).to_ascii_uppercase();
+            std::env::var(env_name).ok()
+        })
+        .unwrap_or_else(|| API_BASE.to_string());
+    api_base.trim_end_matches('/').to_string()
+}
+
+fn build_local_client(config: &OllamaConfig) -> Result<ReqwestClient> {
+    let mut builder = ReqwestClient::builder();
+    if let Some(proxy) = config.extra.as_ref().and_then(|v| v.proxy.as_deref()) {
+        builder = set_proxy(builder, proxy)?;
+    }
+    let client = builder
+        .connect_timeout(Duration::from_secs(2))
+        .timeout(Duration::from_secs(10))
+        .build()
+        .with_context(|| "Failed to build client")?;
+    Ok(client)
+}
+
+/// Fills the Ollama clients that list no models with the models installed in the daemon,
+/// only the clients in `client_names` when it is given. Returns whether any models were added.
+pub async fn load_ollama_models(
+    clients: &mut [ClientConfig],
+    client_names: Option<&[String]>,
+) -> bool {
+    let mut loaded = false;
+    for client_config in clients.iter_mut() {
+        let ClientConfig::OllamaConfig(c) = client_config else {
+            continue;
+        };
+        if !c.models.is_empty() {
+            continue;
+        }
+        if client_names.is_some_and(|v| !v.iter().any(|v| v == OllamaClient::name(c))) {
+            continue;
+        }
+        match fetch_local_models(c).await {
+            Ok(models) => {
+                loaded |= !models.is_empty();
+                c.models = models;
+            }
+            Err(err) => warn!(
+                "Failed to list the models of '{}', {err:#}",
+                OllamaClient::name(c)
+            ),
+        }
+    }
+    loaded
+}
+
+async fn fetch_local_models(config: &OllamaConfig) -> Result<Vec<ModelData>> {
+    let client = build_local_client(config)?;
+    let api_base = api_base(config);
+    let res = send_request(client.get(format!("{api_base}/api/tags"))).await?;
+    let data: Value = res.json().await?;
+    let names: Vec<&str> = data["models"]
+        .as_array()
+        .map(|v| v.iter().filter_map(|v| v["name"].as_str()).collect())
+        .unwrap_or_default();
+    let details = join_all(names.iter().map(|name| {
+        let builder = client
+            .post(format!("{api_base}/api/show"))
+            .json(&json!({ "model": name }));
+        async move {
+            let res = send_request(builder).await?;
+            res.json::<Value>().await.map_err(anyhow::Error::from)
+        }
+    }))
+    .await;
+    let models = names
+        .into_iter()
+        .zip(details)
+        .map(|(name, detail)| {
+            let detail = detail.unwrap_or_else(|err| {
+                warn!("Failed to show the Ollama model '{name}', {err:#}");
+                Value::Null
+            });
+            to_model_data(name, &detail)
+        })
+        .collect();
+    Ok(models)
+}
+
+fn to_model_data(name: &str, detail: &Value) -> ModelData {
+    let name = name.strip_suffix(":latest").unwrap_or(name);
+    let mut data = ModelData::new(name);
+    let context_length = detail["model_info"].as_object().and_then(|info| {
+        info.iter()
+            .find(|(key, _)| key.ends_with(".context_length"))
+            .and_then(|(_, v)| v.as_u64())
+            .map(|v| v as usize)
+    });
+    let capabilities: Vec<&str> = detail["capabilities"]
+        .as_array()
+        .map(|v| v.iter().filter_map(|v| v.as_str()).collect())
+        .unwrap_or_default();
+    if capabilities.contains(&"embedding") && !capabilities.contains(&"completion") {
+        data.model_type = "embedding".into();
+        data.max_tokens_per_chunk = context_length;
+        data.default_chunk_size = Some(1000);
+        data.max_batch_size = Some(50);
+    } else {
+        data.max_input_tokens = context_length;
+        data.supports_vision = capabilities.contains(&"vision");
+        data.supports_function_calling = capabilities.contains(&"tools");
+        data.supports_reasoning = capabilities.contains(&"thinking");
+    }
+    data
+}
+
+/// Offers to pull a model that isn't installed, returns false for models of other clients.
+pub async fn pull_ollama_model(
+    config: &GlobalConfig,
+    model: &Model,
+    abort_signal: AbortSignal,
+) -> Result<bool> {
+    let ollama_config = config.read().clients.iter().find_map(|v| {
+        let ClientConfig::OllamaConfig(c) = v else {
+            return None;
+        };
+        (OllamaClient::name(c) == model.client_name()).then(|| c.clone())
+    });
+    let Some(ollama_config) = ollama_config else {
+        return Ok(false);
+    };
+    let models = fetch_local_models(&ollama_config).await?;
+    let name = model.real_name();
+    let installed = models
+        .iter()
+        .any(|v| v.name == name || format!("{}:latest", v.name) == name);
+    if installed {
+        return Ok(false);
+    }
+    let ans = inquire::Confirm::new(&format!("Pull '{name}' from Ollama?"))
+        .with_default(true)
+        .prompt()?;
+    if !ans {
+        return Ok(false);
+    }
+    let mut builder = ReqwestClient::builder();
+    if let Some(proxy) = ollama_config.extra.as_ref().and_then(|v| v.proxy.as_deref()) {
+        builder = set_proxy(builder, proxy)?;
+    }
+    let client = builder.build()?;
+    let builder = client
+        .post(format!("{}/api/pull", api_base(&ollama_config)))
+        .json(&json!({ "model": name }));
+    let (spinner, spinner_rx) = Spinner::create("Pulling");
+    let pull = async {
+        let res = send_request(builder).await?;
+        let handle = |value: &str| -> Result<()> {
+            let data: Value = serde_json::from_str(value)?;
+            if let Some(error) = data["error"].as_str() {
+                bail!("{error}");
+            }
+            let status = data["status"].as_str().unwrap_or_default();
+            let message = match (data["completed"].as_u64(), data["total"].as_u64()) {
+                (Some(completed), Some(total)) if total > 0 => {
+                    format!("{status} {}%", completed * 100 / total)
+                }
+                _ => status.to_string(),
+            };
+            let _ = spinner.set_message(message);
+            Ok(())
+        };
+        json_stream(res.bytes_stream(), handle).await
+    };
+    abortable_run_with_spinner_rx(pull, spinner_rx, abort_signal).await?;
+    println!("✓ Pulled '{name}'.");
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::utils::create_abort_signal;
+
+    use parking_lot::{Mutex, RwLock};
+    use std::sync::Arc;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::mpsc::unbounded_channel,
+    };
+
+    type Requests = Arc<Mutex<Vec<(String, Value)>>>;
+
+    /// Serves canned Ollama replies and keeps the requests it gets.
+    async fn start_stand_in_server() -> (String, Requests) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests: Requests = Default::default();
+        let requests_ = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let requests = requests_.clone();
+                tokio::spawn(async move {
+                    let (path, body) = read_request(&mut stream).await;
+                    let reply = stand_in_reply(&path, &body);
+                    requests.lock().push((path, body));
+                    let res = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{reply}",
+                        reply.len()
+                    );
+                    let _ = stream.write_all(res.as_bytes()).await;
+                });
+            }
+        });
+        (format!("http://{addr}"), requests)
+    }
+
+    async fn read_request(stream: &mut TcpStream) -> (String, Value) {
+        let mut buf = vec![];
+        let mut chunk = [0; 4096];
+        loop {
+            let n = stream.read(&mut chunk).await.unwrap();
+            buf.extend_from_slice(&chunk[..n]);
+            let text = String::from_utf8_lossy(&buf).to_string();
+            let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                continue;
+            };
+            let content_length = head
+                .lines()
+                .find_map(|v| {
+                    let (name, value) = v.split_once(':')?;
+                    name.eq_ignore_ascii_case("content-length")
+                        .then(|| value.trim().parse::<usize>().ok())?
+                })
+                .unwrap_or_default();
+            if n == 0 || body.len() >= content_length {
+                let path = head.split(' ').nth(1).unwrap_or_default().to_string();
+                let body = serde_json::from_str(body).unwrap_or_default();
+                return (path, body);
+            }
+        }
+    }
+
+    fn stand_in_reply(path: &str, body: &Value) -> String {
+        match path {
+            "/api/tags" => json!({
+                "models": [{ "name": "llama3.1:latest" }, { "name": "nomic-embed-text:latest" }]
+            })
+            .to_string(),
+            "/api/show" if body["model"] == "llama3.1:latest" => json!({
+                "model_info": { "llama.context_length": 131072 },
+                "capabilities": ["completion", "tools"],
+            })
+            .to_string(),
+            "/api/show" => json!({
+                "model_info": { "bert.context_length": 8192 },
+                "capabilities": ["embedding"],
+            })
+            .to_string(),
+            "/api/chat" if body["stream"] == true => [
+                json!({ "message": { "role": "assistant", "content": "", "thinking": "Hmm" }, "done": false }),
+                json!({ "message": { "role": "assistant", "content": "Hel" }, "done": false }),
+                json!({ "message": { "role": "assistant", "content": "lo" }, "done": false }),
+                json!({ "message": { "role": "assistant", "content": "" }, "done": true, "prompt_eval_count": 12, "eval_count": 3 }),
+            ]
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join("\n"),
+            "/api/chat" => json!({
+                "message": {
+                    "role": "assistant",
+                    "content": "",
+                    "tool_calls": [{ "function": { "name": "get_weather", "arguments": { "city": "Paris" } } }],
+                },
+                "done": true,
+                "prompt_eval_count": 12,
+                "eval_count": 5,
+            })
+            .to_string(),
+            "/api/embed" => json!({ "embeddings": [[0.1, 0.2], [0.3, 0.4]] }).to_string(),
+            _ => json!({ "error": "not found" }).to_string(),
+        }
+    }
+
+    fn stand_in_client(api_base: &str, model_name: &str) -> Box<dyn Client> {
+        let config = Config {
+            clients: vec![ClientConfig::OllamaConfig(OllamaConfig {
+                api_base: Some(api_base.into()),
+                keep_alive: Some("10m".into()),
+                num_ctx: Some(8192),
+                ..Default::default()
+            })],
+            ..Default::default()
+        };
+        let config = Arc::new(RwLock::new(config));
+        OllamaClient::init(&config, &Model::new("ollama", model_name)).unwrap()
+    }
+
+    fn chat_data(stream: bool) -> ChatCompletionsData {
+        ChatCompletionsData {
+            messages: vec![Message::new(
+                MessageRole::User,
+                MessageContent::Text("Hello".into()),
+            )],
+            temperature: Some(0.5),
+            top_p: None,
+            functions: None,
+            stream,
+            response_format: None,
+            reasoning_effort: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chat_completions() {
+        let (api_base, requests) = start_stand_in_server().await;
+        let client = stand_in_client(&api_base, "llama3.1");
+        let output = client
+            .chat_completions_inner(&client.build_client().unwrap(), chat_data(false))
+            .await
+            .unwrap();
+        assert_eq!(output.tool_calls.len(), 1);
+        assert_eq!(output.tool_calls[0].name, "get_weather");
+        assert_eq!(output.tool_calls[0].arguments, json!({ "city": "Paris" }));
+        assert_eq!(output.input_tokens, Some(12));
+        assert_eq!(output.output_tokens, Some(5));
+
+        let (path, body) = requests.lock()[0].clone();
+        assert_eq!(path, "/api/chat");
+        assert_eq!(body["model"], "llama3.1");
+        assert_eq!(body["stream"], false);
+        assert_eq!(body["keep_alive"], "10m");
+        assert_eq!(body["options"], json!({ "temperature": 0.5, "num_ctx": 8192 }));
+    }
+
+    #[tokio::test]
+    async fn test_chat_completions_streaming() {
+        let (api_base, requests) = start_stand_in_server().await;
+        let client = stand_in_client(&api_base, "llama3.1");
+        let (tx, _rx) = unbounded_channel();
+        let mut handler = SseHandler::new(tx, create_abort_signal());
+        client
+            .chat_completions_streaming_inner(
+                &client.build_client().unwrap(),
+                &mut handler,
+                chat_data(true),
+            )
+            .await
+            .unwrap();
+        let output = handler.to_output();
+        assert_eq!(output.text, "Hello");
+        assert_eq!(output.thinking[0].text, "Hmm");
+        assert_eq!(output.input_tokens, Some(12));
+        assert_eq!(output.output_tokens, Some(3));
+        assert_eq!(requests.lock()[0].1["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn test_embeddings() {
+        let (api_base, requests) = start_stand_in_server().await;
+        let client = stand_in_client(&api_base, "nomic-embed-text");
+        let data = EmbeddingsData::new(vec!["a".into(), "b".into()], false);
+        let output = client
+            .embeddings_inner(&client.build_client().unwrap(), &data)
+            .await
+            .unwrap();
+        assert_eq!(output, vec![vec![0.1, 0.2], vec![0.3, 0.4]]);
+
+        let (path, body) = requests.lock()[0].clone();
+        assert_eq!(path, "/api/embed");
+        assert_eq!(body["model"], "nomic-embed-text");
+        assert_eq!(body["input"], json!(["a", "b"]));
+    }
+
+    #[tokio::test]
+    async fn test_load_ollama_models() {
+        let (api_base, _) = start_stand_in_server().await;
+        let mut clients = vec![ClientConfig::OllamaConfig(OllamaConfig {
+            api_base: Some(api_base),
+            ..Default::default()
+        })];
+        assert!(!load_ollama_models(&mut clients, Some(&["other".into()])).await);
+        let ClientConfig::OllamaConfig(c) = &clients[0] else {
+            unreachable!()
+        };
+        assert!(c.models.is_empty());
+
+        assert!(load_ollama_models(&mut clients, None).await);
+        let ClientConfig::OllamaConfig(c) = &clients[0] else {
+            unreachable!()
+        };
+        let names: Vec<&str> = c.models.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, ["llama3.1", "nomic-embed-text"]);
+        assert_eq!(c.models[0].max_input_tokens, Some(131072));
+        assert_eq!(c.models[1].model_type, "embedding");
+    }
+
+    #[test]
+    fn test_to_model_data() {
+        let detail = json!({
+            "model_info": { "llama.context_length": 131072 },
+            "capabilities": ["completion", "tools", "thinking"],
+        });
+        let data = to_model_data("llama3.1:latest", &detail);
+        assert_eq!(data.name, "llama3.1");
+        assert_eq!(data.max_input_tokens, Some(131072));
+        assert!(data.supports_function_calling);
+        assert!(data.supports_reasoning);
+        assert!(!data.supports_vision);
+
+        let detail = json!({
+            "model_info": { "bert.context_length": 8192 },
+            "capabilities": ["embedding"],
+        });
+        let data = to_model_data("nomic-embed-text", &detail);
+        assert_eq!(data.model_type, "embedding");
+        assert_eq!(data.max_tokens_per_chunk, Some(8192));
+    }
+}
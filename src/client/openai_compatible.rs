@@ -37,13 +37,17 @@ impl_client_trait!(
 
 fn prepare_chat_completions(
     self_: &OpenAICompatibleClient,
-    data: ChatCompletionsData,
+    mut data: ChatCompletionsData,
 ) -> Result<RequestData> {
     let api_key = self_.get_api_key().ok();
     let api_base = get_api_base_ext(self_)?;
 
     let url = format!("{api_base}/chat/completions");
 
+    // Not every compatible API supports `json_schema`, the prompt and local validation enforce it otherwise.
+    if !self_.model.supports_response_format() {
+        data.response_format = None;
+    }
     let body = openai_build_chat_completions_body(data, &self_.model);
 
     let mut request_data = RequestData::new(url, body);
@@ -118,12 +122,8 @@ fn get_api_base_ext(self_: &OpenAICompatibleClient) -> Result<String> {
 }
 
 pub async fn generic_rerank(builder: RequestBuilder, _model: &Model) -> Result<RerankOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let mut data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
     if data.get("results").is_none() && data.get("data").is_some() {
         if let Some(data_obj) = data.as_object_mut() {
             if let Some(value) = data_obj.remove("data") {
@@ -1,18 +1,20 @@
-use super::{catch_error, ToolCall};
+use super::{response_error, ChatCompletionsOutput, Model, ThinkingBlock, ToolCall};
 use crate::utils::AbortSignal;
 
 use anyhow::{anyhow, bail, Context, Result};
 use futures_util::{Stream, StreamExt};
 use reqwest::RequestBuilder;
 use reqwest_eventsource::{Error as EventSourceError, Event, RequestBuilderExt};
-use serde_json::Value;
 use tokio::sync::mpsc::UnboundedSender;
 
 pub struct SseHandler {
     sender: UnboundedSender<SseEvent>,
     abort_signal: AbortSignal,
     buffer: String,
+    thinking: Vec<ThinkingBlock>,
     tool_calls: Vec<ToolCall>,
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
 }
 
 impl SseHandler {
@@ -21,7 +23,10 @@ impl SseHandler {
             sender,
             abort_signal,
             buffer: String::new(),
+            thinking: Vec::new(),
             tool_calls: Vec::new(),
+            input_tokens: None,
+            output_tokens: None,
         }
     }
 
@@ -44,6 +49,80 @@ impl SseHandler {
         Ok(())
     }
 
+    pub fn thinking(&mut self, text: &str) -> Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        match self.thinking.last_mut() {
+            Some(block) if block.signature.is_none() && block.redacted.is_none() => {
+                block.text.push_str(text)
+            }
+            _ => self.thinking.push(ThinkingBlock {
+                text: text.to_string(),
+                ..Default::default()
+            }),
+        }
+        self.send_thinking(text)
+    }
+
+    fn send_thinking(&self, text: &str) -> Result<()> {
+        let ret = self
+            .sender
+            .send(SseEvent::Thinking(text.to_string()))
+            .with_context(|| "Failed to send SseEvent:Thinking");
+        if let Err(err) = ret {
+            if self.abort_signal.aborted() {
+                return Ok(());
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Signs the thinking block received last, which closes it.
+    pub fn thinking_signature(&mut self, signature: &str) {
+        match self.thinking.last_mut() {
+            Some(block) if block.signature.is_none() && block.redacted.is_none() => {
+                block.signature = Some(signature.to_string())
+            }
+            _ => self.thinking.push(ThinkingBlock {
+                signature: Some(signature.to_string()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Keeps the signature Gemini puts on a function call, it goes back with that call.
+    pub fn tool_call_signature(&mut self, call_id: &str, signature: &str) {
+        self.thinking.push(ThinkingBlock {
+            signature: Some(signature.to_string()),
+            tool_call_id: Some(call_id.to_string()),
+            ..Default::default()
+        });
+    }
+
+    pub fn redacted_thinking(&mut self, data: &str) {
+        self.thinking.push(ThinkingBlock {
+            redacted: Some(data.to_string()),
+            ..Default::default()
+        });
+    }
+
+    /// Replays thinking blocks, e.g. from the cache, as if they were streamed.
+    pub fn thinking_blocks(&mut self, blocks: Vec<ThinkingBlock>) -> Result<()> {
+        for block in blocks {
+            if !block.text.is_empty() {
+                self.send_thinking(&block.text)?;
+            }
+            self.thinking.push(block);
+        }
+        Ok(())
+    }
+
+    pub fn set_thinking_source(&mut self, model: &Model) {
+        ThinkingBlock::set_source(&mut self.thinking, model);
+    }
+
     pub fn done(&mut self) {
         // debug!("HandleDone");
         let ret = self.sender.send(SseEvent::Done);
@@ -61,25 +140,48 @@ impl SseHandler {
         Ok(())
     }
 
-    pub fn abort(&self) -> AbortSignal {
-        self.abort_signal.clone()
+    /// Providers may report usage in several events, the latest values win.
+    pub fn usage(&mut self, input_tokens: Option<u64>, output_tokens: Option<u64>) {
+        if input_tokens.is_some() {
+            self.input_tokens = input_tokens;
+        }
+        if output_tokens.is_some() {
+            self.output_tokens = output_tokens;
+        }
     }
 
-    pub fn tool_calls(&self) -> &[ToolCall] {
-        &self.tool_calls
+    pub fn to_output(&self) -> ChatCompletionsOutput {
+        ChatCompletionsOutput {
+            text: self.buffer.clone(),
+            thinking: self.thinking.clone(),
+            tool_calls: self.tool_calls.clone(),
+            input_tokens: self.input_tokens,
+            output_tokens: self.output_tokens,
+            ..Default::default()
+        }
     }
 
-    pub fn take(self) -> (String, Vec<ToolCall>) {
+    pub fn abort(&self) -> AbortSignal {
+        self.abort_signal.clone()
+    }
+
+    pub fn take(self) -> (String, Vec<ThinkingBlock>, Vec<ToolCall>) {
         let Self {
-            buffer, tool_calls, ..
+            buffer,
+            thinking,
+            tool_calls,
+            ..
         } = self;
-        (buffer, tool_calls)
+        (buffer, thinking, tool_calls)
     }
 }
 
 #[derive(Debug)]
 pub enum SseEvent {
     Text(String),
+    Thinking(String),
+    /// A progress line reported by a running tool
+    Progress(String),
     Done,
 }
 
@@ -110,18 +212,8 @@ where
             Err(err) => {
                 match err {
                     EventSourceError::StreamEnded => {}
-                    EventSourceError::InvalidStatusCode(status, res) => {
-                        let text = res.text().await?;
-                        let data: Value = match text.parse() {
-                            Ok(data) => data,
-                            Err(_) => {
-                                bail!(
-                                    "Invalid response data: {text} (status: {})",
-                                    status.as_u16()
-                                );
-                            }
-                        };
-                        catch_error(&data, status.as_u16())?;
+                    EventSourceError::InvalidStatusCode(_, res) => {
+                        return Err(response_error(res).await);
                     }
                     EventSourceError::InvalidContentType(header_value, res) => {
                         let text = res.text().await?;
@@ -131,7 +223,7 @@ where
                         );
                     }
                     _ => {
-                        bail!("{}", err);
+                        return Err(err.into());
                     }
                 }
                 es.close();
@@ -212,10 +304,8 @@ impl JsonStreamParser {
                     }
                     self.balances.push(ch);
                 }
-                '[' => {
-                    if self.start.is_some() {
-                        self.balances.push(ch);
-                    }
+                '[' if self.start.is_some() => {
+                    self.balances.push(ch);
                 }
                 '}' => {
                     self.balances.pop();
@@ -250,9 +340,9 @@ mod tests {
         let len = text.len();
         let cut1 = rng.random_range(1..len - 1);
         let cut2 = rng.random_range(cut1 + 1..len);
-        let chunk1 = text[..cut1].as_bytes().to_vec();
-        let chunk2 = text[cut1..cut2].as_bytes().to_vec();
-        let chunk3 = text[cut2..].as_bytes().to_vec();
+        let chunk1 = text.as_bytes()[..cut1].to_vec();
+        let chunk2 = text.as_bytes()[cut1..cut2].to_vec();
+        let chunk3 = text.as_bytes()[cut2..].to_vec();
         vec![chunk1, chunk2, chunk3]
     }
 
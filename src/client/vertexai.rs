@@ -176,12 +176,8 @@ pub async fn gemini_chat_completions(
     builder: RequestBuilder,
     _model: &Model,
 ) -> Result<ChatCompletionsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
     debug!("non-stream-data: {data}");
     gemini_extract_chat_completions_text(&data)
 }
@@ -191,50 +187,57 @@ pub async fn gemini_chat_completions_streaming(
     handler: &mut SseHandler,
     _model: &Model,
 ) -> Result<()> {
-    let res = builder.send().await?;
-    let status = res.status();
-    if !status.is_success() {
-        let data: Value = res.json().await?;
-        catch_error(&data, status.as_u16())?;
-    } else {
-        let handle = |value: &str| -> Result<()> {
-            let data: Value = serde_json::from_str(value)?;
-            debug!("stream-data: {data}");
-            if let Some(parts) = data["candidates"][0]["content"]["parts"].as_array() {
-                for (i, part) in parts.iter().enumerate() {
-                    if let Some(text) = part["text"].as_str() {
-                        if i > 0 {
-                            handler.text("\n\n")?;
-                        }
-                        handler.text(text)?;
-                    } else if let (Some(name), Some(args)) = (
-                        part["functionCall"]["name"].as_str(),
-                        part["functionCall"]["args"].as_object(),
-                    ) {
-                        handler.tool_call(ToolCall::new(name.to_string(), json!(args), None))?;
+    let res = send_request(builder).await?;
+    let handle = |value: &str| -> Result<()> {
+        let data: Value = serde_json::from_str(value)?;
+        debug!("stream-data: {data}");
+        handler.usage(
+            data["usageMetadata"]["promptTokenCount"].as_u64(),
+            data["usageMetadata"]["candidatesTokenCount"].as_u64(),
+        );
+        if let Some(parts) = data["candidates"][0]["content"]["parts"].as_array() {
+            let mut has_text = false;
+            for part in parts {
+                if let (Some(text), Some(true)) = (part["text"].as_str(), part["thought"].as_bool())
+                {
+                    handler.thinking(text)?;
+                } else if let Some(text) = part["text"].as_str() {
+                    if has_text {
+                        handler.text("\n\n")?;
                     }
+                    handler.text(text)?;
+                    has_text = true;
+                } else if let (Some(name), Some(args)) = (
+                    part["functionCall"]["name"].as_str(),
+                    part["functionCall"]["args"].as_object(),
+                ) {
+                    let id = function_call_id(part);
+                    if let Some(signature) = part["thoughtSignature"].as_str() {
+                        handler.tool_call_signature(&id, signature);
+                    }
+                    handler.tool_call(ToolCall::new(name.to_string(), json!(args), Some(id)))?;
+                    continue;
+                }
+                if let Some(signature) = part["thoughtSignature"].as_str() {
+                    handler.thinking_signature(signature);
                 }
-            } else if let Some("SAFETY") = data["promptFeedback"]["blockReason"]
-                .as_str()
-                .or_else(|| data["candidates"][0]["finishReason"].as_str())
-            {
-                bail!("Blocked due to safety")
             }
+        } else if let Some("SAFETY") = data["promptFeedback"]["blockReason"]
+            .as_str()
+            .or_else(|| data["candidates"][0]["finishReason"].as_str())
+        {
+            bail!("Blocked due to safety")
+        }
 
-            Ok(())
-        };
-        json_stream(res.bytes_stream(), handle).await?;
-    }
+        Ok(())
+    };
+    json_stream(res.bytes_stream(), handle).await?;
     Ok(())
 }
 
 async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
     let res_body: EmbeddingsResBody =
         serde_json::from_value(data).context("Invalid embeddings data")?;
     let output = res_body
@@ -260,19 +263,52 @@ struct EmbeddingsResBodyPredictionEmbeddings {
     values: Vec<f32>,
 }
 
+/// Gemini may leave out the id of a call, one is made up to pair the call with its signature.
+fn function_call_id(part: &Value) -> String {
+    part["functionCall"]["id"]
+        .as_str()
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| format!("call_{}", uuid::Uuid::new_v4().simple()))
+}
+
 fn gemini_extract_chat_completions_text(data: &Value) -> Result<ChatCompletionsOutput> {
     let mut text_parts = vec![];
+    let mut thinking: Vec<ThinkingBlock> = vec![];
     let mut tool_calls = vec![];
     if let Some(parts) = data["candidates"][0]["content"]["parts"].as_array() {
         for part in parts {
-            if let Some(text) = part["text"].as_str() {
+            if let (Some(text), Some(true)) = (part["text"].as_str(), part["thought"].as_bool()) {
+                thinking.push(ThinkingBlock {
+                    text: text.to_string(),
+                    ..Default::default()
+                });
+            } else if let Some(text) = part["text"].as_str() {
                 text_parts.push(text);
-            }
-            if let (Some(name), Some(args)) = (
+            } else if let (Some(name), Some(args)) = (
                 part["functionCall"]["name"].as_str(),
                 part["functionCall"]["args"].as_object(),
             ) {
-                tool_calls.push(ToolCall::new(name.to_string(), json!(args), None));
+                let id = function_call_id(part);
+                if let Some(signature) = part["thoughtSignature"].as_str() {
+                    thinking.push(ThinkingBlock {
+                        signature: Some(signature.to_string()),
+                        tool_call_id: Some(id.clone()),
+                        ..Default::default()
+                    });
+                }
+                tool_calls.push(ToolCall::new(name.to_string(), json!(args), Some(id)));
+                continue;
+            }
+            if let Some(signature) = part["thoughtSignature"].as_str() {
+                match thinking.last_mut() {
+                    Some(block) if block.signature.is_none() => {
+                        block.signature = Some(signature.to_string())
+                    }
+                    _ => thinking.push(ThinkingBlock {
+                        signature: Some(signature.to_string()),
+                        ..Default::default()
+                    }),
+                }
             }
         }
     }
@@ -290,6 +326,7 @@ fn gemini_extract_chat_completions_text(data: &Value) -> Result<ChatCompletionsO
     }
     let output = ChatCompletionsOutput {
         text,
+        thinking,
         tool_calls,
         id: None,
         input_tokens: data["usageMetadata"]["promptTokenCount"].as_u64(),
@@ -308,6 +345,8 @@ pub fn gemini_build_chat_completions_body(
         top_p,
         functions,
         stream: _,
+        response_format,
+        reasoning_effort,
     } = data;
 
     let system_message = extract_system_message(&mut messages);
@@ -316,7 +355,7 @@ pub fn gemini_build_chat_completions_body(
     let contents: Vec<Value> = messages
         .into_iter()
         .flat_map(|message| {
-            let Message { role, content } = message;
+            let Message { role, content, .. } = message;
             let role = match role {
                 MessageRole::User => "user",
                 _ => "model",
@@ -343,14 +382,22 @@ pub fn gemini_build_chat_completions_body(
                             .collect();
                         vec![json!({ "role": role, "parts": parts })]
                     },
-                    MessageContent::ToolCalls(MessageContentToolCalls { tool_results, .. }) => {
+                    MessageContent::ToolCalls(MessageContentToolCalls { tool_results, thinking, .. }) => {
+                        // Gemini expects each thought signature back on the function call it came with
                         let model_parts: Vec<Value> = tool_results.iter().map(|tool_result| {
-                            json!({
+                            let mut part = json!({
                                 "functionCall": {
                                     "name": tool_result.call.name,
                                     "args": tool_result.call.arguments,
                                 }
-                            })
+                            });
+                            let signature = thinking.iter().find(|v| {
+                                v.is_from(model) && v.tool_call_id.is_some() && v.tool_call_id == tool_result.call.id
+                            }).and_then(|v| v.signature.clone());
+                            if let Some(signature) = signature {
+                                part["thoughtSignature"] = signature.into();
+                            }
+                            part
                         }).collect();
                         let function_parts: Vec<Value> = tool_results.into_iter().map(|tool_result| {
                             json!({
@@ -394,6 +441,12 @@ pub fn gemini_build_chat_completions_body(
     if let Some(v) = top_p {
         body["generationConfig"]["topP"] = v.into();
     }
+    if let Some(v) = reasoning_effort {
+        body["generationConfig"]["thinkingConfig"] = json!({
+            "thinkingBudget": v.budget_tokens(),
+            "includeThoughts": true,
+        });
+    }
 
     if let Some(functions) = functions {
         // Gemini doesn't support functions with parameters that have empty properties, so we need to patch it.
@@ -411,11 +464,63 @@ pub fn gemini_build_chat_completions_body(
             })
             .collect();
         body["tools"] = json!([{ "functionDeclarations": function_declarations }]);
+    } else if let Some(schema) = response_format {
+        // Gemini can't combine JSON mode with functions, the reply is validated locally then.
+        body["generationConfig"]["responseMimeType"] = "application/json".into();
+        body["generationConfig"]["responseSchema"] = gemini_response_schema(&schema);
     }
 
     Ok(body)
 }
 
+/// Gemini only accepts a subset of JSON schema, unsupported keywords are dropped.
+fn gemini_response_schema(schema: &Value) -> Value {
+    const KEYS: [&str; 16] = [
+        "type",
+        "format",
+        "description",
+        "nullable",
+        "enum",
+        "items",
+        "properties",
+        "required",
+        "anyOf",
+        "minItems",
+        "maxItems",
+        "minimum",
+        "maximum",
+        "minLength",
+        "maxLength",
+        "propertyOrdering",
+    ];
+    let Some(map) = schema.as_object() else {
+        return schema.clone();
+    };
+    let mut output = serde_json::Map::new();
+    for (key, value) in map {
+        if !KEYS.contains(&key.as_str()) {
+            continue;
+        }
+        let value = match key.as_str() {
+            "items" => gemini_response_schema(value),
+            "properties" => match value.as_object() {
+                Some(properties) => properties
+                    .iter()
+                    .map(|(k, v)| (k.clone(), gemini_response_schema(v)))
+                    .collect(),
+                None => value.clone(),
+            },
+            "anyOf" => match value.as_array() {
+                Some(list) => list.iter().map(gemini_response_schema).collect(),
+                None => value.clone(),
+            },
+            _ => value.clone(),
+        };
+        output.insert(key.clone(), value);
+    }
+    Value::Object(output)
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum ModelCategory {
     Gemini,
@@ -527,3 +632,58 @@ fn strip_model_version(name: &str) -> &str {
         None => name,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::function::ToolResult;
+
+    #[test]
+    fn test_gemini_thought_signatures() {
+        let data = json!({
+            "candidates": [{ "content": { "parts": [
+                { "functionCall": { "name": "a", "args": {} }, "thoughtSignature": "sig-a" },
+                { "functionCall": { "name": "b", "args": {} }, "thoughtSignature": "sig-b" },
+            ] } }]
+        });
+        let model = Model::new("gemini", "gemini-2.5-pro");
+        let mut output = gemini_extract_chat_completions_text(&data).unwrap();
+        ThinkingBlock::set_source(&mut output.thinking, &model);
+        let tool_results = output
+            .tool_calls
+            .into_iter()
+            .map(|v| ToolResult::new(v, json!("ok")))
+            .collect();
+        let data = ChatCompletionsData {
+            messages: vec![
+                Message::new(MessageRole::User, MessageContent::Text("Hi".into())),
+                Message::new(
+                    MessageRole::Assistant,
+                    MessageContent::ToolCalls(MessageContentToolCalls::new(
+                        tool_results,
+                        String::new(),
+                        output.thinking,
+                    )),
+                ),
+            ],
+            temperature: None,
+            top_p: None,
+            functions: None,
+            stream: false,
+            response_format: None,
+            reasoning_effort: None,
+        };
+
+        let body = gemini_build_chat_completions_body(data.clone(), &model).unwrap();
+        let parts = &body["contents"][1]["parts"];
+        assert_eq!(parts[0]["thoughtSignature"], "sig-a");
+        assert_eq!(parts[1]["thoughtSignature"], "sig-b");
+
+        let other_model = Model::new("vertexai", "gemini-2.5-pro");
+        let body = gemini_build_chat_completions_body(data, &other_model).unwrap();
+        assert!(body["contents"][1]["parts"][0]
+            .get("thoughtSignature")
+            .is_none());
+    }
+}
@@ -2,7 +2,8 @@ use super::*;
 
 use crate::{
     client::Model,
-    function::{run_llm_function, Functions},
+    function::{run_llm_function, Functions, ToolPolicies},
+    sandbox::SandboxConfig,
 };
 
 use anyhow::{Context, Result};
@@ -68,7 +69,10 @@ impl Agent {
 
         let rag = if rag_path.exists() {
             Some(Arc::new(Rag::load(config, DEFAULT_AGENT_NAME, &rag_path)?))
-        } else if !definition.documents.is_empty() && !config.read().info_flag {
+        } else if !definition.documents.is_empty()
+            && !config.read().info_flag
+            && !config.read().working_mode.is_serve()
+        {
             let mut ans = false;
             if *IS_STDOUT_TERMINAL {
                 ans = Confirm::new("The agent has the documents, init RAG?")
@@ -256,6 +260,14 @@ impl Agent {
             .collect()
     }
 
+    pub fn tool_policy(&self) -> &ToolPolicies {
+        &self.config.tool_policy
+    }
+
+    pub fn sandbox(&self) -> Option<&SandboxConfig> {
+        self.config.sandbox.as_ref()
+    }
+
     pub fn config_variables(&self) -> &AgentVariables {
         &self.config.variables
     }
@@ -308,6 +320,7 @@ impl Agent {
             self.name().to_string(),
             vec!["_instructions".into(), "{}".into()],
             self.variable_envs(),
+            self.config.sandbox.clone(),
         )?;
         match value {
             Some(v) => Ok(v),
@@ -340,12 +353,16 @@ impl RoleLike for Agent {
         self.config.top_p
     }
 
+    fn reasoning_effort(&self) -> Option<ReasoningEffort> {
+        self.config.reasoning_effort
+    }
+
     fn use_tools(&self) -> Option<String> {
         self.config.use_tools.clone()
     }
 
     fn set_model(&mut self, model: &Model) {
-        self.config.model_id = Some(model.id());
+        self.config.model_id = Some(model.alias_or_id());
         self.model = model.clone();
     }
 
@@ -357,6 +374,10 @@ impl RoleLike for Agent {
         self.config.top_p = value;
     }
 
+    fn set_reasoning_effort(&mut self, value: Option<ReasoningEffort>) {
+        self.config.reasoning_effort = value;
+    }
+
     fn set_use_tools(&mut self, value: Option<String>) {
         self.config.use_tools = value;
     }
@@ -371,7 +392,13 @@ pub struct AgentConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub use_tools: Option<String>,
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub tool_policy: ToolPolicies,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<SandboxConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub agent_prelude: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -409,9 +436,22 @@ impl AgentConfig {
         if let Some(v) = read_env_value::<f64>(&with_prefix("top_p")) {
             self.top_p = v;
         }
+        if let Some(v) = read_env_value::<ReasoningEffort>(&with_prefix("reasoning_effort")) {
+            self.reasoning_effort = v;
+        }
         if let Some(v) = read_env_value::<String>(&with_prefix("use_tools")) {
             self.use_tools = v;
         }
+        if let Ok(v) = env::var(with_prefix("tool_policy")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.tool_policy = v;
+            }
+        }
+        if let Ok(v) = env::var(with_prefix("sandbox")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.sandbox = Some(v);
+            }
+        }
         if let Some(v) = read_env_value::<String>(&with_prefix("agent_prelude")) {
             self.agent_prelude = v;
         }
@@ -0,0 +1,104 @@
+use super::Usage;
+
+use crate::client::{Message, ReasoningEffort};
+use crate::function::{FunctionDeclaration, ToolCall};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs::{read_to_string, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatLogRecord {
+    pub time: String,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub functions: Option<Vec<FunctionDeclaration>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+    pub messages: Vec<Message>,
+    pub output: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    pub usage: Usage,
+    pub latency_ms: u64,
+    /// Served from the response cache, so nothing was sent
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+}
+
+pub fn append_chat_log_record(path: &Path, record: &ChatLogRecord) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open chat log '{}'", path.display()))?;
+    let line = serde_json::to_string(record)?;
+    writeln!(file, "{line}")
+        .with_context(|| format!("Failed to write chat log '{}'", path.display()))?;
+    Ok(())
+}
+
+/// Records are addressed by their 1-based line number, or `last`.
+pub fn load_chat_log_record(path: &Path, id: &str) -> Result<ChatLogRecord> {
+    let content = read_to_string(path)
+        .with_context(|| format!("Failed to read chat log '{}'", path.display()))?;
+    let lines: Vec<&str> = content.lines().filter(|v| !v.trim().is_empty()).collect();
+    let index = match id {
+        "last" => lines.len().checked_sub(1),
+        _ => id
+            .parse::<usize>()
+            .ok()
+            .and_then(|v| v.checked_sub(1))
+            .filter(|v| *v < lines.len()),
+    };
+    let Some(index) = index else {
+        bail!(
+            "Invalid chat log record '{id}', expected 'last' or 1 to {}",
+            lines.len()
+        );
+    };
+    serde_json::from_str(lines[index]).with_context(|| format!("Invalid chat log record '{id}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_chat_log_record() {
+        let path =
+            std::env::temp_dir().join(format!("aichat-chat-log-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        for model in ["a:m1", "b:m2"] {
+            let record = ChatLogRecord {
+                model: model.into(),
+                ..Default::default()
+            };
+            append_chat_log_record(&path, &record).unwrap();
+        }
+        assert_eq!(load_chat_log_record(&path, "1").unwrap().model, "a:m1");
+        assert_eq!(load_chat_log_record(&path, "last").unwrap().model, "b:m2");
+        assert!(load_chat_log_record(&path, "0").is_err());
+        assert!(load_chat_log_record(&path, "3").is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
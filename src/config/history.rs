@@ -0,0 +1,255 @@
+use super::Config;
+
+use crate::client::{Message, MessageContent, MessageRole};
+use crate::utils::list_file_names;
+
+use anyhow::Result;
+use bm25::{Language, SearchEngineBuilder};
+use fancy_regex::Regex;
+use inquire::Select;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::read_to_string;
+use std::path::Path;
+use std::sync::LazyLock;
+
+const SEARCH_TOP_K: usize = 10;
+const SNIPPET_WIDTH: usize = 120;
+
+static RE_CHAT_HEADER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^# CHAT: .* \[([^\]]+)\](?: \((.+)\))?$").unwrap());
+
+#[derive(Debug, Clone)]
+pub struct HistoryHit {
+    pub session: Option<String>,
+    pub date: String,
+    pub role: Option<String>,
+    pub message_role: MessageRole,
+    pub text: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SessionFile {
+    role_name: Option<String>,
+    #[serde(default)]
+    compressed_messages: Vec<Message>,
+    #[serde(default)]
+    messages: Vec<Message>,
+    #[serde(default)]
+    branches: Vec<SessionBranchFile>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SessionBranchFile {
+    #[serde(default)]
+    messages: Vec<Message>,
+}
+
+pub fn search_history(config: &Config, query: &str) -> Result<Vec<HistoryHit>> {
+    let mut entries = vec![];
+    let sessions_dir = config.sessions_dir();
+    let names = list_file_names(&sessions_dir, ".yaml").into_iter().chain(
+        list_file_names(sessions_dir.join("_"), ".yaml")
+            .into_iter()
+            .map(|v| format!("_/{v}")),
+    );
+    for name in names {
+        let path = config.session_file(&name);
+        if let Err(err) = load_session_entries(&name, &path, &mut entries) {
+            debug!("Skip session '{name}' in history search, {err}");
+        }
+    }
+    if let Ok(content) = read_to_string(config.messages_file()) {
+        load_messages_entries(&content, &mut entries);
+    }
+    if entries.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let documents: Vec<_> = entries
+        .iter()
+        .enumerate()
+        .map(|(i, v)| bm25::Document::new(i, &v.text))
+        .collect();
+    let engine = SearchEngineBuilder::<usize>::with_documents(Language::English, documents)
+        .k1(1.5)
+        .b(0.75)
+        .build();
+    let hits = engine
+        .search(query, SEARCH_TOP_K)
+        .into_iter()
+        .filter(|v| v.score > 0.0)
+        .map(|v| entries[v.document.id].clone())
+        .collect();
+    Ok(hits)
+}
+
+pub fn render_history_hits(hits: &[HistoryHit], query: &str) -> String {
+    if hits.is_empty() {
+        return format!("No history matches '{query}'");
+    }
+    hits.iter()
+        .map(|hit| {
+            let source = match &hit.session {
+                Some(name) => format!("session {name}"),
+                None => "messages".to_string(),
+            };
+            let role = match &hit.role {
+                Some(role) => format!(" (role {role})"),
+                None => String::new(),
+            };
+            let prefix = if hit.message_role.is_user() {
+                ">> "
+            } else {
+                ""
+            };
+            format!(
+                "{}  {source}{role}\n    {prefix}{}",
+                hit.date,
+                snippet(&hit.text, query)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn select_history_session(hits: &[HistoryHit]) -> Result<Option<String>> {
+    let mut names = vec![];
+    for name in hits.iter().filter_map(|v| v.session.as_ref()) {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    if names.is_empty() {
+        return Ok(None);
+    }
+    let name = Select::new("Resume a session (esc to skip):", names).prompt_skippable()?;
+    Ok(name)
+}
+
+fn load_session_entries(name: &str, path: &Path, entries: &mut Vec<HistoryHit>) -> Result<()> {
+    let content = read_to_string(path)?;
+    let session: SessionFile = serde_yaml::from_str(&content)?;
+    let date = std::fs::metadata(path)
+        .and_then(|v| v.modified())
+        .map(|v| {
+            chrono::DateTime::<chrono::Local>::from(v)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_default();
+    let messages = session
+        .compressed_messages
+        .iter()
+        .chain(session.messages.iter())
+        .chain(session.branches.iter().flat_map(|v| v.messages.iter()));
+    let mut seen = HashSet::new();
+    for message in messages {
+        let text = match &message.content {
+            MessageContent::ToolCalls(tool_calls) => tool_calls.text.clone(),
+            content => content.to_text(),
+        };
+        if message.role.is_system() || text.trim().is_empty() || !seen.insert(text.clone()) {
+            continue;
+        }
+        entries.push(HistoryHit {
+            session: Some(name.to_string()),
+            date: date.clone(),
+            role: session.role_name.clone(),
+            message_role: message.role,
+            text,
+        });
+    }
+    Ok(())
+}
+
+/// Parses the chats appended by `Config::save_message`.
+fn load_messages_entries(content: &str, entries: &mut Vec<HistoryHit>) {
+    for chat in content.split("\n# CHAT: ") {
+        let chat = match chat.strip_prefix("# CHAT: ") {
+            Some(v) => v,
+            None => chat,
+        };
+        let (header, body) = chat.split_once('\n').unwrap_or((chat, ""));
+        let header = format!("# CHAT: {header}");
+        let (date, role) = match RE_CHAT_HEADER.captures(&header) {
+            Ok(Some(caps)) => (
+                caps.get(1).map(|v| format_date(v.as_str())),
+                caps.get(2).map(|v| v.as_str().to_string()),
+            ),
+            _ => continue,
+        };
+        let mut parts = body.split("\n--------\n");
+        let parts = [
+            (MessageRole::User, parts.next()),
+            (MessageRole::Assistant, parts.next()),
+        ];
+        for (message_role, text) in parts {
+            if let Some(text) = text.map(|v| v.trim()).filter(|v| !v.is_empty()) {
+                entries.push(HistoryHit {
+                    session: None,
+                    date: date.clone().unwrap_or_default(),
+                    role: role.clone(),
+                    message_role,
+                    text: text.to_string(),
+                });
+            }
+        }
+    }
+}
+
+fn format_date(value: &str) -> String {
+    match chrono::DateTime::parse_from_rfc3339(value) {
+        Ok(v) => v.format("%Y-%m-%d %H:%M").to_string(),
+        Err(_) => value.to_string(),
+    }
+}
+
+fn snippet(text: &str, query: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = chars
+        .iter()
+        .map(|v| v.to_lowercase().next().unwrap_or(*v))
+        .collect();
+    let position = query
+        .split_whitespace()
+        .filter_map(|term| {
+            let term: Vec<char> = term.to_lowercase().chars().collect();
+            lower.windows(term.len()).position(|v| v == term.as_slice())
+        })
+        .min()
+        .unwrap_or_default();
+    let start = position.saturating_sub(SNIPPET_WIDTH / 3);
+    let end = (start + SNIPPET_WIDTH).min(chars.len());
+    let mut output = chars[start..end]
+        .iter()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if start > 0 {
+        output = format!("...{output}");
+    }
+    if end < chars.len() {
+        output.push_str("...");
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_messages_entries() {
+        let content = "# CHAT: hello [2026-10-01T10:20:30+08:00] (coder)\nhow to sort a vec\n--------\nUse sort_unstable.\n--------\n\n# CHAT: hi [2026-10-02T10:20:30+08:00]\nhi\n--------\nhello\n--------\n\n";
+        let mut entries = vec![];
+        load_messages_entries(content, &mut entries);
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].date, "2026-10-01 10:20");
+        assert_eq!(entries[0].role.as_deref(), Some("coder"));
+        assert_eq!(entries[1].text, "Use sort_unstable.");
+        assert_eq!(entries[3].role, None);
+        assert_eq!(snippet("Use sort_unstable.", "SORT"), "Use sort_unstable.");
+    }
+}
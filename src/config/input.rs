@@ -1,8 +1,9 @@
 use super::*;
 
 use crate::client::{
-    init_client, patch_messages, ChatCompletionsData, Client, ImageUrl, Message, MessageContent,
-    MessageContentPart, MessageContentToolCalls, MessageRole, Model,
+    init_client, patch_messages, patch_response_format, ChatCompletionsData, Client, ImageUrl,
+    Message, MessageContent, MessageContentPart, MessageContentToolCalls, MessageRole, Model,
+    ThinkingBlock,
 };
 use crate::function::ToolResult;
 use crate::utils::{base64_encode, is_loader_protocol, sha256, AbortSignal};
@@ -25,6 +26,7 @@ pub struct Input {
     last_reply: Option<String>,
     continue_output: Option<String>,
     regenerate: bool,
+    edit_at: Option<usize>,
     medias: Vec<String>,
     data_urls: HashMap<String, String>,
     tool_calls: Option<MessageContentToolCalls>,
@@ -32,6 +34,7 @@ pub struct Input {
     rag_name: Option<String>,
     with_session: bool,
     with_agent: bool,
+    budget_confirmed: bool,
 }
 
 impl Input {
@@ -45,6 +48,7 @@ impl Input {
             last_reply: None,
             continue_output: None,
             regenerate: false,
+            edit_at: None,
             medias: Default::default(),
             data_urls: Default::default(),
             tool_calls: None,
@@ -52,6 +56,7 @@ impl Input {
             rag_name: None,
             with_session,
             with_agent,
+            budget_confirmed: false,
         }
     }
 
@@ -112,6 +117,7 @@ impl Input {
             last_reply,
             continue_output: None,
             regenerate: false,
+            edit_at: None,
             medias,
             data_urls,
             tool_calls: Default::default(),
@@ -119,6 +125,7 @@ impl Input {
             rag_name: None,
             with_session,
             with_agent,
+            budget_confirmed: false,
         })
     }
 
@@ -165,7 +172,25 @@ impl Input {
     }
 
     pub fn stream(&self) -> bool {
-        self.config.read().stream && !self.role().model().no_stream()
+        self.config.read().stream
+            && !self.role().model().no_stream()
+            && self.response_format().is_none()
+    }
+
+    pub fn response_format(&self) -> Option<Value> {
+        self.config
+            .read()
+            .response_format
+            .clone()
+            .or_else(|| self.role().response_format().cloned())
+    }
+
+    pub fn budget_confirmed(&self) -> bool {
+        self.budget_confirmed
+    }
+
+    pub fn set_budget_confirmed(&mut self) {
+        self.budget_confirmed = true;
     }
 
     pub fn continue_output(&self) -> Option<&str> {
@@ -192,6 +217,21 @@ impl Input {
         self.regenerate = true;
     }
 
+    pub fn edit_at(&self) -> Option<usize> {
+        self.edit_at
+    }
+
+    pub fn set_edit_at(
+        &mut self,
+        index: usize,
+        medias: Vec<String>,
+        data_urls: HashMap<String, String>,
+    ) {
+        self.edit_at = Some(index);
+        self.medias = medias;
+        self.data_urls = data_urls;
+    }
+
     pub async fn use_embeddings(&mut self, abort_signal: AbortSignal) -> Result<()> {
         if self.text.is_empty() {
             return Ok(());
@@ -212,16 +252,30 @@ impl Input {
         self.rag_name.as_deref()
     }
 
-    pub fn merge_tool_results(mut self, output: String, tool_results: Vec<ToolResult>) -> Self {
+    pub fn merge_tool_results(
+        mut self,
+        output: String,
+        thinking: Vec<ThinkingBlock>,
+        tool_results: Vec<ToolResult>,
+    ) -> Self {
         match self.tool_calls.as_mut() {
             Some(exist_tool_results) => {
-                exist_tool_results.merge(tool_results, output);
+                exist_tool_results.merge(tool_results, output, thinking);
+            }
+            None => {
+                self.tool_calls = Some(MessageContentToolCalls::new(tool_results, output, thinking))
             }
-            None => self.tool_calls = Some(MessageContentToolCalls::new(tool_results, output)),
         }
         self
     }
 
+    /// Moves the follow-ups of the chat to the model that answered, e.g. a fallback model.
+    pub fn set_model(&mut self, model: &Model) {
+        if self.role.model().id() != model.id() {
+            self.role.set_model(model);
+        }
+    }
+
     pub fn create_client(&self) -> Result<Box<dyn Client>> {
         init_client(&self.config, Some(self.role().model().clone()))
     }
@@ -239,6 +293,10 @@ impl Input {
         stream: bool,
     ) -> Result<ChatCompletionsData> {
         let mut messages = self.build_messages()?;
+        let response_format = self.response_format();
+        if let Some(schema) = &response_format {
+            patch_response_format(&mut messages, schema);
+        }
         patch_messages(&mut messages, model);
         model.guard_max_input_tokens(&messages)?;
         let (temperature, top_p) = (self.role().temperature(), self.role().top_p());
@@ -249,9 +307,11 @@ impl Input {
             top_p,
             functions,
             stream,
+            response_format,
+            reasoning_effort: self.role().reasoning_effort(),
         };
 
-        hooks::after_prepare_chat_completion_data(&mut data, &self);
+        hooks::after_prepare_chat_completion_data(&mut data, self);
         Ok(data)
     }
 
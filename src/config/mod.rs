@@ -1,31 +1,50 @@
 mod agent;
+mod chat_log;
+mod history;
 mod input;
 mod role;
 mod session;
+mod usage;
 
 pub use self::agent::{complete_agent_variables, list_agents, Agent, AgentVariables};
+pub use self::history::{render_history_hits, search_history, select_history_session};
 pub use self::input::Input;
 pub use self::role::{
     Role, RoleLike, CODE_ROLE, CREATE_TITLE_ROLE, EXPLAIN_SHELL_ROLE, SHELL_ROLE,
 };
 pub use self::session::Session;
+pub use self::usage::{BudgetConfig, Usage};
+
+use self::chat_log::{append_chat_log_record, load_chat_log_record, ChatLogRecord};
+use self::usage::{
+    append_usage_record, load_usage_records, render_usage_report, ApiKeyUsage, BudgetUsage,
+    UsageRecord,
+};
 
 use crate::client::{
-    create_client_config, list_client_types, list_models, ClientConfig, MessageContentToolCalls,
-    Model, ModelType, ProviderModels, OPENAI_COMPATIBLE_PROVIDERS,
+    create_client_config, init_client, list_client_types, list_models, load_ollama_models,
+    reset_all_models, CacheConfig, ChatCompletionsData, ChatCompletionsOutput, ClientConfig,
+    MessageContentToolCalls, Model, ModelGroup, ModelType, ProviderModels, ReasoningEffort,
+    ThinkingBlock, OPENAI_COMPATIBLE_PROVIDERS,
+};
+use crate::function::{
+    builtin_policy, match_tool_name, FunctionDeclaration, Functions, ToolCall, ToolPolicies,
+    ToolPolicy, ToolResult,
 };
-use crate::function::{FunctionDeclaration, Functions, ToolResult};
+use crate::mcp::McpServerConfig;
 use crate::rag::Rag;
-use crate::render::{MarkdownRender, RenderOptions};
+use crate::render::{thinking_summary, MarkdownRender, RenderOptions};
 use crate::repl::{run_repl_command, split_args_text};
+use crate::sandbox::SandboxConfig;
+use crate::serve::ServeApiKey;
 use crate::utils::*;
 
 use anyhow::{anyhow, bail, Context, Result};
 use indexmap::IndexMap;
 use inquire::{list_option::ListOption, validator::Validation, Confirm, MultiSelect, Select, Text};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use simplelog::LevelFilter;
 use std::collections::{HashMap, HashSet};
 use std::{
@@ -36,7 +55,8 @@ use std::{
     io::Write,
     path::{Path, PathBuf},
     process,
-    sync::{Arc, OnceLock},
+    sync::{Arc, LazyLock, OnceLock},
+    time::Duration,
 };
 use syntect::highlighting::ThemeSet;
 
@@ -53,12 +73,16 @@ const ROLES_DIR_NAME: &str = "roles";
 const MACROS_DIR_NAME: &str = "macros";
 const ENV_FILE_NAME: &str = ".env";
 const MESSAGES_FILE_NAME: &str = "messages.md";
+const USAGE_FILE_NAME: &str = "usage.jsonl";
+const CHAT_LOG_FILE_NAME: &str = "chat_log.jsonl";
+const MODEL_GROUPS_FILE_NAME: &str = "model_groups.json";
 const SESSIONS_DIR_NAME: &str = "sessions";
 const RAGS_DIR_NAME: &str = "rags";
 const FUNCTIONS_DIR_NAME: &str = "functions";
 const FUNCTIONS_FILE_NAME: &str = "functions.json";
 const FUNCTIONS_BIN_DIR_NAME: &str = "bin";
 const AGENTS_DIR_NAME: &str = "agents";
+const CACHE_DIR_NAME: &str = "cache";
 
 const CLIENTS_FIELD: &str = "clients";
 
@@ -70,6 +94,8 @@ const SYNC_MODELS_URL: &str =
 const SUMMARIZE_PROMPT: &str =
     "Summarize the discussion briefly in 200 words or less to use as a prompt for future context.";
 const SUMMARY_PROMPT: &str = "This is a summary of the chat history as a recap: ";
+const MERGE_SUMMARY_PROMPT: &str =
+    "If a previous summary is given, merge it into the new one so that no earlier details are lost.";
 
 const RAG_TEMPLATE: &str = r#"Answer the query based on the context while respecting the rules. (user query, some textual context and rules, all inside xml tags)
 
@@ -94,6 +120,8 @@ const LEFT_PROMPT: &str = "{color.green}{?session {?agent {agent}>}{session}{?ro
 const RIGHT_PROMPT: &str = "{color.purple}{?session {?consume_tokens {consume_tokens}({consume_percent}%)}{!consume_tokens {consume_tokens}}}{color.reset}";
 
 static EDITOR: OnceLock<Option<String>> = OnceLock::new();
+static API_KEY_USAGE: LazyLock<Mutex<Option<ApiKeyUsage>>> = LazyLock::new(Default::default);
+static BUDGET_USAGE: LazyLock<Mutex<Option<BudgetUsage>>> = LazyLock::new(Default::default);
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
@@ -103,10 +131,12 @@ pub struct Config {
     pub model_id: String,
     pub temperature: Option<f64>,
     pub top_p: Option<f64>,
+    pub reasoning_effort: Option<ReasoningEffort>,
 
     pub dry_run: bool,
     pub stream: bool,
     pub save: bool,
+    pub chat_log: bool,
     pub keybindings: String,
     pub editor: Option<String>,
     pub wrap: Option<String>,
@@ -115,6 +145,11 @@ pub struct Config {
     pub function_calling: bool,
     pub mapping_tools: IndexMap<String, String>,
     pub use_tools: Option<String>,
+    pub tool_call_concurrency: usize,
+    pub tool_policy: ToolPolicies,
+    pub tool_sandbox: IndexMap<String, SandboxConfig>,
+    pub tool_timeout: IndexMap<String, u64>,
+    pub mcp_servers: IndexMap<String, McpServerConfig>,
 
     pub repl_prelude: Option<String>,
     pub cmd_prelude: Option<String>,
@@ -122,9 +157,18 @@ pub struct Config {
 
     pub save_session: Option<bool>,
     pub compress_threshold: usize,
+    pub compress_headroom: f64,
+    pub compress_keep_turns: usize,
     pub summarize_prompt: Option<String>,
     pub summary_prompt: Option<String>,
 
+    pub budget: BudgetConfig,
+
+    pub retry: RetryConfig,
+    pub fallback_models: Vec<String>,
+    pub model_groups: IndexMap<String, ModelGroup>,
+    pub cache: CacheConfig,
+
     pub rag_embedding_model: Option<String>,
     pub rag_reranker_model: Option<String>,
     pub rag_top_k: usize,
@@ -136,11 +180,16 @@ pub struct Config {
     pub document_loaders: HashMap<String, String>,
 
     pub highlight: bool,
+    pub collapse_thinking: bool,
     pub light_theme: bool,
     pub left_prompt: Option<String>,
     pub right_prompt: Option<String>,
 
     pub serve_addr: Option<String>,
+    pub serve_api_keys: Vec<ServeApiKey>,
+    pub serve_cors_origins: Vec<String>,
+    pub serve_use_tools: Option<String>,
+    pub serve_sessions: bool,
     pub user_agent: Option<String>,
     pub save_shell_history: bool,
     pub sync_models_url: Option<String>,
@@ -153,6 +202,12 @@ pub struct Config {
     pub info_flag: bool,
     #[serde(skip)]
     pub agent_variables: Option<AgentVariables>,
+    #[serde(skip)]
+    pub tool_policy_decisions: HashMap<String, bool>,
+    #[serde(skip)]
+    pub response_format: Option<Value>,
+    #[serde(skip)]
+    pub serve_api_key: Option<String>,
 
     #[serde(skip)]
     pub model: Model,
@@ -179,10 +234,12 @@ impl Default for Config {
             model_id: Default::default(),
             temperature: None,
             top_p: None,
+            reasoning_effort: None,
 
             dry_run: false,
             stream: true,
             save: false,
+            chat_log: false,
             keybindings: "emacs".into(),
             editor: None,
             wrap: None,
@@ -191,6 +248,11 @@ impl Default for Config {
             function_calling: true,
             mapping_tools: Default::default(),
             use_tools: None,
+            tool_call_concurrency: 4,
+            tool_policy: Default::default(),
+            tool_sandbox: Default::default(),
+            tool_timeout: Default::default(),
+            mcp_servers: Default::default(),
 
             repl_prelude: None,
             cmd_prelude: None,
@@ -198,9 +260,18 @@ impl Default for Config {
 
             save_session: None,
             compress_threshold: 4000,
+            compress_headroom: 0.2,
+            compress_keep_turns: 2,
             summarize_prompt: None,
             summary_prompt: None,
 
+            budget: Default::default(),
+
+            retry: Default::default(),
+            fallback_models: vec![],
+            model_groups: Default::default(),
+            cache: Default::default(),
+
             rag_embedding_model: None,
             rag_reranker_model: None,
             rag_top_k: 5,
@@ -211,11 +282,16 @@ impl Default for Config {
             document_loaders: Default::default(),
 
             highlight: true,
+            collapse_thinking: true,
             light_theme: false,
             left_prompt: None,
             right_prompt: None,
 
             serve_addr: None,
+            serve_api_keys: vec![],
+            serve_cors_origins: vec![],
+            serve_use_tools: None,
+            serve_sessions: false,
             user_agent: None,
             save_shell_history: true,
             sync_models_url: None,
@@ -225,6 +301,9 @@ impl Default for Config {
             macro_flag: false,
             info_flag: false,
             agent_variables: None,
+            tool_policy_decisions: Default::default(),
+            response_format: None,
+            serve_api_key: None,
 
             model: Default::default(),
             functions: Default::default(),
@@ -264,6 +343,11 @@ impl Config {
         config.working_mode = working_mode;
         config.info_flag = info_flag;
 
+        // Only the daemon behind the configured model is asked at startup
+        let model_id = env::var(get_env_name("model")).unwrap_or_else(|_| config.model_id.clone());
+        let client_names = (!model_id.is_empty()).then(|| config.model_client_names(&model_id));
+        load_ollama_models(&mut config.clients, client_names.as_deref()).await;
+
         let setup = |config: &mut Self| -> Result<()> {
             config.load_envs();
 
@@ -346,6 +430,27 @@ impl Config {
         }
     }
 
+    pub fn usage_file() -> PathBuf {
+        match env::var(get_env_name("usage_file")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(USAGE_FILE_NAME),
+        }
+    }
+
+    pub fn chat_log_file() -> PathBuf {
+        match env::var(get_env_name("chat_log_file")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(CHAT_LOG_FILE_NAME),
+        }
+    }
+
+    pub fn model_groups_file() -> PathBuf {
+        match env::var(get_env_name("model_groups_file")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(MODEL_GROUPS_FILE_NAME),
+        }
+    }
+
     pub fn sessions_dir(&self) -> PathBuf {
         match &self.agent {
             None => match env::var(get_env_name("sessions_dir")) {
@@ -363,6 +468,13 @@ impl Config {
         }
     }
 
+    pub fn cache_dir() -> PathBuf {
+        match env::var(get_env_name("cache_dir")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(CACHE_DIR_NAME),
+        }
+    }
+
     pub fn functions_dir() -> PathBuf {
         match env::var(get_env_name("functions_dir")) {
             Ok(value) => PathBuf::from(value),
@@ -545,6 +657,9 @@ impl Config {
         if role.top_p().is_none() && self.top_p.is_some() {
             role.set_top_p(self.top_p);
         }
+        if role.reasoning_effort().is_none() && self.reasoning_effort.is_some() {
+            role.set_reasoning_effort(self.reasoning_effort);
+        }
         role
     }
 
@@ -588,6 +703,10 @@ impl Config {
             ("model", role.model().id()),
             ("temperature", format_option_value(&role.temperature())),
             ("top_p", format_option_value(&role.top_p())),
+            (
+                "reasoning_effort",
+                format_option_value(&role.reasoning_effort()),
+            ),
             ("use_tools", format_option_value(&role.use_tools())),
             (
                 "max_output_tokens",
@@ -598,6 +717,8 @@ impl Config {
             ),
             ("save_session", format_option_value(&self.save_session)),
             ("compress_threshold", self.compress_threshold.to_string()),
+            ("compress_headroom", self.compress_headroom.to_string()),
+            ("compress_keep_turns", self.compress_keep_turns.to_string()),
             (
                 "rag_reranker_model",
                 format_option_value(&rag_reranker_model),
@@ -605,12 +726,18 @@ impl Config {
             ("rag_top_k", rag_top_k.to_string()),
             ("dry_run", self.dry_run.to_string()),
             ("function_calling", self.function_calling.to_string()),
+            (
+                "tool_call_concurrency",
+                self.tool_call_concurrency.to_string(),
+            ),
             ("stream", self.stream.to_string()),
             ("save", self.save.to_string()),
+            ("chat_log", self.chat_log.to_string()),
             ("keybindings", self.keybindings.clone()),
             ("wrap", wrap),
             ("wrap_code", self.wrap_code.to_string()),
             ("highlight", self.highlight.to_string()),
+            ("collapse_thinking", self.collapse_thinking.to_string()),
             ("light_theme", self.light_theme.to_string()),
             ("config_file", display_path(&Self::config_file())),
             ("env_file", display_path(&Self::env_file())),
@@ -620,6 +747,7 @@ impl Config {
             ("macros_dir", display_path(&Self::macros_dir())),
             ("functions_dir", display_path(&Self::functions_dir())),
             ("messages_file", display_path(&self.messages_file())),
+            ("usage_file", display_path(&Self::usage_file())),
         ];
         if let Ok((_, Some(log_path))) = Self::log_config(self.working_mode.is_serve()) {
             items.push(("log_path", display_path(&log_path)));
@@ -648,6 +776,10 @@ impl Config {
                 let value = parse_value(value)?;
                 config.write().set_top_p(value);
             }
+            "reasoning_effort" => {
+                let value = parse_value(value)?;
+                config.write().set_reasoning_effort(value);
+            }
             "use_tools" => {
                 let value = parse_value(value)?;
                 config.write().set_use_tools(value);
@@ -664,6 +796,17 @@ impl Config {
                 let value = parse_value(value)?;
                 config.write().set_compress_threshold(value);
             }
+            "compress_headroom" => {
+                let value: f64 = value.parse().with_context(|| "Invalid value")?;
+                if !(0.0..1.0).contains(&value) {
+                    bail!("Invalid value, expected a number between 0 and 1");
+                }
+                config.write().compress_headroom = value;
+            }
+            "compress_keep_turns" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().compress_keep_turns = value;
+            }
             "rag_reranker_model" => {
                 let value = parse_value(value)?;
                 Self::set_rag_reranker_model(config, value)?;
@@ -683,6 +826,10 @@ impl Config {
                 }
                 config.write().function_calling = value;
             }
+            "tool_call_concurrency" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().tool_call_concurrency = value;
+            }
             "stream" => {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 config.write().stream = value;
@@ -691,10 +838,18 @@ impl Config {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 config.write().save = value;
             }
+            "chat_log" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().chat_log = value;
+            }
             "highlight" => {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 config.write().highlight = value;
             }
+            "collapse_thinking" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().collapse_thinking = value;
+            }
             _ => bail!("Unknown key '{key}'"),
         }
         Ok(())
@@ -783,6 +938,13 @@ impl Config {
         }
     }
 
+    pub fn set_reasoning_effort(&mut self, value: Option<ReasoningEffort>) {
+        match self.role_like_mut() {
+            Some(role_like) => role_like.set_reasoning_effort(value),
+            None => self.reasoning_effort = value,
+        }
+    }
+
     pub fn set_use_tools(&mut self, value: Option<String>) {
         match self.role_like_mut() {
             Some(role_like) => role_like.set_use_tools(value),
@@ -865,6 +1027,32 @@ impl Config {
         Ok(())
     }
 
+    /// Lists the models of the Ollama clients when a model of theirs is listed or selected,
+    /// all of them when `model_id` is None.
+    pub async fn load_ollama_models(config: &GlobalConfig, model_id: Option<&str>) {
+        let client_names = model_id.map(|v| config.read().model_client_names(v));
+        let mut clients = config.read().clients.clone();
+        if load_ollama_models(&mut clients, client_names.as_deref()).await {
+            config.write().clients = clients;
+            reset_all_models();
+        }
+    }
+
+    fn model_client_names(&self, model_id: &str) -> Vec<String> {
+        let model_ids: Vec<&str> = match self.model_groups.get(model_id) {
+            Some(group) => group.models.iter().map(|v| v.id()).collect(),
+            None => vec![model_id],
+        };
+        model_ids
+            .into_iter()
+            .map(|v| v.split_once(':').map(|(v, _)| v).unwrap_or(v).to_string())
+            .collect()
+    }
+
+    pub fn list_model_groups(&self) -> Vec<(&String, &ModelGroup)> {
+        self.model_groups.iter().collect()
+    }
+
     pub fn use_prompt(&mut self, prompt: &str) -> Result<()> {
         let mut role = Role::new(TEMP_ROLE_NAME, prompt);
         role.set_model(self.current_model());
@@ -889,6 +1077,215 @@ impl Config {
         Ok(())
     }
 
+    pub fn usage_info(&self) -> Result<String> {
+        let records = load_usage_records(&Self::usage_file())?;
+        Ok(render_usage_report(&records))
+    }
+
+    pub fn record_usage(&mut self, input: Option<&Input>, model: &Model, usage: Usage) {
+        if usage.is_empty() {
+            return;
+        }
+        let record = UsageRecord {
+            time: now(),
+            model: model.id(),
+            role: input.and_then(|v| convert_option_string(v.role().name())),
+            session: input
+                .and_then(|v| v.session(&self.session))
+                .map(|v| v.name().to_string()),
+            agent: self.agent.as_ref().map(|v| v.name().to_string()),
+            api_key: self.serve_api_key.clone(),
+            usage,
+        };
+        // Held while appending, so a reload of the totals can't count the record twice
+        let mut api_key_usage = API_KEY_USAGE.lock();
+        let mut budget_usage = BUDGET_USAGE.lock();
+        if let Err(err) = append_usage_record(&Self::usage_file(), &record) {
+            warn!("{err:#}");
+        }
+        if let (Some(name), Some(totals)) = (&record.api_key, api_key_usage.as_mut()) {
+            if totals.is_current(record.day()) {
+                totals.add(name, &usage);
+            }
+        }
+        if let Some(totals) = budget_usage.as_mut() {
+            totals.add(&record);
+        }
+        drop(budget_usage);
+        drop(api_key_usage);
+        if let Some(session) = input.and_then(|v| v.session_mut(&mut self.session)) {
+            session.add_usage(&usage);
+        }
+    }
+
+    pub fn log_chat(
+        &self,
+        input: Option<&Input>,
+        model: &Model,
+        data: &ChatCompletionsData,
+        output: &ChatCompletionsOutput,
+        usage: Usage,
+        latency: Duration,
+    ) {
+        let record = ChatLogRecord {
+            usage,
+            latency_ms: latency.as_millis() as u64,
+            ..self.chat_log_record(input, model, data, output)
+        };
+        self.append_chat_log(&record);
+    }
+
+    pub fn log_cached_chat(
+        &self,
+        input: Option<&Input>,
+        model: &Model,
+        data: &ChatCompletionsData,
+        output: &ChatCompletionsOutput,
+    ) {
+        let record = ChatLogRecord {
+            cached: true,
+            ..self.chat_log_record(input, model, data, output)
+        };
+        self.append_chat_log(&record);
+    }
+
+    fn chat_log_record(
+        &self,
+        input: Option<&Input>,
+        model: &Model,
+        data: &ChatCompletionsData,
+        output: &ChatCompletionsOutput,
+    ) -> ChatLogRecord {
+        ChatLogRecord {
+            time: now(),
+            model: model.id(),
+            role: input.and_then(|v| convert_option_string(v.role().name())),
+            session: input
+                .and_then(|v| v.session(&self.session))
+                .map(|v| v.name().to_string()),
+            rag: input.and_then(|v| v.rag_name().map(|v| v.to_string())),
+            agent: self.agent.as_ref().map(|v| v.name().to_string()),
+            temperature: data.temperature,
+            top_p: data.top_p,
+            functions: data.functions.clone(),
+            response_format: data.response_format.clone(),
+            reasoning_effort: data.reasoning_effort,
+            messages: data.messages.clone(),
+            output: output.text.clone(),
+            tool_calls: output.tool_calls.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn append_chat_log(&self, record: &ChatLogRecord) {
+        if let Err(err) = append_chat_log_record(&Self::chat_log_file(), record) {
+            warn!("{err:#}");
+        }
+    }
+
+    pub async fn replay_chat(
+        config: &GlobalConfig,
+        id: &str,
+        abort_signal: AbortSignal,
+    ) -> Result<()> {
+        let record = load_chat_log_record(&Self::chat_log_file(), id)?;
+        let client = init_client(config, None)?;
+        let data = ChatCompletionsData {
+            messages: record.messages.clone(),
+            temperature: record.temperature,
+            top_p: record.top_p,
+            functions: record.functions.clone(),
+            stream: false,
+            response_format: record.response_format.clone(),
+            reasoning_effort: record.reasoning_effort,
+        };
+        let (output, usage, latency) = abortable_run_with_spinner(
+            client.replay_chat_completions(data),
+            "Replaying",
+            abort_signal,
+        )
+        .await?;
+        println!(
+            "{}\n\n{}",
+            render_replay_output(
+                &record.model,
+                record.latency_ms,
+                &record.usage,
+                &record.output,
+                &record.tool_calls
+            ),
+            render_replay_output(
+                &client.model().id(),
+                latency.as_millis() as u64,
+                &usage,
+                &output.text,
+                &output.tool_calls
+            ),
+        );
+        Ok(())
+    }
+
+    /// Estimates the request from its input tokens and `max_output_tokens` if it's sent.
+    pub fn check_budget(
+        &self,
+        model: &Model,
+        session_usage: Option<&Usage>,
+        input_tokens: usize,
+    ) -> Option<String> {
+        if self.budget.is_empty() {
+            return None;
+        }
+        let output_tokens = model.max_tokens_param().unwrap_or_default().max(0) as u64;
+        let estimate = Usage::new(model, input_tokens as u64, output_tokens);
+        let mut checks = vec![("request", self.budget.request, Usage::default())];
+        if let Some(usage) = session_usage {
+            checks.push(("session", self.budget.session, *usage));
+        }
+        if self.budget.daily != Default::default() || self.budget.monthly != Default::default() {
+            let now = now();
+            let today = &now[..10];
+            let mut budget_usage = BUDGET_USAGE.lock();
+            if !budget_usage.as_ref().is_some_and(|v| v.is_current(today)) {
+                let records = match load_usage_records(&Self::usage_file()) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        warn!("{err:#}");
+                        vec![]
+                    }
+                };
+                *budget_usage = Some(BudgetUsage::load(&records, today));
+            }
+            if let Some(totals) = budget_usage.as_ref() {
+                checks.push(("daily", self.budget.daily, totals.daily()));
+                checks.push(("monthly", self.budget.monthly, totals.monthly()));
+            }
+        }
+        checks
+            .into_iter()
+            .find_map(|(name, limit, spent)| limit.check(name, &spent, &estimate))
+    }
+
+    /// Today's usage of an API key of the server.
+    pub fn serve_api_key_usage(name: &str) -> Usage {
+        let now = now();
+        let today = &now[..10];
+        let mut api_key_usage = API_KEY_USAGE.lock();
+        if !api_key_usage.as_ref().is_some_and(|v| v.is_current(today)) {
+            let records = match load_usage_records(&Self::usage_file()) {
+                Ok(v) => v,
+                Err(err) => {
+                    warn!("{err:#}");
+                    vec![]
+                }
+            };
+            *api_key_usage = Some(ApiKeyUsage::load(&records, today));
+        }
+        api_key_usage
+            .as_ref()
+            .map(|v| v.get(name))
+            .unwrap_or_default()
+    }
+
     pub fn role_info(&self) -> Result<String> {
         if let Some(session) = &self.session {
             if session.role_name().is_some() {
@@ -919,7 +1316,7 @@ impl Config {
         let mut role = if names.contains(&name.to_string()) {
             let path = Self::role_file(name);
             let content = read_to_string(&path)?;
-            Role::new(name, &content)
+            Role::load(name, &content)?
         } else {
             Role::builtin(name)?
         };
@@ -1027,8 +1424,12 @@ impl Config {
         let names = Self::list_roles(false);
         for name in names {
             if let Ok(content) = read_to_string(Self::role_file(&name)) {
-                let role = Role::new(&name, &content);
-                roles.insert(name, role);
+                match Role::load(&name, &content) {
+                    Ok(role) => {
+                        roles.insert(name, role);
+                    }
+                    Err(err) => warn!("{err:#}"),
+                }
             }
         }
         let mut roles: Vec<_> = roles.into_values().collect();
@@ -1114,6 +1515,7 @@ impl Config {
             }
         }
         self.session = session;
+        self.tool_policy_decisions.clear();
         self.init_agent_session_variables(new_session)?;
         Ok(())
     }
@@ -1141,6 +1543,7 @@ impl Config {
         if let Some(mut session) = self.session.take() {
             let sessions_dir = self.sessions_dir();
             session.exit(&sessions_dir, self.working_mode.is_repl())?;
+            self.tool_policy_decisions.clear();
             self.discontinuous_last_message();
         }
         Ok(())
@@ -1183,6 +1586,77 @@ impl Config {
         Ok(())
     }
 
+    pub fn edit_session_message(config: &GlobalConfig, number: Option<&str>) -> Result<Input> {
+        let (turns, editor) = {
+            let config = config.read();
+            match &config.session {
+                Some(session) => (session.user_turns(), config.editor()?),
+                None => bail!("No session"),
+            }
+        };
+        if turns.is_empty() {
+            bail!("No messages to edit in the session");
+        }
+        let turn = match number {
+            Some(value) => parse_turn_number(value, turns.len())?,
+            None => {
+                let options: Vec<String> = turns
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (_, text))| {
+                        let line = text.lines().next().unwrap_or_default();
+                        let mut line: String = line.chars().take(72).collect();
+                        if line.len() < text.len() {
+                            line.push_str("...");
+                        }
+                        format!("{}. {line}", i + 1)
+                    })
+                    .collect();
+                Select::new("Select a message to edit:", options)
+                    .with_starting_cursor(turns.len() - 1)
+                    .raw_prompt()?
+                    .index
+            }
+        };
+        let (index, text) = turns[turn].clone();
+
+        let temp_file = temp_file("-message", ".md");
+        std::fs::write(&temp_file, &text)
+            .with_context(|| format!("Failed to write to '{}'", temp_file.display()))?;
+        edit_file(&editor, &temp_file)?;
+        let new_text = read_to_string(&temp_file)
+            .with_context(|| format!("Failed to read '{}'", temp_file.display()))?;
+        let _ = remove_file(&temp_file);
+        let new_text = new_text.trim_end();
+        if new_text.trim().is_empty() {
+            bail!("Empty message, nothing to resend");
+        }
+
+        let (medias, data_urls) = match &config.read().session {
+            Some(session) => session.message_medias(index),
+            None => bail!("No session"),
+        };
+        let mut input = Input::from_str(config, new_text, None);
+        input.set_edit_at(index, medias, data_urls);
+        Ok(input)
+    }
+
+    pub fn pin_session_message(&mut self, number: Option<&str>, pinned: bool) -> Result<()> {
+        let session = match self.session.as_mut() {
+            Some(v) => v,
+            None => bail!("No session"),
+        };
+        let turns = session.user_turns();
+        if turns.is_empty() {
+            bail!("No messages to pin in the session");
+        }
+        let turn = match number {
+            Some(value) => parse_turn_number(value, turns.len())?,
+            None => turns.len() - 1,
+        };
+        session.set_pinned(turns[turn].0, pinned)
+    }
+
     pub fn empty_session(&mut self) -> Result<()> {
         if let Some(session) = self.session.as_mut() {
             if let Some(agent) = self.agent.as_ref() {
@@ -1196,6 +1670,48 @@ impl Config {
         Ok(())
     }
 
+    pub fn list_session_branches(&self) -> Result<String> {
+        match &self.session {
+            Some(session) => Ok(session.list_branches()),
+            None => bail!("No session"),
+        }
+    }
+
+    pub fn switch_session_branch(&mut self, name: &str) -> Result<()> {
+        match self.session.as_mut() {
+            Some(session) => session.switch_branch(name)?,
+            None => bail!("No session"),
+        }
+        self.discontinuous_last_message();
+        Ok(())
+    }
+
+    pub fn diff_session_branches(&self, name1: &str, name2: Option<&str>) -> Result<String> {
+        match &self.session {
+            Some(session) => {
+                session.diff_branches(name1, name2.unwrap_or_else(|| session.branch_name()))
+            }
+            None => bail!("No session"),
+        }
+    }
+
+    pub fn fork_session_branch(&self, session_name: &str) -> Result<()> {
+        let session = match &self.session {
+            Some(session) => session,
+            None => bail!("No session"),
+        };
+        if session_name == TEMP_SESSION_NAME || session_name == session.name() {
+            bail!("Invalid session name '{session_name}'");
+        }
+        let session_path = self.session_file(session_name);
+        if session_path.exists() {
+            bail!("Session '{session_name}' already exists");
+        }
+        let mut new_session = session.extract_branch(session.branch_name())?;
+        new_session.save(session_name, &session_path, self.working_mode.is_repl())?;
+        Ok(())
+    }
+
     pub fn set_save_session_this_time(&mut self) -> Result<()> {
         if let Some(session) = self.session.as_mut() {
             session.set_save_session_this_time();
@@ -1218,8 +1734,11 @@ impl Config {
         {
             let mut config = config.write();
             let compress_threshold = config.compress_threshold;
+            let compress_headroom = config.compress_headroom;
+            let compress_keep_turns = config.compress_keep_turns;
             if let Some(session) = config.session.as_mut() {
-                if session.need_compress(compress_threshold) {
+                if session.need_compress(compress_threshold, compress_headroom, compress_keep_turns)
+                {
                     session.set_compressing(true);
                     need_compress = true;
                 }
@@ -1248,21 +1767,29 @@ impl Config {
     }
 
     pub async fn compress_session(config: &GlobalConfig) -> Result<()> {
-        match config.read().session.as_ref() {
-            Some(session) => {
-                if !session.has_user_messages() {
-                    bail!("No need to compress since there are no messages in the session")
-                }
-            }
+        let keep_turns = config.read().compress_keep_turns;
+        let (history, model) = match config.read().session.as_ref() {
+            Some(session) if !session.has_user_messages() => {
+                bail!("No need to compress since there are no messages in the session")
+            }
+            Some(session) => match session.compression_history(keep_turns) {
+                Some(history) => (history, session.model().clone()),
+                None => bail!(
+                    "No need to compress since there are no older unpinned messages in the session"
+                ),
+            },
             None => bail!("No session"),
-        }
+        };
 
         let prompt = config
             .read()
             .summarize_prompt
             .clone()
             .unwrap_or_else(|| SUMMARIZE_PROMPT.into());
-        let input = Input::from_str(config, &prompt, None);
+        let mut role = Role::default();
+        role.set_model(&model);
+        let text = format!("{history}\n\n{prompt} {MERGE_SUMMARY_PROMPT}");
+        let input = Input::from_str(config, &text, Some(role));
         let summary = input.fetch_chat_text().await?;
         let summary_prompt = config
             .read()
@@ -1270,7 +1797,7 @@ impl Config {
             .clone()
             .unwrap_or_else(|| SUMMARY_PROMPT.into());
         if let Some(session) = config.write().session.as_mut() {
-            session.compress(format!("{}{}", summary_prompt, summary));
+            session.compress(format!("{}{}", summary_prompt, summary), keep_turns);
         }
         config.write().discontinuous_last_message();
         Ok(())
@@ -1550,6 +2077,7 @@ impl Config {
         self.exit_session()?;
         if self.agent.take().is_some() {
             self.rag.take();
+            self.tool_policy_decisions.clear();
             self.discontinuous_last_message();
         }
         Ok(())
@@ -1641,34 +2169,51 @@ impl Config {
         Ok(())
     }
 
+    /// The agent or role policy is consulted before the global one, tools are allowed by default.
+    pub fn tool_policy(&self, name: &str) -> ToolPolicy {
+        if let Some(allowed) = self.tool_policy_decisions.get(name) {
+            return match allowed {
+                true => ToolPolicy::Allow,
+                false => ToolPolicy::Deny,
+            };
+        }
+        let scoped_policy = if let Some(agent) = &self.agent {
+            Some(agent.tool_policy())
+        } else if let Some(session) = &self.session {
+            Some(session.role_tool_policy())
+        } else {
+            self.role.as_ref().map(|v| v.tool_policy())
+        };
+        scoped_policy
+            .and_then(|v| match_tool_name(v, name))
+            .or_else(|| match_tool_name(&self.tool_policy, name))
+            .copied()
+            .or_else(|| {
+                self.functions
+                    .find(name)
+                    .filter(|v| v.builtin)
+                    .and_then(|_| builtin_policy(name))
+            })
+            .unwrap_or(ToolPolicy::Allow)
+    }
+
+    /// The agent sandbox covers all its tools, otherwise look up the tool name or glob.
+    pub fn tool_sandbox(&self, name: &str) -> Option<SandboxConfig> {
+        if let Some(sandbox) = self.agent.as_ref().and_then(|v| v.sandbox()) {
+            return Some(sandbox.clone());
+        }
+        match_tool_name(&self.tool_sandbox, name).cloned()
+    }
+
+    pub fn tool_timeout(&self, name: &str) -> Option<u64> {
+        match_tool_name(&self.tool_timeout, name).copied()
+    }
+
     pub fn select_functions(&self, role: &Role) -> Option<Vec<FunctionDeclaration>> {
         let mut functions = vec![];
         if self.function_calling {
             if let Some(use_tools) = role.use_tools() {
-                let mut tool_names: HashSet<String> = Default::default();
-                let declaration_names: HashSet<String> = self
-                    .functions
-                    .declarations()
-                    .iter()
-                    .map(|v| v.name.to_string())
-                    .collect();
-                if use_tools == "all" {
-                    tool_names.extend(declaration_names);
-                } else {
-                    for item in use_tools.split(',') {
-                        let item = item.trim();
-                        if let Some(values) = self.mapping_tools.get(item) {
-                            tool_names.extend(
-                                values
-                                    .split(',')
-                                    .map(|v| v.to_string())
-                                    .filter(|v| declaration_names.contains(v)),
-                            )
-                        } else if declaration_names.contains(item) {
-                            tool_names.insert(item.to_string());
-                        }
-                    }
-                }
+                let tool_names = self.functions.select_names(&use_tools, &self.mapping_tools);
                 functions = self
                     .functions
                     .declarations()
@@ -1738,9 +2283,15 @@ impl Config {
         if args.len() == 1 {
             values = match cmd {
                 ".role" => map_completion_values(Self::list_roles(true)),
-                ".model" => list_models(self, ModelType::Chat)
+                ".model" => self
+                    .list_model_groups()
                     .into_iter()
-                    .map(|v| (v.id(), Some(v.description())))
+                    .map(|(name, group)| (name.clone(), Some(group.description())))
+                    .chain(
+                        list_models(self, ModelType::Chat)
+                            .into_iter()
+                            .map(|v| (v.id(), Some(v.description()))),
+                    )
                     .collect(),
                 ".session" => {
                     if args[0].starts_with("_/") {
@@ -1771,17 +2322,23 @@ impl Config {
                     let mut values = vec![
                         "temperature",
                         "top_p",
+                        "reasoning_effort",
                         "use_tools",
                         "save_session",
                         "compress_threshold",
+                        "compress_headroom",
+                        "compress_keep_turns",
                         "rag_reranker_model",
                         "rag_top_k",
                         "max_output_tokens",
                         "dry_run",
                         "function_calling",
+                        "tool_call_concurrency",
                         "stream",
                         "save",
+                        "chat_log",
                         "highlight",
+                        "collapse_thinking",
                     ];
                     values.sort_unstable();
                     values
@@ -1792,8 +2349,13 @@ impl Config {
                 ".delete" => {
                     map_completion_values(vec!["role", "session", "rag", "macro", "agent-data"])
                 }
+                ".branch" => map_completion_values(vec!["list", "switch", "diff", "fork"]),
                 _ => vec![],
             };
+        } else if cmd == ".branch" && matches!(args[0], "switch" | "diff") && args.len() <= 3 {
+            if let Some(session) = &self.session {
+                values = map_completion_values(session.branch_names());
+            }
         } else if cmd == ".set" && args.len() == 2 {
             let candidates = match args[0] {
                 "max_output_tokens" => match self.current_model().max_output_tokens() {
@@ -1803,6 +2365,7 @@ impl Config {
                 "dry_run" => complete_bool(self.dry_run),
                 "stream" => complete_bool(self.stream),
                 "save" => complete_bool(self.save),
+                "chat_log" => complete_bool(self.chat_log),
                 "function_calling" => complete_bool(self.function_calling),
                 "use_tools" => {
                     let mut prefix = String::new();
@@ -1816,6 +2379,7 @@ impl Config {
                         values.push("all".to_string());
                     }
                     values.extend(self.functions.declarations().iter().map(|v| v.name.clone()));
+                    values.extend(self.mcp_servers.keys().map(|v| format!("{v}:*")));
                     values.extend(self.mapping_tools.keys().map(|v| v.to_string()));
                     values
                         .into_iter()
@@ -1836,6 +2400,11 @@ impl Config {
                     .map(|v| v.id())
                     .collect(),
                 "highlight" => complete_bool(self.highlight),
+                "collapse_thinking" => complete_bool(self.collapse_thinking),
+                "reasoning_effort" => ["low", "medium", "high", "null"]
+                    .into_iter()
+                    .map(|v| v.to_string())
+                    .collect(),
                 _ => vec![],
             };
             values = candidates.into_iter().map(|v| (v, None)).collect();
@@ -1940,6 +2509,25 @@ impl Config {
         render_prompt(right_prompt, &variables)
     }
 
+    pub fn print_thinking(&self, thinking: &[ThinkingBlock]) {
+        let text = thinking
+            .iter()
+            .map(|v| v.text.trim())
+            .filter(|v| !v.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        if text.is_empty() {
+            return;
+        }
+        if !*IS_STDOUT_TERMINAL {
+            println!("<think>\n{text}\n</think>\n");
+        } else if self.collapse_thinking {
+            println!("{}\n", dimmed_text(&thinking_summary(&text)));
+        } else {
+            println!("{}\n", dimmed_text(&text));
+        }
+    }
+
     pub fn print_markdown(&self, text: &str) -> Result<()> {
         if *IS_STDOUT_TERMINAL {
             let render_options = self.render_options()?;
@@ -2115,7 +2703,7 @@ impl Config {
             .with_context(|| "Failed to save message")
     }
 
-    fn init_agent_shared_variables(&mut self) -> Result<()> {
+    pub fn init_agent_shared_variables(&mut self) -> Result<()> {
         let agent = match self.agent.as_mut() {
             Some(v) => v,
             None => return Ok(()),
@@ -2128,7 +2716,7 @@ impl Config {
             let new_variables = Agent::init_agent_variables(
                 agent.defined_variables(),
                 &config_variables,
-                self.info_flag,
+                self.info_flag || self.working_mode.is_serve(),
             )?;
             agent.set_shared_variables(new_variables);
         }
@@ -2243,6 +2831,9 @@ impl Config {
         if let Some(v) = read_env_value::<f64>(&get_env_name("top_p")) {
             self.top_p = v;
         }
+        if let Some(v) = read_env_value::<ReasoningEffort>(&get_env_name("reasoning_effort")) {
+            self.reasoning_effort = v;
+        }
 
         if let Some(Some(v)) = read_env_bool(&get_env_name("dry_run")) {
             self.dry_run = v;
@@ -2253,6 +2844,9 @@ impl Config {
         if let Some(Some(v)) = read_env_bool(&get_env_name("save")) {
             self.save = v;
         }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("chat_log")) {
+            self.chat_log = v;
+        }
         if let Ok(v) = env::var(get_env_name("keybindings")) {
             if v == "vi" {
                 self.keybindings = v;
@@ -2279,6 +2873,29 @@ impl Config {
         if let Some(v) = read_env_value::<String>(&get_env_name("use_tools")) {
             self.use_tools = v;
         }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("tool_call_concurrency")) {
+            self.tool_call_concurrency = v;
+        }
+        if let Ok(v) = env::var(get_env_name("tool_policy")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.tool_policy = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("tool_sandbox")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.tool_sandbox = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("tool_timeout")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.tool_timeout = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("mcp_servers")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.mcp_servers = v;
+            }
+        }
 
         if let Some(v) = read_env_value::<String>(&get_env_name("repl_prelude")) {
             self.repl_prelude = v;
@@ -2296,6 +2913,12 @@ impl Config {
         if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("compress_threshold")) {
             self.compress_threshold = v;
         }
+        if let Some(Some(v)) = read_env_value::<f64>(&get_env_name("compress_headroom")) {
+            self.compress_headroom = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("compress_keep_turns")) {
+            self.compress_keep_turns = v;
+        }
         if let Some(v) = read_env_value::<String>(&get_env_name("summarize_prompt")) {
             self.summarize_prompt = v;
         }
@@ -2303,6 +2926,35 @@ impl Config {
             self.summary_prompt = v;
         }
 
+        if let Ok(v) = env::var(get_env_name("budget")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.budget = v;
+            }
+        }
+
+        if let Ok(v) = env::var(get_env_name("retry")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.retry = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("fallback_models")) {
+            self.fallback_models = v
+                .split(',')
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect();
+        }
+        if let Ok(v) = env::var(get_env_name("model_groups")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.model_groups = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("cache")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.cache = v;
+            }
+        }
+
         if let Some(v) = read_env_value::<String>(&get_env_name("rag_embedding_model")) {
             self.rag_embedding_model = v;
         }
@@ -2334,6 +2986,9 @@ impl Config {
         if *NO_COLOR {
             self.highlight = false;
         }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("collapse_thinking")) {
+            self.collapse_thinking = v;
+        }
         if let Some(Some(v)) = read_env_bool(&get_env_name("light_theme")) {
             self.light_theme = v;
         } else if !self.light_theme {
@@ -2353,6 +3008,24 @@ impl Config {
         if let Some(v) = read_env_value::<String>(&get_env_name("serve_addr")) {
             self.serve_addr = v;
         }
+        if let Ok(v) = env::var(get_env_name("serve_api_keys")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.serve_api_keys = v;
+            }
+        }
+        if let Ok(v) = env::var(get_env_name("serve_cors_origins")) {
+            self.serve_cors_origins = v
+                .split(',')
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect();
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("serve_use_tools")) {
+            self.serve_use_tools = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("serve_sessions")) {
+            self.serve_sessions = v;
+        }
         if let Some(v) = read_env_value::<String>(&get_env_name("user_agent")) {
             self.user_agent = v;
         }
@@ -2366,9 +3039,34 @@ impl Config {
 
     fn load_functions(&mut self) -> Result<()> {
         self.functions = Functions::init(&Self::functions_file())?;
+        self.functions.register_builtins();
         Ok(())
     }
 
+    /// Connects to the MCP servers that `use_tools` refers to, the first time they are used.
+    pub async fn load_mcp_servers(config: &GlobalConfig, use_tools: Option<&str>) {
+        let Some(use_tools) = use_tools else {
+            return;
+        };
+        let (servers, mut functions) = {
+            let config = config.read();
+            if !config.function_calling {
+                return;
+            }
+            let servers = config.functions.pending_mcp_servers(
+                use_tools,
+                &config.mapping_tools,
+                &config.mcp_servers,
+            );
+            (servers, config.functions.clone())
+        };
+        if servers.is_empty() {
+            return;
+        }
+        functions.load_mcp_servers(&servers).await;
+        config.write().functions = functions;
+    }
+
     fn setup_model(&mut self) -> Result<()> {
         let mut model_id = self.model_id.clone();
         if model_id.is_empty() {
@@ -2447,7 +3145,7 @@ pub async fn macro_execute(
     name: &str,
     args: Option<&str>,
     abort_signal: AbortSignal,
-) -> Result<()> {
+) -> Result<String> {
     let macro_value = Config::load_macro(name)?;
     let (mut new_args, text) = split_args_text(args.unwrap_or_default(), cfg!(windows));
     if !text.is_empty() {
@@ -2460,6 +3158,7 @@ pub async fn macro_execute(
     let mut config = config.read().clone();
     config.temperature = role.temperature();
     config.top_p = role.top_p();
+    config.reasoning_effort = role.reasoning_effort();
     config.use_tools = role.use_tools().clone();
     config.macro_flag = true;
     config.model = role.model().clone();
@@ -2475,7 +3174,13 @@ pub async fn macro_execute(
         println!(">> {}", multiline_text(&command));
         run_repl_command(&config, abort_signal.clone(), &command).await?;
     }
-    Ok(())
+    let output = config
+        .read()
+        .last_message
+        .as_ref()
+        .map(|v| v.output.clone())
+        .unwrap_or_default();
+    Ok(output)
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -2541,6 +3246,27 @@ pub struct MacroVariable {
     pub default: Option<String>,
 }
 
+/// Retries of requests that fail with 429, 5xx or a network error.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    pub max_retries: usize,
+    /// Seconds before the first retry, doubled after each retry
+    pub initial_delay: u64,
+    /// Longest wait in seconds, a longer `Retry-After` moves on to the fallback models
+    pub max_delay: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            initial_delay: 1,
+            max_delay: 30,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelsOverride {
     pub version: String,
@@ -2688,6 +3414,34 @@ fn read_env_bool(key: &str) -> Option<Option<bool>> {
     Some(parse_bool(&value))
 }
 
+fn render_replay_output(
+    model: &str,
+    latency_ms: u64,
+    usage: &Usage,
+    text: &str,
+    tool_calls: &[ToolCall],
+) -> String {
+    let mut lines = vec![dimmed_text(&format!(
+        "--- {model} · {:.2}s · {}",
+        latency_ms as f64 / 1000.0,
+        usage.summary()
+    ))];
+    if !text.is_empty() {
+        lines.push(text.to_string());
+    }
+    for call in tool_calls {
+        lines.push(format!("<tool_call {}({})>", call.name, call.arguments));
+    }
+    lines.join("\n")
+}
+
+fn parse_turn_number(value: &str, len: usize) -> Result<usize> {
+    match value.parse::<usize>() {
+        Ok(n) if (1..=len).contains(&n) => Ok(n - 1),
+        _ => bail!("Invalid message number '{value}', expected 1 to {len}"),
+    }
+}
+
 fn complete_bool(value: bool) -> Vec<String> {
     vec![(!value).to_string()]
 }
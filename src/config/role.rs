@@ -1,12 +1,12 @@
 use super::*;
 
-use crate::client::{Message, MessageContent, MessageRole, Model};
+use crate::client::{Message, MessageContent, MessageRole, Model, ReasoningEffort};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use fancy_regex::Regex;
 use rust_embed::Embed;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::sync::LazyLock;
 
 pub const SHELL_ROLE: &str = "%shell%";
@@ -29,10 +29,12 @@ pub trait RoleLike {
     fn model_mut(&mut self) -> &mut Model;
     fn temperature(&self) -> Option<f64>;
     fn top_p(&self) -> Option<f64>;
+    fn reasoning_effort(&self) -> Option<ReasoningEffort>;
     fn use_tools(&self) -> Option<String>;
     fn set_model(&mut self, model: &Model);
     fn set_temperature(&mut self, value: Option<f64>);
     fn set_top_p(&mut self, value: Option<f64>);
+    fn set_reasoning_effort(&mut self, value: Option<ReasoningEffort>);
     fn set_use_tools(&mut self, value: Option<String>);
 }
 
@@ -51,7 +53,15 @@ pub struct Role {
     #[serde(skip_serializing_if = "Option::is_none")]
     top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_effort: Option<ReasoningEffort>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     use_tools: Option<String>,
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    tool_policy: ToolPolicies,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fallback_models: Vec<String>,
 
     #[serde(skip)]
     model: Model,
@@ -59,6 +69,15 @@ pub struct Role {
 
 impl Role {
     pub fn new(name: &str, content: &str) -> Self {
+        Self::parse(name, content, false).unwrap_or_default()
+    }
+
+    /// Loads a role file, where an invalid `tool_policy` or `reasoning_effort` is an error.
+    pub fn load(name: &str, content: &str) -> Result<Self> {
+        Self::parse(name, content, true)
+    }
+
+    fn parse(name: &str, content: &str, strict: bool) -> Result<Self> {
         let mut metadata = "";
         let mut prompt = content.trim();
         if let Ok(Some(caps)) = RE_METADATA.captures(content) {
@@ -82,14 +101,42 @@ impl Role {
                             "model" => role.model_id = value.as_str().map(|v| v.to_string()),
                             "temperature" => role.temperature = value.as_f64(),
                             "top_p" => role.top_p = value.as_f64(),
+                            "reasoning_effort" => match serde_json::from_value(value.clone()) {
+                                Ok(value) => role.reasoning_effort = value,
+                                Err(err) if strict => {
+                                    bail!("Invalid reasoning_effort in role '{name}': {err}")
+                                }
+                                Err(err) => {
+                                    warn!("Invalid reasoning_effort in role '{name}': {err}")
+                                }
+                            },
                             "use_tools" => role.use_tools = value.as_str().map(|v| v.to_string()),
+                            "tool_policy" => match serde_json::from_value(value.clone()) {
+                                Ok(value) => role.tool_policy = value,
+                                Err(err) if strict => {
+                                    bail!("Invalid tool_policy in role '{name}': {err}")
+                                }
+                                Err(err) => {
+                                    // Deny all tools rather than dropping the policy
+                                    warn!("Invalid tool_policy in role '{name}': {err}");
+                                    role.tool_policy =
+                                        IndexMap::from([("*".into(), ToolPolicy::Deny)]);
+                                }
+                            },
+                            "response_format" => {
+                                role.response_format = Some(value.clone()).filter(|v| v.is_object())
+                            }
+                            "fallback_models" => {
+                                role.fallback_models =
+                                    serde_json::from_value(value.clone()).unwrap_or_default()
+                            }
                             _ => (),
                         }
                     }
                 }
             }
         }
-        role
+        Ok(role)
     }
 
     pub fn builtin(name: &str) -> Result<Self> {
@@ -126,9 +173,29 @@ impl Role {
         if let Some(top_p) = self.top_p() {
             metadata.push(format!("top_p: {}", top_p));
         }
+        if let Some(reasoning_effort) = self.reasoning_effort() {
+            metadata.push(format!("reasoning_effort: {}", reasoning_effort));
+        }
         if let Some(use_tools) = self.use_tools() {
             metadata.push(format!("use_tools: {}", use_tools));
         }
+        if !self.tool_policy.is_empty() {
+            metadata.push("tool_policy:".into());
+            for (pattern, policy) in &self.tool_policy {
+                let policy = serde_json::to_value(policy).unwrap_or_default();
+                metadata.push(format!(
+                    "  {}: {}",
+                    json!(pattern),
+                    policy.as_str().unwrap_or_default()
+                ));
+            }
+        }
+        if let Some(response_format) = &self.response_format {
+            metadata.push(format!("response_format: {response_format}"));
+        }
+        if !self.fallback_models.is_empty() {
+            metadata.push(format!("fallback_models: {}", json!(self.fallback_models)));
+        }
         if metadata.is_empty() {
             format!("{}\n", self.prompt)
         } else if self.prompt.is_empty() {
@@ -167,6 +234,9 @@ impl Role {
         let top_p = role_like.top_p();
         let use_tools = role_like.use_tools();
         self.batch_set(model, temperature, top_p, use_tools);
+        if let Some(reasoning_effort) = role_like.reasoning_effort() {
+            self.set_reasoning_effort(Some(reasoning_effort));
+        }
     }
 
     pub fn batch_set(
@@ -204,6 +274,26 @@ impl Role {
         &self.prompt
     }
 
+    pub fn tool_policy(&self) -> &ToolPolicies {
+        &self.tool_policy
+    }
+
+    pub fn response_format(&self) -> Option<&Value> {
+        self.response_format.as_ref()
+    }
+
+    pub fn set_response_format(&mut self, value: Option<Value>) {
+        self.response_format = value;
+    }
+
+    pub fn fallback_models(&self) -> &[String] {
+        &self.fallback_models
+    }
+
+    pub fn set_fallback_models(&mut self, value: Vec<String>) {
+        self.fallback_models = value;
+    }
+
     pub fn is_empty_prompt(&self) -> bool {
         self.prompt.is_empty()
     }
@@ -258,6 +348,52 @@ impl Role {
         }
         messages
     }
+
+    /// Applies the prompt to the messages of an existing conversation.
+    pub fn patch_messages(&self, messages: &mut Vec<Message>) {
+        if self.is_empty_prompt() {
+            return;
+        }
+        if self.is_embedded_prompt() {
+            if let Some(message) = messages.iter_mut().rev().find(|v| v.role.is_user()) {
+                message
+                    .content
+                    .merge_prompt(|v: &str| self.prompt.replace(INPUT_PLACEHOLDER, v));
+            }
+            return;
+        }
+        let (system, cases) = parse_structure_prompt(&self.prompt);
+        let mut index = 0;
+        if !system.is_empty() {
+            match messages.first_mut() {
+                Some(Message {
+                    role: MessageRole::System,
+                    content: MessageContent::Text(text),
+                    ..
+                }) => *text = format!("{system}\n\n{text}"),
+                _ => messages.insert(
+                    0,
+                    Message::new(
+                        MessageRole::System,
+                        MessageContent::Text(system.to_string()),
+                    ),
+                ),
+            }
+        }
+        if messages.first().is_some_and(|v| v.role.is_system()) {
+            index = 1;
+        }
+        for (i, o) in cases.into_iter().rev() {
+            messages.insert(
+                index,
+                Message::new(MessageRole::Assistant, MessageContent::Text(o.to_string())),
+            );
+            messages.insert(
+                index,
+                Message::new(MessageRole::User, MessageContent::Text(i.to_string())),
+            );
+        }
+    }
 }
 
 impl RoleLike for Role {
@@ -281,13 +417,17 @@ impl RoleLike for Role {
         self.top_p
     }
 
+    fn reasoning_effort(&self) -> Option<ReasoningEffort> {
+        self.reasoning_effort
+    }
+
     fn use_tools(&self) -> Option<String> {
         self.use_tools.clone()
     }
 
     fn set_model(&mut self, model: &Model) {
         if !self.model().id().is_empty() {
-            self.model_id = Some(model.id().to_string());
+            self.model_id = Some(model.alias_or_id());
         }
         self.model = model.clone();
     }
@@ -300,6 +440,10 @@ impl RoleLike for Role {
         self.top_p = value;
     }
 
+    fn set_reasoning_effort(&mut self, value: Option<ReasoningEffort>) {
+        self.reasoning_effort = value;
+    }
+
     fn set_use_tools(&mut self, value: Option<String>) {
         self.use_tools = value;
     }
@@ -395,4 +539,54 @@ Input 1
 "#;
         assert_eq!(parse_structure_prompt(prompt), (prompt, vec![]));
     }
+
+    #[test]
+    fn test_patch_messages() {
+        let text = |role, text: &str| Message::new(role, MessageContent::Text(text.into()));
+        let role = Role::new(
+            "test",
+            "System message\n### INPUT:\nInput 1\n### OUTPUT:\nOutput 1",
+        );
+        let mut messages = vec![
+            text(MessageRole::System, "Be brief"),
+            text(MessageRole::User, "hello"),
+        ];
+        role.patch_messages(&mut messages);
+        let texts: Vec<String> = messages.iter().map(|v| v.content.to_text()).collect();
+        assert_eq!(
+            texts,
+            ["System message\n\nBe brief", "Input 1", "Output 1", "hello"]
+        );
+
+        let role = Role::new("test", "Translate: __INPUT__");
+        let mut messages = vec![
+            text(MessageRole::User, "hi"),
+            text(MessageRole::User, "bye"),
+        ];
+        role.patch_messages(&mut messages);
+        assert_eq!(messages[0].content.to_text(), "hi");
+        assert_eq!(messages[1].content.to_text(), "Translate: bye");
+    }
+
+    #[test]
+    fn test_load_invalid_metadata() {
+        let content = "---\ntool_policy:\n  execute_command: allowed\n---\nprompt";
+        let err = Role::load("test", content).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Invalid tool_policy in role 'test'"));
+        let role = Role::new("test", content);
+        assert_eq!(role.tool_policy().get("*"), Some(&ToolPolicy::Deny));
+
+        let content = "---\nreasoning_effort: extreme\n---\nprompt";
+        assert!(Role::load("test", content).is_err());
+        assert!(Role::new("test", content).reasoning_effort().is_none());
+
+        let content = "---\ntool_policy:\n  execute_command: ask\n---\nprompt";
+        let role = Role::load("test", content).unwrap();
+        assert_eq!(
+            role.tool_policy().get("execute_command"),
+            Some(&ToolPolicy::Ask)
+        );
+    }
 }
@@ -1,19 +1,24 @@
 use super::input::*;
 use super::*;
 
-use crate::client::{Message, MessageContent, MessageRole};
+use crate::client::{Message, MessageContent, MessageContentPart, MessageRole, ReasoningEffort};
 use crate::render::MarkdownRender;
 
 use anyhow::{bail, Context, Result};
 use fancy_regex::Regex;
 use inquire::{validator::Validation, Confirm, Text};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use std::collections::HashMap;
 use std::fs::{read_to_string, write};
+use std::ops::Range;
 use std::path::Path;
 use std::sync::LazyLock;
 
+pub const DEFAULT_BRANCH_NAME: &str = "main";
+
+type TurnRanges = Vec<Range<usize>>;
+
 static RE_AUTONAME_PREFIX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\d{8}T\d{6}-").unwrap());
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
@@ -25,6 +30,8 @@ pub struct Session {
     #[serde(skip_serializing_if = "Option::is_none")]
     top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_effort: Option<ReasoningEffort>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     use_tools: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     save_session: Option<bool>,
@@ -37,10 +44,16 @@ pub struct Session {
     agent_variables: AgentVariables,
     #[serde(default, skip_serializing_if = "String::is_empty")]
     agent_instructions: String,
+    #[serde(default, skip_serializing_if = "Usage::is_empty")]
+    usage: Usage,
 
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     compressed_messages: Vec<Message>,
     messages: Vec<Message>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    branch: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    branches: Vec<SessionBranch>,
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     data_urls: HashMap<String, String>,
 
@@ -49,6 +62,12 @@ pub struct Session {
     #[serde(skip)]
     role_prompt: String,
     #[serde(skip)]
+    role_tool_policy: ToolPolicies,
+    #[serde(skip)]
+    role_response_format: Option<Value>,
+    #[serde(skip)]
+    role_fallback_models: Vec<String>,
+    #[serde(skip)]
     name: String,
     #[serde(skip)]
     path: Option<String>,
@@ -97,6 +116,9 @@ impl Session {
         if let Some(role_name) = &session.role_name {
             if let Ok(role) = config.retrieve_role(role_name) {
                 session.role_prompt = role.prompt().to_string();
+                session.role_tool_policy = role.tool_policy().clone();
+                session.role_response_format = role.response_format().cloned();
+                session.role_fallback_models = role.fallback_models().to_vec();
             }
         }
 
@@ -115,6 +137,18 @@ impl Session {
         self.role_name.as_deref()
     }
 
+    pub fn role_tool_policy(&self) -> &ToolPolicies {
+        &self.role_tool_policy
+    }
+
+    pub fn usage(&self) -> &Usage {
+        &self.usage
+    }
+
+    pub fn add_usage(&mut self, usage: &Usage) {
+        self.usage.add(usage);
+    }
+
     pub fn dirty(&self) -> bool {
         self.dirty
     }
@@ -146,6 +180,9 @@ impl Session {
         if let Some(top_p) = self.top_p() {
             data["top_p"] = top_p.into();
         }
+        if let Some(reasoning_effort) = self.reasoning_effort() {
+            data["reasoning_effort"] = json!(reasoning_effort);
+        }
         if let Some(use_tools) = self.use_tools() {
             data["use_tools"] = use_tools.into();
         }
@@ -160,6 +197,9 @@ impl Session {
         if percent != 0.0 {
             data["total/max"] = format!("{}%", percent).into();
         }
+        if !self.branches.is_empty() {
+            data["branch"] = self.branch_name().into();
+        }
         data["messages"] = json!(self.messages);
 
         let output = serde_yaml::to_string(&data)
@@ -190,6 +230,9 @@ impl Session {
         if let Some(top_p) = self.top_p() {
             items.push(("top_p", top_p.to_string()));
         }
+        if let Some(reasoning_effort) = self.reasoning_effort() {
+            items.push(("reasoning_effort", reasoning_effort.to_string()));
+        }
 
         if let Some(use_tools) = self.use_tools() {
             items.push(("use_tools", use_tools));
@@ -207,6 +250,17 @@ impl Session {
             items.push(("max_input_tokens", max_input_tokens.to_string()));
         }
 
+        if !self.usage.is_empty() {
+            items.push(("usage", self.usage.summary()));
+        }
+
+        if !self.branches.is_empty() {
+            items.push((
+                "branch",
+                format!("{} (of {})", self.branch_name(), self.branches.len()),
+            ));
+        }
+
         let mut lines: Vec<String> = items
             .iter()
             .map(|(name, value)| format!("{name:<20}{value}"))
@@ -232,8 +286,9 @@ impl Session {
                         lines.push("".into());
                     }
                     MessageRole::User => {
+                        let pin = if message.pinned { "📌 " } else { "" };
                         lines.push(format!(
-                            ">> {}",
+                            ">> {pin}{}",
                             message.content.render_input(resolve_url_fn, agent_info)
                         ));
                     }
@@ -260,19 +315,26 @@ impl Session {
     }
 
     pub fn set_role(&mut self, role: Role) {
-        self.model_id = role.model().id();
+        self.model_id = role.model().alias_or_id();
         self.temperature = role.temperature();
         self.top_p = role.top_p();
+        self.reasoning_effort = role.reasoning_effort();
         self.use_tools = role.use_tools();
         self.model = role.model().clone();
         self.role_name = convert_option_string(role.name());
         self.role_prompt = role.prompt().to_string();
+        self.role_tool_policy = role.tool_policy().clone();
+        self.role_response_format = role.response_format().cloned();
+        self.role_fallback_models = role.fallback_models().to_vec();
         self.dirty = true;
     }
 
     pub fn clear_role(&mut self) {
         self.role_name = None;
         self.role_prompt.clear();
+        self.role_tool_policy.clear();
+        self.role_response_format = None;
+        self.role_fallback_models.clear();
     }
 
     pub fn sync_agent(&mut self, agent: &Agent) {
@@ -308,15 +370,30 @@ impl Session {
         }
     }
 
-    pub fn need_compress(&self, global_compress_threshold: usize) -> bool {
-        if self.compressing {
+    pub fn need_compress(
+        &self,
+        global_compress_threshold: usize,
+        compress_headroom: f64,
+        keep_turns: usize,
+    ) -> bool {
+        if self.compressing || self.split_turns(keep_turns).is_none() {
             return false;
         }
+        let tokens = self.tokens();
+        // A threshold of 0 turns compression off, the headroom check included
         let threshold = self.compress_threshold.unwrap_or(global_compress_threshold);
-        if threshold < 1 {
+        if threshold == 0 {
             return false;
         }
-        self.tokens() > threshold
+        if tokens > threshold {
+            return true;
+        }
+        match self.model().max_input_tokens() {
+            Some(max_input_tokens) if compress_headroom > 0.0 => {
+                tokens as f64 > max_input_tokens as f64 * (1.0 - compress_headroom)
+            }
+            _ => false,
+        }
     }
 
     pub fn compressing(&self) -> bool {
@@ -327,26 +404,143 @@ impl Session {
         self.compressing = compressing;
     }
 
-    pub fn compress(&mut self, mut prompt: String) {
-        if let Some(system_prompt) = self.messages.first().and_then(|v| {
-            if MessageRole::System == v.role {
-                let content = v.content.to_text();
-                if !content.is_empty() {
-                    return Some(content);
+    /// Renders the turns that the next compression will summarize, along with the current summary.
+    pub fn compression_history(&self, keep_turns: usize) -> Option<String> {
+        let (turns, _) = self.split_turns(keep_turns)?;
+        let mut lines = vec![];
+        if let Some(summary) = self.summary() {
+            lines.push(format!(
+                "<previous_summary>\n{summary}\n</previous_summary>\n"
+            ));
+        }
+        lines.push("<chat_history>".to_string());
+        for message in turns.into_iter().flat_map(|v| &self.messages[v]) {
+            match (&message.role, &message.content) {
+                (MessageRole::User, content) => lines.push(format!("USER: {}", content.to_text())),
+                (MessageRole::Assistant, content) => {
+                    lines.push(format!("ASSISTANT: {}", content.to_text()))
+                }
+                (_, MessageContent::ToolCalls(tool_calls)) => {
+                    if !tool_calls.text.is_empty() {
+                        lines.push(format!("ASSISTANT: {}", tool_calls.text));
+                    }
+                    for tool_result in &tool_calls.tool_results {
+                        lines.push(format!(
+                            "TOOL: {}({}) => {}",
+                            tool_result.call.name, tool_result.call.arguments, tool_result.output
+                        ));
+                    }
                 }
+                _ => {}
             }
-            None
-        }) {
-            prompt = format!("{system_prompt}\n\n{prompt}",);
         }
-        self.compressed_messages.append(&mut self.messages);
-        self.messages.push(Message::new(
+        lines.push("</chat_history>".to_string());
+        Some(lines.join("\n"))
+    }
+
+    /// Replaces the summarized turns with a system message holding the new summary,
+    /// keeping pinned turns and the last `keep_turns` turns verbatim.
+    pub fn compress(&mut self, summary: String, keep_turns: usize) {
+        let (turns, kept_turns) = match self.split_turns(keep_turns) {
+            Some(v) => v,
+            None => return,
+        };
+        let prompt = match self.base_system_prompt() {
+            Some(system_prompt) => format!("{system_prompt}\n\n{summary}"),
+            None => summary,
+        };
+        self.detach_branches(0);
+        let head_len = self
+            .messages
+            .iter()
+            .position(|v| v.role.is_user())
+            .unwrap_or_default();
+        self.compressed_messages
+            .extend(self.messages[..head_len].iter().cloned());
+        for turn in turns {
+            self.compressed_messages
+                .extend(self.messages[turn].iter().cloned());
+        }
+        let mut messages = vec![Message::new(
             MessageRole::System,
             MessageContent::Text(prompt),
-        ));
+        )];
+        for turn in kept_turns {
+            messages.extend(self.messages[turn].iter().cloned());
+        }
+        self.messages = messages;
         self.dirty = true;
     }
 
+    pub fn set_pinned(&mut self, index: usize, pinned: bool) -> Result<()> {
+        match self.messages.get_mut(index) {
+            Some(message) => {
+                if message.pinned != pinned {
+                    message.pinned = pinned;
+                    self.dirty = true;
+                }
+                Ok(())
+            }
+            None => bail!("Invalid message index '{index}'"),
+        }
+    }
+
+    /// Splits the turns into those to summarize and those kept verbatim, returning None when nothing can be summarized.
+    fn split_turns(&self, keep_turns: usize) -> Option<(TurnRanges, TurnRanges)> {
+        let starts: Vec<usize> = self
+            .messages
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.role.is_user())
+            .map(|(i, _)| i)
+            .collect();
+        let recent_from = starts.len().saturating_sub(keep_turns);
+        let mut turns = vec![];
+        let mut kept_turns = vec![];
+        for (i, start) in starts.iter().enumerate() {
+            let end = starts.get(i + 1).copied().unwrap_or(self.messages.len());
+            if i >= recent_from || self.messages[*start..end].iter().any(|v| v.pinned) {
+                kept_turns.push(*start..end);
+            } else {
+                turns.push(*start..end);
+            }
+        }
+        if turns.is_empty() {
+            return None;
+        }
+        Some((turns, kept_turns))
+    }
+
+    /// The system prompt the session started with, before any summary got merged into it.
+    fn base_system_prompt(&self) -> Option<String> {
+        let message = if self.compressed_messages.is_empty() {
+            self.messages.first()
+        } else {
+            self.compressed_messages.first()
+        };
+        message
+            .filter(|v| v.role.is_system())
+            .map(|v| v.content.to_text())
+            .filter(|v| !v.is_empty())
+    }
+
+    fn summary(&self) -> Option<String> {
+        if self.compressed_messages.is_empty() {
+            return None;
+        }
+        let text = self
+            .messages
+            .first()
+            .filter(|v| v.role.is_system())?
+            .content
+            .to_text();
+        let summary = match self.base_system_prompt() {
+            Some(system_prompt) => text.strip_prefix(&system_prompt).unwrap_or(&text).trim(),
+            None => text.trim(),
+        };
+        Some(summary.to_string())
+    }
+
     pub fn need_autoname(&self) -> bool {
         self.autoname.as_ref().map(|v| v.need()).unwrap_or_default()
     }
@@ -459,18 +653,22 @@ impl Session {
 
     pub fn add_message(&mut self, input: &Input, output: &str) -> Result<()> {
         if input.continue_output().is_some() {
+            self.detach_branches(self.messages.len().saturating_sub(1));
             if let Some(message) = self.messages.last_mut() {
                 if let MessageContent::Text(text) = &mut message.content {
                     *text = format!("{text}{output}");
                 }
             }
         } else if input.regenerate() {
-            if let Some(message) = self.messages.last_mut() {
-                if let MessageContent::Text(text) = &mut message.content {
-                    *text = output.to_string();
-                }
-            }
+            self.fork_branch(self.messages.len().saturating_sub(1));
+            self.messages.push(Message::new(
+                MessageRole::Assistant,
+                MessageContent::Text(output.to_string()),
+            ));
         } else {
+            if let Some(index) = input.edit_at() {
+                self.fork_branch(index);
+            }
             if self.messages.is_empty() {
                 if self.name == TEMP_SESSION_NAME && self.save_session == Some(true) {
                     let raw_input = input.raw();
@@ -498,9 +696,21 @@ impl Session {
         Ok(())
     }
 
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    /// Adds turns after the last one, so no branch loses the messages it shares.
+    pub fn append_messages(&mut self, messages: Vec<Message>) {
+        self.messages.extend(messages);
+        self.dirty = true;
+    }
+
     pub fn clear_messages(&mut self) {
         self.messages.clear();
         self.compressed_messages.clear();
+        self.branch = None;
+        self.branches.clear();
         self.data_urls.clear();
         self.autoname = None;
         self.dirty = true;
@@ -519,6 +729,9 @@ impl Session {
             messages.pop();
             return messages;
         }
+        if let Some(index) = input.edit_at() {
+            messages.truncate(index);
+        }
         let mut need_add_msg = true;
         let len = messages.len();
         if len == 0 {
@@ -538,12 +751,222 @@ impl Session {
         }
         messages
     }
+
+    pub fn user_turns(&self) -> Vec<(usize, String)> {
+        self.messages
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.role.is_user())
+            .map(|(i, v)| (i, v.content.to_text()))
+            .collect()
+    }
+
+    /// Returns the images attached to a message along with the local paths they were loaded from.
+    pub fn message_medias(&self, index: usize) -> (Vec<String>, HashMap<String, String>) {
+        let mut medias = vec![];
+        let mut data_urls = HashMap::new();
+        if let Some(MessageContent::Array(list)) = self.messages.get(index).map(|v| &v.content) {
+            for item in list {
+                if let MessageContentPart::ImageUrl { image_url } = item {
+                    let hash = sha256(&image_url.url);
+                    if let Some(path) = self.data_urls.get(&hash) {
+                        data_urls.insert(hash, path.clone());
+                    }
+                    medias.push(image_url.url.clone());
+                }
+            }
+        }
+        (medias, data_urls)
+    }
+
+    pub fn branch_name(&self) -> &str {
+        self.branch.as_deref().unwrap_or(DEFAULT_BRANCH_NAME)
+    }
+
+    pub fn branch_names(&self) -> Vec<String> {
+        if self.branches.is_empty() {
+            return vec![self.branch_name().to_string()];
+        }
+        self.branches.iter().map(|v| v.name.clone()).collect()
+    }
+
+    pub fn branch_messages(&self, name: &str) -> Result<Vec<Message>> {
+        if name == self.branch_name() {
+            return Ok(self.messages.clone());
+        }
+        let branch = match self.branches.iter().find(|v| v.name == name) {
+            Some(v) => v,
+            None => bail!("Unknown branch '{name}'"),
+        };
+        let mut messages = match &branch.parent {
+            Some(parent) => {
+                let mut messages = self.branch_messages(parent)?;
+                messages.truncate(branch.fork_at);
+                messages
+            }
+            None => vec![],
+        };
+        messages.extend(branch.messages.iter().cloned());
+        Ok(messages)
+    }
+
+    /// Starts a new branch that shares the first `index` messages with the current one and switches to it.
+    pub fn fork_branch(&mut self, index: usize) -> String {
+        let index = index.min(self.messages.len());
+        if self.branches.is_empty() {
+            self.branches
+                .push(SessionBranch::new(self.branch_name(), None, 0));
+        }
+        let name = (1..)
+            .map(|i| format!("b{i}"))
+            .find(|v| self.branches.iter().all(|b| &b.name != v))
+            .unwrap_or_default();
+        let parent = self.branch_name().to_string();
+        self.stash_active_branch();
+        self.branches
+            .push(SessionBranch::new(&name, Some(parent), index));
+        self.messages.truncate(index);
+        self.branch = Some(name.clone());
+        self.dirty = true;
+        name
+    }
+
+    pub fn switch_branch(&mut self, name: &str) -> Result<()> {
+        if name == self.branch_name() {
+            return Ok(());
+        }
+        let messages = self.branch_messages(name)?;
+        self.stash_active_branch();
+        if let Some(branch) = self.branches.iter_mut().find(|v| v.name == name) {
+            branch.messages.clear();
+        }
+        self.messages = messages;
+        self.branch = if name == DEFAULT_BRANCH_NAME {
+            None
+        } else {
+            Some(name.to_string())
+        };
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn list_branches(&self) -> String {
+        let active = self.branch_name();
+        if self.branches.is_empty() {
+            return format!("* {active:<12}{:>4} messages", self.messages.len());
+        }
+        self.branches
+            .iter()
+            .map(|branch| {
+                let marker = if branch.name == active { "*" } else { " " };
+                let len = self
+                    .branch_messages(&branch.name)
+                    .map(|v| v.len())
+                    .unwrap_or_default();
+                let mut line = format!("{marker} {:<12}{len:>4} messages", branch.name);
+                if let Some(parent) = &branch.parent {
+                    line.push_str(&format!("  (forked from {parent} at #{})", branch.fork_at));
+                }
+                line
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn diff_branches(&self, name1: &str, name2: &str) -> Result<String> {
+        let messages1 = self.branch_messages(name1)?;
+        let messages2 = self.branch_messages(name2)?;
+        let common = messages1
+            .iter()
+            .zip(messages2.iter())
+            .take_while(|(a, b)| {
+                a.role == b.role
+                    && serde_json::to_value(&a.content).ok()
+                        == serde_json::to_value(&b.content).ok()
+            })
+            .count();
+        let mut lines = vec![format!(
+            "Branches '{name1}' and '{name2}' share the first {common} messages."
+        )];
+        for (name, messages) in [(name1, &messages1), (name2, &messages2)] {
+            lines.push(String::new());
+            lines.push(format!("[{name}]"));
+            if messages.len() == common {
+                lines.push("(no further messages)".into());
+            }
+            for message in &messages[common..] {
+                let text = match &message.content {
+                    MessageContent::ToolCalls(tool_calls) => tool_calls
+                        .tool_results
+                        .iter()
+                        .map(|v| format!("<tool_call {}>", v.call.name))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    content => content.to_text(),
+                };
+                match message.role {
+                    MessageRole::User => lines.push(format!(">> {text}")),
+                    _ => lines.push(text),
+                }
+            }
+        }
+        Ok(lines.join("\n"))
+    }
+
+    /// Builds a standalone copy of the session that only holds the given branch.
+    pub fn extract_branch(&self, name: &str) -> Result<Self> {
+        let mut session = self.clone();
+        session.messages = self.branch_messages(name)?;
+        session.branch = None;
+        session.branches.clear();
+        session.autoname = None;
+        Ok(session)
+    }
+
+    fn stash_active_branch(&mut self) {
+        let active = self.branch_name().to_string();
+        if let Some(branch) = self.branches.iter_mut().find(|v| v.name == active) {
+            let fork_at = if branch.parent.is_some() {
+                branch.fork_at.min(self.messages.len())
+            } else {
+                0
+            };
+            branch.messages = self.messages[fork_at..].to_vec();
+        }
+    }
+
+    /// Messages from `index` on are about to change in the active branch, so any branch sharing them is made standalone.
+    fn detach_branches(&mut self, index: usize) {
+        let active = self.branch_name().to_string();
+        let names: Vec<String> = self
+            .branches
+            .iter()
+            .filter(|v| v.parent.as_deref() == Some(active.as_str()) && v.fork_at > index)
+            .map(|v| v.name.clone())
+            .collect();
+        for name in names {
+            let messages = self.branch_messages(&name).unwrap_or_default();
+            if let Some(branch) = self.branches.iter_mut().find(|v| v.name == name) {
+                branch.parent = None;
+                branch.fork_at = 0;
+                branch.messages = messages;
+            }
+        }
+        if let Some(branch) = self.branches.iter_mut().find(|v| v.name == active) {
+            if branch.fork_at > index {
+                branch.parent = None;
+                branch.fork_at = 0;
+            }
+        }
+    }
 }
 
 impl RoleLike for Session {
     fn to_role(&self) -> Role {
         let role_name = self.role_name.as_deref().unwrap_or_default();
         let mut role = Role::new(role_name, &self.role_prompt);
+        role.set_response_format(self.role_response_format.clone());
+        role.set_fallback_models(self.role_fallback_models.clone());
         role.sync(self);
         role
     }
@@ -564,13 +987,17 @@ impl RoleLike for Session {
         self.top_p
     }
 
+    fn reasoning_effort(&self) -> Option<ReasoningEffort> {
+        self.reasoning_effort
+    }
+
     fn use_tools(&self) -> Option<String> {
         self.use_tools.clone()
     }
 
     fn set_model(&mut self, model: &Model) {
         if self.model().id() != model.id() {
-            self.model_id = model.id();
+            self.model_id = model.alias_or_id();
             self.model = model.clone();
             self.dirty = true;
         }
@@ -590,6 +1017,13 @@ impl RoleLike for Session {
         }
     }
 
+    fn set_reasoning_effort(&mut self, value: Option<ReasoningEffort>) {
+        if self.reasoning_effort != value {
+            self.reasoning_effort = value;
+            self.dirty = true;
+        }
+    }
+
     fn set_use_tools(&mut self, value: Option<String>) {
         if self.use_tools != value {
             self.use_tools = value;
@@ -622,3 +1056,141 @@ impl AutoName {
         !self.naming && self.chat_history.is_some() && self.name.is_none()
     }
 }
+
+/// A branch stores the messages that follow its fork point in the parent branch.
+/// The active branch keeps its full history in `Session::messages` instead.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SessionBranch {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<String>,
+    #[serde(default)]
+    fork_at: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    messages: Vec<Message>,
+}
+
+impl SessionBranch {
+    pub fn new(name: &str, parent: Option<String>, fork_at: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            parent,
+            fork_at,
+            messages: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(role: MessageRole, text: &str) -> Message {
+        Message::new(role, MessageContent::Text(text.into()))
+    }
+
+    #[test]
+    fn test_session_branches() {
+        let mut session = Session::default();
+        session.append_messages(vec![
+            text(MessageRole::User, "q1"),
+            text(MessageRole::Assistant, "a1"),
+        ]);
+        let name = session.fork_branch(1);
+        session.messages.push(text(MessageRole::Assistant, "a1'"));
+        assert_eq!(name, "b1");
+        assert_eq!(session.branch_messages("main").unwrap().len(), 2);
+
+        session.switch_branch("main").unwrap();
+        assert_eq!(session.messages[1].content.to_text(), "a1");
+        session.messages.push(text(MessageRole::User, "q2"));
+        let b1 = session.branch_messages("b1").unwrap();
+        assert_eq!(b1.len(), 2);
+        assert_eq!(b1[1].content.to_text(), "a1'");
+
+        let content = serde_yaml::to_string(&session).unwrap();
+        let loaded: Session = serde_yaml::from_str(&content).unwrap();
+        assert_eq!(loaded.branch_names(), vec!["main", "b1"]);
+        assert_eq!(loaded.branch_messages("b1").unwrap().len(), 2);
+
+        let legacy: Session = serde_yaml::from_str("model: x\nmessages: []\n").unwrap();
+        assert_eq!(legacy.branch_names(), vec!["main"]);
+    }
+
+    #[test]
+    fn test_compress_session() {
+        let mut session = Session::default();
+        let mut messages = vec![text(MessageRole::System, "sys")];
+        for i in 1..=4 {
+            messages.push(text(MessageRole::User, &format!("q{i}")));
+            messages.push(text(MessageRole::Assistant, &format!("a{i}")));
+        }
+        messages[3].pinned = true;
+        session.append_messages(messages);
+
+        let history = session.compression_history(2).unwrap();
+        assert!(history.contains("USER: q1") && !history.contains("q2"));
+        session.compress("summary one".into(), 2);
+        let texts: Vec<String> = session
+            .messages
+            .iter()
+            .map(|v| v.content.to_text())
+            .collect();
+        assert_eq!(
+            texts,
+            ["sys\n\nsummary one", "q2", "a2", "q3", "a3", "q4", "a4"]
+        );
+
+        session.messages.push(text(MessageRole::User, "q5"));
+        session.messages.push(text(MessageRole::Assistant, "a5"));
+        let history = session.compression_history(2).unwrap();
+        assert!(history.contains("<previous_summary>\nsummary one\n"));
+        assert!(history.contains("USER: q3") && !history.contains("q2"));
+        session.compress("summary two".into(), 2);
+        assert_eq!(session.messages[0].content.to_text(), "sys\n\nsummary two");
+        assert_eq!(session.user_turns().len(), 3);
+        assert!(session.compression_history(2).is_none());
+    }
+
+    #[test]
+    fn test_need_compress() {
+        let mut session = Session::default();
+        session.model.data_mut().max_input_tokens = Some(100);
+        let mut messages = vec![];
+        for i in 1..=4 {
+            messages.push(text(MessageRole::User, &format!("q{i} ").repeat(20)));
+            messages.push(text(MessageRole::Assistant, &format!("a{i} ").repeat(20)));
+        }
+        session.append_messages(messages);
+        assert!(session.need_compress(100_000, 0.2, 2));
+        assert!(!session.need_compress(100_000, 0.0, 2));
+        assert!(!session.need_compress(0, 0.2, 2));
+        session.set_compress_threshold(Some(10));
+        assert!(session.need_compress(0, 0.0, 2));
+    }
+
+    #[test]
+    fn test_message_medias() {
+        let url = "data:image/png;base64,AAAA".to_string();
+        let mut session = Session::default();
+        session.data_urls.insert(sha256(&url), "/tmp/a.png".into());
+        session
+            .data_urls
+            .insert("other".into(), "/tmp/b.png".into());
+        session.append_messages(vec![Message::new(
+            MessageRole::User,
+            MessageContent::Array(vec![
+                MessageContentPart::Text {
+                    text: "describe".into(),
+                },
+                MessageContentPart::ImageUrl {
+                    image_url: crate::client::ImageUrl { url: url.clone() },
+                },
+            ]),
+        )]);
+        assert_eq!(session.user_turns(), vec![(0, "describe".to_string())]);
+        let (medias, data_urls) = session.message_medias(0);
+        assert_eq!(medias, vec![url]);
+        assert_eq!(data_urls.len(), 1);
+    }
+}
@@ -0,0 +1,402 @@
+use crate::client::Model;
+
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{read_to_string, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    #[serde(default)]
+    pub cost: f64,
+}
+
+impl Usage {
+    pub fn new(model: &Model, input_tokens: u64, output_tokens: u64) -> Self {
+        let data = model.data();
+        // Prices are per million tokens
+        let cost = (input_tokens as f64 * data.input_price.unwrap_or_default()
+            + output_tokens as f64 * data.output_price.unwrap_or_default())
+            / 1_000_000.0;
+        Self {
+            input_tokens,
+            output_tokens,
+            cost,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.input_tokens == 0 && self.output_tokens == 0
+    }
+
+    pub fn add(&mut self, other: &Usage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cost += other.cost;
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{} input / {} output tokens, ${:.4}",
+            self.input_tokens, self.output_tokens, self.cost
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BudgetConfig {
+    pub request: BudgetLimit,
+    pub session: BudgetLimit,
+    pub daily: BudgetLimit,
+    pub monthly: BudgetLimit,
+}
+
+impl BudgetConfig {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BudgetLimit {
+    pub tokens: Option<u64>,
+    pub cost: Option<f64>,
+}
+
+impl BudgetLimit {
+    /// Returns the reason if `spent` plus `estimate` goes over the limit.
+    pub fn check(&self, name: &str, spent: &Usage, estimate: &Usage) -> Option<String> {
+        let tokens = spent.input_tokens
+            + spent.output_tokens
+            + estimate.input_tokens
+            + estimate.output_tokens;
+        if let Some(limit) = self.tokens.filter(|v| tokens > *v) {
+            return Some(format!(
+                "The {name} budget of {limit} tokens would be exceeded ({tokens} tokens)"
+            ));
+        }
+        let cost = spent.cost + estimate.cost;
+        if let Some(limit) = self.cost.filter(|v| cost > *v) {
+            return Some(format!(
+                "The {name} budget of ${limit} would be exceeded (${cost:.4})"
+            ));
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub time: String,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+impl UsageRecord {
+    pub fn day(&self) -> &str {
+        self.time.get(..10).unwrap_or(&self.time)
+    }
+}
+
+/// Today's usage per API key of the server, so quota checks don't re-read the ledger.
+#[derive(Debug, Default)]
+pub struct ApiKeyUsage {
+    day: String,
+    totals: HashMap<String, Usage>,
+}
+
+impl ApiKeyUsage {
+    pub fn load(records: &[UsageRecord], day: &str) -> Self {
+        let mut totals: HashMap<String, Usage> = HashMap::new();
+        for record in records.iter().filter(|v| v.day() == day) {
+            if let Some(name) = &record.api_key {
+                totals.entry(name.clone()).or_default().add(&record.usage);
+            }
+        }
+        Self {
+            day: day.to_string(),
+            totals,
+        }
+    }
+
+    pub fn is_current(&self, day: &str) -> bool {
+        self.day == day
+    }
+
+    pub fn get(&self, name: &str) -> Usage {
+        self.totals.get(name).copied().unwrap_or_default()
+    }
+
+    pub fn add(&mut self, name: &str, usage: &Usage) {
+        self.totals.entry(name.to_string()).or_default().add(usage);
+    }
+}
+
+/// The usage of today and of this month, so budget checks don't re-read the ledger.
+#[derive(Debug, Default)]
+pub struct BudgetUsage {
+    day: String,
+    daily: Usage,
+    monthly: Usage,
+}
+
+impl BudgetUsage {
+    pub fn load(records: &[UsageRecord], day: &str) -> Self {
+        Self {
+            day: day.to_string(),
+            daily: sum_usage_since(records, day),
+            monthly: sum_usage_since(records, month_of(day)),
+        }
+    }
+
+    pub fn is_current(&self, day: &str) -> bool {
+        self.day == day
+    }
+
+    pub fn daily(&self) -> Usage {
+        self.daily
+    }
+
+    pub fn monthly(&self) -> Usage {
+        self.monthly
+    }
+
+    pub fn add(&mut self, record: &UsageRecord) {
+        if record.time.starts_with(&self.day) {
+            self.daily.add(&record.usage);
+        }
+        if record.time.starts_with(month_of(&self.day)) {
+            self.monthly.add(&record.usage);
+        }
+    }
+}
+
+fn month_of(day: &str) -> &str {
+    day.get(..7).unwrap_or(day)
+}
+
+pub fn append_usage_record(path: &Path, record: &UsageRecord) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open usage file '{}'", path.display()))?;
+    let line = serde_json::to_string(record)?;
+    writeln!(file, "{line}")
+        .with_context(|| format!("Failed to write usage file '{}'", path.display()))?;
+    Ok(())
+}
+
+pub fn load_usage_records(path: &Path) -> Result<Vec<UsageRecord>> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let content = read_to_string(path)
+        .with_context(|| format!("Failed to read usage file '{}'", path.display()))?;
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+pub fn sum_usage_since(records: &[UsageRecord], time_prefix: &str) -> Usage {
+    let mut usage = Usage::default();
+    for record in records.iter().filter(|v| v.time.starts_with(time_prefix)) {
+        usage.add(&record.usage);
+    }
+    usage
+}
+
+pub fn render_usage_report(records: &[UsageRecord]) -> String {
+    if records.is_empty() {
+        return "No usage recorded\n".into();
+    }
+    let mut total = Usage::default();
+    for record in records {
+        total.add(&record.usage);
+    }
+    let mut output = format!("{:<20}{}\n", "requests", records.len());
+    output.push_str(&format!("{:<20}{}\n", "total", total.summary()));
+    let mut names = vec!["model", "role", "day"];
+    if records.iter().any(|v| v.api_key.is_some()) {
+        names.push("api_key");
+    }
+    for name in names {
+        let mut groups: IndexMap<String, (usize, Usage)> = IndexMap::new();
+        for record in records {
+            let key = match name {
+                "model" => record.model.clone(),
+                "role" => record.role.clone().unwrap_or_else(|| "-".into()),
+                "api_key" => record.api_key.clone().unwrap_or_else(|| "-".into()),
+                _ => record.day().to_string(),
+            };
+            let (count, usage) = groups.entry(key).or_default();
+            *count += 1;
+            usage.add(&record.usage);
+        }
+        groups.sort_keys();
+        output.push_str(&format!(
+            "\n{:<32}{:>10}{:>12}{:>12}{:>12}\n",
+            name, "requests", "input", "output", "cost"
+        ));
+        for (key, (count, usage)) in groups {
+            output.push_str(&format!(
+                "{:<32}{:>10}{:>12}{:>12}{:>12}\n",
+                key,
+                count,
+                usage.input_tokens,
+                usage.output_tokens,
+                format!("${:.4}", usage.cost)
+            ));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_usage_report() {
+        let record = |time: &str, model: &str, role: Option<&str>, input_tokens, output_tokens| {
+            UsageRecord {
+                time: time.into(),
+                model: model.into(),
+                role: role.map(|v| v.into()),
+                usage: Usage {
+                    input_tokens,
+                    output_tokens,
+                    cost: 0.5,
+                },
+                ..Default::default()
+            }
+        };
+        let records = vec![
+            record(
+                "2024-01-02T10:00:00+00:00",
+                "openai:gpt-4o",
+                Some("coder"),
+                10,
+                5,
+            ),
+            record("2024-01-01T10:00:00+00:00", "openai:gpt-4o", None, 20, 5),
+            record(
+                "2024-01-02T11:00:00+00:00",
+                "claude:haiku",
+                Some("coder"),
+                1,
+                1,
+            ),
+        ];
+        let report = render_usage_report(&records);
+        assert!(report.contains("total               31 input / 11 output tokens, $1.5000"));
+        assert!(report.contains(&format!(
+            "{:<32}{:>10}{:>12}{:>12}{:>12}",
+            "openai:gpt-4o", 2, 30, 10, "$1.0000"
+        )));
+        assert!(report.contains(&format!(
+            "{:<32}{:>10}{:>12}{:>12}{:>12}",
+            "2024-01-02", 2, 11, 6, "$1.0000"
+        )));
+        let day1 = report.find("2024-01-01").unwrap();
+        let day2 = report.find("2024-01-02").unwrap();
+        assert!(day1 < day2);
+        assert_eq!(sum_usage_since(&records, "2024-01-02").input_tokens, 11);
+    }
+
+    #[test]
+    fn test_api_key_usage() {
+        let record = |time: &str, api_key: Option<&str>, input_tokens| UsageRecord {
+            time: time.into(),
+            api_key: api_key.map(|v| v.into()),
+            usage: Usage {
+                input_tokens,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let records = vec![
+            record("2024-01-01T10:00:00+00:00", Some("alice"), 10),
+            record("2024-01-02T10:00:00+00:00", Some("alice"), 20),
+            record("2024-01-02T11:00:00+00:00", Some("bob"), 5),
+            record("2024-01-02T12:00:00+00:00", None, 7),
+        ];
+        let mut usage = ApiKeyUsage::load(&records, "2024-01-02");
+        assert!(usage.is_current("2024-01-02"));
+        assert!(!usage.is_current("2024-01-03"));
+        assert_eq!(usage.get("alice").input_tokens, 20);
+        assert_eq!(usage.get("carol").input_tokens, 0);
+        usage.add("bob", &records[0].usage);
+        assert_eq!(usage.get("bob").input_tokens, 15);
+    }
+
+    #[test]
+    fn test_budget_usage() {
+        let record = |time: &str, input_tokens| UsageRecord {
+            time: time.into(),
+            usage: Usage {
+                input_tokens,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let records = vec![
+            record("2023-12-31T10:00:00+00:00", 1),
+            record("2024-01-01T10:00:00+00:00", 10),
+            record("2024-01-02T10:00:00+00:00", 20),
+        ];
+        let mut usage = BudgetUsage::load(&records, "2024-01-02");
+        assert!(usage.is_current("2024-01-02"));
+        assert!(!usage.is_current("2024-01-03"));
+        assert_eq!(usage.daily().input_tokens, 20);
+        assert_eq!(usage.monthly().input_tokens, 30);
+        usage.add(&record("2024-01-02T11:00:00+00:00", 5));
+        assert_eq!(usage.daily().input_tokens, 25);
+        assert_eq!(usage.monthly().input_tokens, 35);
+    }
+
+    #[test]
+    fn test_budget_limit() {
+        let limit = BudgetLimit {
+            tokens: Some(100),
+            cost: Some(1.0),
+        };
+        let usage = |tokens, cost| Usage {
+            input_tokens: tokens,
+            output_tokens: 0,
+            cost,
+        };
+        assert_eq!(limit.check("daily", &usage(50, 0.5), &usage(50, 0.5)), None);
+        assert_eq!(
+            limit
+                .check("daily", &usage(60, 0.1), &usage(50, 0.1))
+                .unwrap(),
+            "The daily budget of 100 tokens would be exceeded (110 tokens)"
+        );
+        assert_eq!(
+            limit
+                .check("session", &usage(0, 0.8), &usage(10, 0.3))
+                .unwrap(),
+            "The session budget of $1 would be exceeded ($1.1000)"
+        );
+    }
+}
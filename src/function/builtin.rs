@@ -0,0 +1,435 @@
+use super::*;
+
+use crate::sandbox::truncate_output;
+
+use fancy_regex::Regex;
+use std::{
+    io::{BufRead, BufReader, Read},
+    path::Path,
+    time::Duration,
+};
+
+/// Enables all built-in tools in `use_tools`.
+pub const BUILTIN_TOOLSET: &str = "builtin";
+/// Enables one built-in tool in `use_tools`, e.g. `builtin:read_file`.
+pub const BUILTIN_PREFIX: &str = "builtin:";
+
+const READ_FILE: &str = "read_file";
+const LIST_DIRECTORY: &str = "list_directory";
+const GREP: &str = "grep";
+const FETCH_URL: &str = "fetch_url";
+const SEARCH_RAG: &str = "search_rag";
+const RUN_SHELL_COMMAND: &str = "run_shell_command";
+
+const READ_FILE_MAX_LINES: usize = 2000;
+const GREP_MAX_MATCHES: usize = 200;
+const GREP_MAX_FILES: usize = 10000;
+const GREP_MAX_LINE_LENGTH: usize = 500;
+const GREP_MAX_FILE_SIZE: u64 = 1024 * 1024;
+const RUN_SHELL_COMMAND_MAX_OUTPUT: usize = 64 * 1024;
+
+pub fn builtin_declarations() -> Vec<FunctionDeclaration> {
+    let value = json!([
+        {
+            "name": READ_FILE,
+            "description": "Read the contents of a text file.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "The path of the file" },
+                    "offset": { "type": "integer", "description": "The line number to start reading from, starting at 1" },
+                    "limit": { "type": "integer", "description": "The maximum number of lines to read" }
+                },
+                "required": ["path"]
+            }
+        },
+        {
+            "name": LIST_DIRECTORY,
+            "description": "List the entries of a directory.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "The path of the directory" }
+                },
+                "required": ["path"]
+            }
+        },
+        {
+            "name": GREP,
+            "description": "Search files for lines matching a regular expression, skipping hidden files.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "pattern": { "type": "string", "description": "The regular expression to search for" },
+                    "path": { "type": "string", "description": "The file or directory to search in, defaults to the current directory" },
+                    "include": { "type": "string", "description": "Only search files whose name matches this glob, e.g. '*.rs'" }
+                },
+                "required": ["pattern"]
+            }
+        },
+        {
+            "name": FETCH_URL,
+            "description": "Fetch the contents of a URL as text.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "url": { "type": "string", "description": "The URL to fetch" }
+                },
+                "required": ["url"]
+            }
+        },
+        {
+            "name": SEARCH_RAG,
+            "description": "Search the documents of the active RAG.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "The search query" }
+                },
+                "required": ["query"]
+            }
+        },
+        {
+            "name": RUN_SHELL_COMMAND,
+            "description": "Run a shell command and return its output. The user is asked for confirmation first.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "command": { "type": "string", "description": "The shell command to run" }
+                },
+                "required": ["command"]
+            }
+        }
+    ]);
+    let mut declarations: Vec<FunctionDeclaration> =
+        serde_json::from_value(value).expect("Invalid builtin declarations");
+    for declaration in declarations.iter_mut() {
+        declaration.builtin = true;
+    }
+    declarations
+}
+
+/// Built-in tools that must be confirmed unless a tool policy says otherwise.
+pub fn builtin_policy(name: &str) -> Option<ToolPolicy> {
+    match name {
+        RUN_SHELL_COMMAND => Some(ToolPolicy::Ask),
+        _ => None,
+    }
+}
+
+pub async fn eval_builtin(
+    config: &GlobalConfig,
+    name: &str,
+    args: &Value,
+    abort_signal: &AbortSignal,
+) -> Result<Value> {
+    let timeout = config.read().tool_timeout(name);
+    let run = async {
+        match name {
+            READ_FILE => read_file(args),
+            LIST_DIRECTORY => list_directory(args),
+            GREP => grep(args),
+            FETCH_URL => fetch_url(config, args).await,
+            SEARCH_RAG => search_rag(config, args).await,
+            RUN_SHELL_COMMAND => run_shell_command(args, timeout, abort_signal).await,
+            _ => bail!("Unknown builtin tool '{name}'"),
+        }
+    };
+    let run = async {
+        match timeout {
+            Some(timeout) => tokio::time::timeout(Duration::from_secs(timeout), run)
+                .await
+                .map_err(|_| anyhow!("Tool call {name} timed out after {timeout}s"))?,
+            None => run.await,
+        }
+    };
+    tokio::select! {
+        ret = run => ret,
+        _ = wait_abort_signal(abort_signal) => bail!("Aborted."),
+    }
+}
+
+fn read_file(args: &Value) -> Result<Value> {
+    let path = get_str_arg(args, "path")?;
+    let file = fs::File::open(path).with_context(|| format!("Failed to read file '{path}'"))?;
+    let offset = args["offset"].as_u64().unwrap_or(1).max(1) as usize;
+    let limit = args["limit"]
+        .as_u64()
+        .map(|v| v as usize)
+        .unwrap_or(READ_FILE_MAX_LINES)
+        .min(READ_FILE_MAX_LINES);
+    // Keeps only the requested lines, the rest of the file is just counted
+    let mut reader = BufReader::new(file);
+    let mut lines = vec![];
+    let mut total_lines = 0;
+    for line in reader.by_ref().lines().take(offset - 1 + limit) {
+        let line = line.with_context(|| format!("Failed to read file '{path}'"))?;
+        total_lines += 1;
+        if total_lines >= offset {
+            lines.push(line);
+        }
+    }
+    let mut last_byte = b'\n';
+    loop {
+        let buf = reader.fill_buf()?;
+        let Some(byte) = buf.last() else {
+            break;
+        };
+        last_byte = *byte;
+        total_lines += buf.iter().filter(|v| **v == b'\n').count();
+        let len = buf.len();
+        reader.consume(len);
+    }
+    if last_byte != b'\n' {
+        total_lines += 1;
+    }
+    let end_line = offset - 1 + lines.len();
+    let mut output = json!({
+        "path": path,
+        "content": lines.join("\n"),
+    });
+    if offset > 1 || end_line < total_lines {
+        output["lines"] = format!("{offset}-{end_line} of {total_lines}").into();
+    }
+    Ok(output)
+}
+
+fn list_directory(args: &Value) -> Result<Value> {
+    let path = get_str_arg(args, "path")?;
+    let entries =
+        fs::read_dir(path).with_context(|| format!("Failed to read directory '{path}'"))?;
+    let mut output = vec![];
+    for entry in entries.flatten() {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let name = entry.file_name().to_string_lossy().to_string();
+        if metadata.is_dir() {
+            output.push(json!({ "name": name, "type": "directory" }));
+        } else {
+            output.push(json!({ "name": name, "type": "file", "size": metadata.len() }));
+        }
+    }
+    output.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+    Ok(json!({ "path": path, "entries": output }))
+}
+
+fn grep(args: &Value) -> Result<Value> {
+    let pattern = get_str_arg(args, "pattern")?;
+    let path = args["path"].as_str().unwrap_or(".");
+    let include = args["include"].as_str();
+    let re = Regex::new(pattern).with_context(|| format!("Invalid pattern '{pattern}'"))?;
+    let mut state = GrepState::default();
+    let path = Path::new(path);
+    let metadata =
+        fs::metadata(path).with_context(|| format!("Failed to access '{}'", path.display()))?;
+    grep_path(path, &metadata, &re, include, &mut state);
+    let truncated = state.matches.len() >= GREP_MAX_MATCHES || state.files >= GREP_MAX_FILES;
+    Ok(json!({
+        "matches": state.matches,
+        "truncated": truncated,
+    }))
+}
+
+#[derive(Debug, Default)]
+struct GrepState {
+    matches: Vec<String>,
+    files: usize,
+}
+
+impl GrepState {
+    fn is_full(&self) -> bool {
+        self.matches.len() >= GREP_MAX_MATCHES || self.files >= GREP_MAX_FILES
+    }
+}
+
+/// Symbolic links below `path` are skipped.
+fn grep_path(
+    path: &Path,
+    metadata: &fs::Metadata,
+    re: &Regex,
+    include: Option<&str>,
+    state: &mut GrepState,
+) {
+    if state.is_full() {
+        return;
+    }
+    if metadata.is_dir() {
+        let Ok(entries) = fs::read_dir(path) else {
+            return;
+        };
+        let mut entries: Vec<_> = entries.flatten().collect();
+        entries.sort_by_key(|v| v.file_name());
+        for entry in entries {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let Ok(metadata) = fs::symlink_metadata(entry.path()) else {
+                continue;
+            };
+            if metadata.file_type().is_symlink() {
+                continue;
+            }
+            grep_path(&entry.path(), &metadata, re, include, state);
+        }
+        return;
+    }
+    let file_name = path
+        .file_name()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if !metadata.is_file()
+        || include.is_some_and(|v| !glob_match(v, &file_name))
+        || metadata.len() > GREP_MAX_FILE_SIZE
+    {
+        return;
+    }
+    state.files += 1;
+    let Ok(contents) = fs::read_to_string(path) else {
+        return;
+    };
+    for (i, line) in contents.lines().enumerate() {
+        if let Ok(true) = re.is_match(line) {
+            let line = truncate_output(line.trim_end().to_string(), GREP_MAX_LINE_LENGTH);
+            state
+                .matches
+                .push(format!("{}:{}:{line}", path.display(), i + 1));
+            if state.matches.len() >= GREP_MAX_MATCHES {
+                break;
+            }
+        }
+    }
+}
+
+async fn fetch_url(config: &GlobalConfig, args: &Value) -> Result<Value> {
+    let url = get_str_arg(args, "url")?;
+    let loaders = config.read().document_loaders.clone();
+    let (contents, _) = fetch_with_loaders(&loaders, url, false)
+        .await
+        .with_context(|| format!("Failed to fetch '{url}'"))?;
+    Ok(json!({ "url": url, "content": contents }))
+}
+
+async fn search_rag(config: &GlobalConfig, args: &Value) -> Result<Value> {
+    let query = get_str_arg(args, "query")?;
+    let rag = config
+        .read()
+        .rag
+        .clone()
+        .ok_or_else(|| anyhow!("No active RAG"))?;
+    let (reranker_model, top_k) = rag.get_config();
+    let documents = rag
+        .hybird_search(query, top_k, reranker_model.as_deref())
+        .await?;
+    let documents: Vec<String> = documents.into_iter().map(|(_, v)| v).collect();
+    Ok(json!({ "documents": documents }))
+}
+
+async fn run_shell_command(
+    args: &Value,
+    timeout: Option<u64>,
+    abort_signal: &AbortSignal,
+) -> Result<Value> {
+    let command = get_str_arg(args, "command")?.to_string();
+    let abort_signal = abort_signal.clone();
+    let status = tokio::task::spawn_blocking(move || {
+        let mut cmd = Command::new(&SHELL.cmd);
+        cmd.arg(&SHELL.arg).arg(&command);
+        // One byte over the cap, so the output is marked as truncated
+        run_command_abortable(
+            &mut cmd,
+            Some(RUN_SHELL_COMMAND_MAX_OUTPUT + 1),
+            timeout.map(Duration::from_secs),
+            &abort_signal,
+            || {},
+        )
+    })
+    .await??;
+    match status {
+        CommandStatus::Exited(exit_code, stdout, stderr) => Ok(json!({
+            "exit_code": exit_code,
+            "stdout": truncate_output(stdout, RUN_SHELL_COMMAND_MAX_OUTPUT),
+            "stderr": truncate_output(stderr, RUN_SHELL_COMMAND_MAX_OUTPUT),
+        })),
+        CommandStatus::TimedOut => bail!("The command timed out"),
+        CommandStatus::Aborted => bail!("Aborted."),
+    }
+}
+
+fn get_str_arg<'a>(args: &'a Value, name: &str) -> Result<&'a str> {
+    args[name]
+        .as_str()
+        .ok_or_else(|| anyhow!("Missing argument '{name}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_file() {
+        let path = temp_file("-read-file-", ".txt");
+        fs::write(&path, "a\nb\nc\nd\n").unwrap();
+        let path = path.display().to_string();
+        let output = read_file(&json!({ "path": path, "offset": 2, "limit": 2 })).unwrap();
+        assert_eq!(output["content"], "b\nc");
+        assert_eq!(output["lines"], "2-3 of 4");
+        let output = read_file(&json!({ "path": path })).unwrap();
+        assert_eq!(output["content"], "a\nb\nc\nd");
+        assert!(output.get("lines").is_none());
+        fs::write(&path, "a\nb\nc").unwrap();
+        let output = read_file(&json!({ "path": path, "limit": 1 })).unwrap();
+        assert_eq!(output["content"], "a");
+        assert_eq!(output["lines"], "1-1 of 3");
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_grep() {
+        let dir = temp_file("-grep-", "");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.rs"), "fn main() {}\nlet x = 1;\n").unwrap();
+        fs::write(dir.join("sub").join("b.txt"), "fn helper\n").unwrap();
+        fs::write(dir.join(".hidden.rs"), "fn hidden\n").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&dir, dir.join("sub").join("loop")).unwrap();
+        let path = dir.display().to_string();
+        let output = grep(&json!({ "pattern": "^fn", "path": path })).unwrap();
+        let matches: Vec<&str> = output["matches"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v.as_str().unwrap())
+            .collect();
+        assert_eq!(matches.len(), 2);
+        assert!(matches[0].ends_with("a.rs:1:fn main() {}"));
+        assert!(matches[1].ends_with("b.txt:1:fn helper"));
+        assert_eq!(output["truncated"], false);
+        let output = grep(&json!({ "pattern": "fn", "path": path, "include": "*.rs" })).unwrap();
+        assert_eq!(output["matches"].as_array().unwrap().len(), 1);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_shell_command_truncated() {
+        let args = json!({ "command": "yes | head -c 10000000; echo done" });
+        let output = run_shell_command(&args, None, &create_abort_signal())
+            .await
+            .unwrap();
+        let stdout = output["stdout"].as_str().unwrap();
+        assert_eq!(output["exit_code"], 0);
+        assert!(stdout.len() < RUN_SHELL_COMMAND_MAX_OUTPUT + 100);
+        assert!(stdout.ends_with("[output truncated]"));
+    }
+
+    #[test]
+    fn test_builtin_policy() {
+        let mut config = Config::default();
+        config.functions.register_builtins();
+        assert_eq!(config.tool_policy(RUN_SHELL_COMMAND), ToolPolicy::Ask);
+        assert_eq!(config.tool_policy(READ_FILE), ToolPolicy::Allow);
+        config.tool_policy.insert("run_*".into(), ToolPolicy::Deny);
+        assert_eq!(config.tool_policy(RUN_SHELL_COMMAND), ToolPolicy::Deny);
+    }
+}
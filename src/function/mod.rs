@@ -0,0 +1,957 @@
+mod builtin;
+
+pub use self::builtin::{builtin_policy, BUILTIN_PREFIX, BUILTIN_TOOLSET};
+
+use self::builtin::{builtin_declarations, eval_builtin};
+
+use crate::{
+    client::SseEvent,
+    config::{Config, GlobalConfig},
+    mcp::{mcp_tool_name, normalize_json_schema, McpClient, McpServerConfig, MCP_TOOL_SEPARATOR},
+    render::render_stream,
+    sandbox::SandboxConfig,
+    utils::*,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures_util::{future::join_all, stream, StreamExt};
+use indexmap::IndexMap;
+use inquire::Select;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::mpsc::unbounded_channel;
+
+#[cfg(windows)]
+const PATH_SEP: &str = ";";
+#[cfg(not(windows))]
+const PATH_SEP: &str = ":";
+
+pub async fn eval_tool_calls(
+    config: &GlobalConfig,
+    mut calls: Vec<ToolCall>,
+    abort_signal: AbortSignal,
+) -> Result<Vec<ToolResult>> {
+    let mut output = vec![];
+    if calls.is_empty() {
+        return Ok(output);
+    }
+    calls = ToolCall::dedup(calls);
+    if calls.is_empty() {
+        bail!("The request was aborted because an infinite loop of function calls was detected.")
+    }
+    let mut results: Vec<Value> = vec![Value::Null; calls.len()];
+    let mut indexes = vec![];
+    let mut allowed_calls = vec![];
+    for (i, call) in calls.iter().enumerate() {
+        if call.check_policy(config)? {
+            indexes.push(i);
+            allowed_calls.push(call.clone());
+        } else {
+            results[i] = json!({
+                "error": format!("The call '{}' was denied by the tool policy", call.name)
+            });
+        }
+    }
+    let (concurrency, serve) = {
+        let config = config.read();
+        (config.tool_call_concurrency, config.working_mode.is_serve())
+    };
+    let eval_calls = async {
+        if serve {
+            eval_tool_calls_logged(config, &allowed_calls, concurrency, &abort_signal).await
+        } else if allowed_calls.len() > 1 && concurrency > 1 {
+            eval_tool_calls_concurrently(config, &allowed_calls, concurrency, &abort_signal).await
+        } else {
+            let mut values = vec![];
+            for call in &allowed_calls {
+                values.push(call.eval(config, false, &abort_signal, None).await?);
+            }
+            Ok(values)
+        }
+    };
+    let watch_ctrlc = async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            abort_signal.set_ctrlc();
+        }
+        std::future::pending::<()>().await
+    };
+    let values = tokio::select! {
+        ret = eval_calls => ret?,
+        _ = watch_ctrlc => unreachable!(),
+    };
+    for (i, value) in indexes.into_iter().zip(values) {
+        results[i] = value;
+    }
+    let mut is_all_null = true;
+    for (call, mut result) in calls.into_iter().zip(results) {
+        if result.is_null() {
+            result = json!("DONE");
+        } else {
+            is_all_null = false;
+        }
+        output.push(ToolResult::new(call, result));
+    }
+    if is_all_null {
+        output = vec![];
+    }
+    Ok(output)
+}
+
+async fn eval_tool_calls_concurrently(
+    config: &GlobalConfig,
+    calls: &[ToolCall],
+    concurrency: usize,
+    abort_signal: &AbortSignal,
+) -> Result<Vec<Value>> {
+    let progress = ProgressLines::new(
+        calls
+            .iter()
+            .map(|call| format!("Call {} {}", call.name, call.arguments))
+            .collect(),
+    );
+    let results: Vec<Result<Value>> = stream::iter(calls.iter().cloned().enumerate())
+        .map(|(i, call)| {
+            let progress = &progress;
+            async move {
+                progress.start(i);
+                let ret = call
+                    .eval(config, true, abort_signal, Some(progress.detail_fn(i)))
+                    .await;
+                progress.finish(i, ret.is_ok());
+                ret
+            }
+        })
+        .buffered(concurrency)
+        .collect()
+        .await;
+    progress.stop().await;
+    results.into_iter().collect()
+}
+
+/// Runs the calls of the server, which reports them to the log rather than its stdout.
+async fn eval_tool_calls_logged(
+    config: &GlobalConfig,
+    calls: &[ToolCall],
+    concurrency: usize,
+    abort_signal: &AbortSignal,
+) -> Result<Vec<Value>> {
+    let results: Vec<Result<Value>> = stream::iter(calls.iter().cloned())
+        .map(|call| async move {
+            info!("Call {} {}", call.name, call.arguments);
+            let name = call.name.clone();
+            let on_progress: ProgressFn = Arc::new(move |line: &str| debug!("{name}: {line}"));
+            let ret = call
+                .eval(config, true, abort_signal, Some(on_progress))
+                .await;
+            if let Err(err) = &ret {
+                warn!("Call {} failed, {err:#}", call.name);
+            }
+            ret
+        })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await;
+    results.into_iter().collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolPolicy {
+    Allow,
+    Deny,
+    Ask,
+}
+
+/// Tool policies keyed by tool name or glob pattern.
+pub type ToolPolicies = IndexMap<String, ToolPolicy>;
+
+/// Look up a setting keyed by tool name or glob.
+/// An exact name takes precedence, otherwise the first matching glob wins.
+pub fn match_tool_name<'a, T>(map: &'a IndexMap<String, T>, name: &str) -> Option<&'a T> {
+    if let Some(value) = map.get(name) {
+        return Some(value);
+    }
+    map.iter()
+        .find(|(pattern, _)| glob_match(pattern, name))
+        .map(|(_, value)| value)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ToolResult {
+    pub call: ToolCall,
+    pub output: Value,
+}
+
+impl ToolResult {
+    pub fn new(call: ToolCall, output: Value) -> Self {
+        Self { call, output }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Functions {
+    declarations: Vec<FunctionDeclaration>,
+    mcp_clients: IndexMap<String, Arc<McpClient>>,
+    /// The MCP servers that were connected or failed to connect
+    mcp_servers: HashSet<String>,
+}
+
+impl Functions {
+    pub fn init(declarations_path: &Path) -> Result<Self> {
+        let declarations: Vec<FunctionDeclaration> = if declarations_path.exists() {
+            let ctx = || {
+                format!(
+                    "Failed to load functions at {}",
+                    declarations_path.display()
+                )
+            };
+            let content = fs::read_to_string(declarations_path).with_context(ctx)?;
+            serde_json::from_str(&content).with_context(ctx)?
+        } else {
+            vec![]
+        };
+
+        Ok(Self {
+            declarations,
+            ..Default::default()
+        })
+    }
+
+    /// The MCP servers that `use_tools` refers to and that are not loaded yet.
+    pub fn pending_mcp_servers(
+        &self,
+        use_tools: &str,
+        mapping_tools: &IndexMap<String, String>,
+        servers: &IndexMap<String, McpServerConfig>,
+    ) -> IndexMap<String, McpServerConfig> {
+        let mut items = vec![];
+        for item in use_tools.split(',') {
+            let item = item.trim();
+            match mapping_tools.get(item) {
+                Some(values) => items.extend(values.split(',').map(|v| v.trim())),
+                None => items.push(item),
+            }
+        }
+        servers
+            .iter()
+            .filter(|(name, _)| {
+                !self.mcp_servers.contains(name.as_str())
+                    && items.iter().any(|item| {
+                        *item == "all"
+                            || item.strip_suffix(":*") == Some(name.as_str())
+                            || item.split_once(MCP_TOOL_SEPARATOR).map(|(v, _)| v)
+                                == Some(name.as_str())
+                    })
+            })
+            .map(|(name, server)| (name.clone(), server.clone()))
+            .collect()
+    }
+
+    /// Connects to the MCP servers concurrently, a server that fails is reported and skipped.
+    pub async fn load_mcp_servers(&mut self, servers: &IndexMap<String, McpServerConfig>) {
+        let results = join_all(servers.iter().map(|(name, server)| async move {
+            let client = McpClient::connect(name, server).await?;
+            let tools = client.list_tools().await?;
+            anyhow::Ok((client, tools))
+        }))
+        .await;
+        for ((name, _), ret) in servers.iter().zip(results) {
+            self.mcp_servers.insert(name.clone());
+            let (client, tools) = match ret {
+                Ok(v) => v,
+                Err(err) => {
+                    warn!("Failed to load mcp server '{name}', {err:?}");
+                    eprintln!(
+                        "{}",
+                        warning_text(&format!("⚠️ Failed to load mcp server '{name}': {err}"))
+                    );
+                    continue;
+                }
+            };
+            for tool in tools {
+                let mut parameters = tool.input_schema;
+                normalize_json_schema(&mut parameters);
+                let parameters = match serde_json::from_value(parameters) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        warn!("Skip mcp tool '{}' of '{name}', {err}", tool.name);
+                        continue;
+                    }
+                };
+                self.declarations.push(FunctionDeclaration {
+                    name: mcp_tool_name(name, &tool.name),
+                    description: tool.description,
+                    parameters,
+                    agent: false,
+                    mcp: Some((name.clone(), tool.name)),
+                    builtin: false,
+                });
+            }
+            self.mcp_clients.insert(name.clone(), Arc::new(client));
+        }
+    }
+
+    /// Built-in tools never shadow the functions from `functions_dir`.
+    pub fn register_builtins(&mut self) {
+        for declaration in builtin_declarations() {
+            if !self.contains(&declaration.name) {
+                self.declarations.push(declaration);
+            }
+        }
+    }
+
+    /// Resolve `use_tools` into tool names. Items can be tool names, `mapping_tools` aliases,
+    /// `<mcp-server>:*`, `builtin` or `builtin:<name>`. Built-in tools are only selected explicitly.
+    pub fn select_names(
+        &self,
+        use_tools: &str,
+        mapping_tools: &IndexMap<String, String>,
+    ) -> HashSet<String> {
+        let mut tool_names: HashSet<String> = Default::default();
+        if use_tools == "all" {
+            tool_names.extend(
+                self.declarations
+                    .iter()
+                    .filter(|v| !v.builtin)
+                    .map(|v| v.name.clone()),
+            );
+            return tool_names;
+        }
+        for item in use_tools.split(',') {
+            let item = item.trim();
+            match mapping_tools.get(item) {
+                Some(values) => {
+                    for value in values.split(',') {
+                        self.select_name(value.trim(), &mut tool_names);
+                    }
+                }
+                None => self.select_name(item, &mut tool_names),
+            }
+        }
+        tool_names
+    }
+
+    fn select_name(&self, item: &str, tool_names: &mut HashSet<String>) {
+        if item == BUILTIN_TOOLSET {
+            tool_names.extend(self.builtin_names());
+        } else if let Some(name) = item.strip_prefix(BUILTIN_PREFIX) {
+            if self.find(name).is_some_and(|v| v.builtin) {
+                tool_names.insert(name.to_string());
+            }
+        } else if let Some(server) = item.strip_suffix(":*") {
+            tool_names.extend(self.mcp_names(server));
+        } else if self.find(item).is_some_and(|v| !v.builtin) {
+            tool_names.insert(item.to_string());
+        }
+    }
+
+    pub fn builtin_names(&self) -> Vec<String> {
+        self.declarations
+            .iter()
+            .filter(|v| v.builtin)
+            .map(|v| v.name.clone())
+            .collect()
+    }
+
+    pub fn mcp_names(&self, server: &str) -> Vec<String> {
+        self.declarations
+            .iter()
+            .filter(|v| matches!(&v.mcp, Some((name, _)) if name == server))
+            .map(|v| v.name.clone())
+            .collect()
+    }
+
+    pub fn find_mcp(&self, name: &str) -> Option<(Arc<McpClient>, String)> {
+        let (server_name, tool_name) = self.find(name)?.mcp.as_ref()?;
+        let client = self.mcp_clients.get(server_name)?;
+        Some((client.clone(), tool_name.clone()))
+    }
+
+    pub fn find(&self, name: &str) -> Option<&FunctionDeclaration> {
+        self.declarations.iter().find(|v| v.name == name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.declarations.iter().any(|v| v.name == name)
+    }
+
+    pub fn declarations(&self) -> &[FunctionDeclaration] {
+        &self.declarations
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.declarations.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    pub description: String,
+    pub parameters: JsonSchema,
+    #[serde(skip_serializing, default)]
+    pub agent: bool,
+    #[serde(skip)]
+    pub mcp: Option<(String, String)>,
+    #[serde(skip)]
+    pub builtin: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonSchema {
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub type_value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub properties: Option<IndexMap<String, JsonSchema>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub items: Option<Box<JsonSchema>>,
+    #[serde(rename = "anyOf", skip_serializing_if = "Option::is_none")]
+    pub any_of: Option<Vec<JsonSchema>>,
+    #[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
+    pub enum_value: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub required: Option<Vec<String>>,
+}
+
+impl JsonSchema {
+    pub fn is_empty_properties(&self) -> bool {
+        match &self.properties {
+            Some(v) => v.is_empty(),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ToolCall {
+    pub name: String,
+    pub arguments: Value,
+    pub id: Option<String>,
+}
+
+impl ToolCall {
+    pub fn dedup(calls: Vec<Self>) -> Vec<Self> {
+        let mut new_calls = vec![];
+        let mut seen_ids = HashSet::new();
+
+        for call in calls.into_iter().rev() {
+            if let Some(id) = &call.id {
+                if !seen_ids.contains(id) {
+                    seen_ids.insert(id.clone());
+                    new_calls.push(call);
+                }
+            } else {
+                new_calls.push(call);
+            }
+        }
+
+        new_calls.reverse();
+        new_calls
+    }
+
+    pub fn new(name: String, arguments: Value, id: Option<String>) -> Self {
+        Self {
+            name,
+            arguments,
+            id,
+        }
+    }
+
+    /// Returns whether the call may run, "ask" becomes a deny when nobody can answer.
+    pub fn check_policy(&self, config: &GlobalConfig) -> Result<bool> {
+        let (policy, serve) = {
+            let config = config.read();
+            (
+                config.tool_policy(&self.name),
+                config.working_mode.is_serve(),
+            )
+        };
+        let interactive = *IS_STDIN_TERMINAL && !serve;
+        let allowed = match policy {
+            ToolPolicy::Allow => true,
+            ToolPolicy::Deny => false,
+            ToolPolicy::Ask if !interactive => false,
+            ToolPolicy::Ask => {
+                let options = vec![
+                    "Allow",
+                    "Deny",
+                    "Always allow in this session",
+                    "Always deny in this session",
+                ];
+                let message = format!("Allow the call {} {}?", self.name, self.arguments);
+                let answer = Select::new(&message, options).prompt()?;
+                let allowed = answer.starts_with("Allow") || answer.starts_with("Always allow");
+                if answer.starts_with("Always") {
+                    config
+                        .write()
+                        .tool_policy_decisions
+                        .insert(self.name.clone(), allowed);
+                }
+                allowed
+            }
+        };
+        if !allowed && serve {
+            info!("Deny call {} {}", self.name, self.arguments);
+        } else if !allowed && *IS_STDOUT_TERMINAL {
+            println!(
+                "{}",
+                dimmed_text(&format!("Deny call {} {}", self.name, self.arguments))
+            );
+        }
+        Ok(allowed)
+    }
+
+    pub async fn eval(
+        &self,
+        config: &GlobalConfig,
+        quiet: bool,
+        abort_signal: &AbortSignal,
+        on_progress: Option<ProgressFn>,
+    ) -> Result<Value> {
+        let function_name = self.name.clone();
+        let (call_name, cmd_name, mut cmd_args, envs, mcp, builtin) = {
+            let config = config.read();
+            let mcp = config.functions.find_mcp(&function_name);
+            let builtin = config
+                .functions
+                .find(&function_name)
+                .is_some_and(|v| v.builtin);
+            match &config.agent {
+                Some(agent) => match agent.functions().find(&function_name) {
+                    Some(function) => {
+                        let agent_name = agent.name().to_string();
+                        if function.agent {
+                            (
+                                format!("{agent_name}-{function_name}"),
+                                agent_name,
+                                vec![function_name],
+                                agent.variable_envs(),
+                                None,
+                                false,
+                            )
+                        } else {
+                            (
+                                function_name.clone(),
+                                function_name,
+                                vec![],
+                                Default::default(),
+                                None,
+                                false,
+                            )
+                        }
+                    }
+                    None if mcp.is_some() || builtin => (
+                        function_name.clone(),
+                        function_name,
+                        vec![],
+                        Default::default(),
+                        mcp,
+                        builtin,
+                    ),
+                    None => bail!("Unexpected call: {function_name} {}", self.arguments),
+                },
+                None => match config.functions.contains(&function_name) {
+                    true => (
+                        function_name.clone(),
+                        function_name,
+                        vec![],
+                        Default::default(),
+                        mcp,
+                        builtin,
+                    ),
+                    false => bail!("Unexpected call: {function_name} {}", self.arguments),
+                },
+            }
+        };
+        let json_data = if self.arguments.is_object() {
+            self.arguments.clone()
+        } else if let Some(arguments) = self.arguments.as_str() {
+            let arguments: Value = serde_json::from_str(arguments).map_err(|_| {
+                anyhow!("The call '{call_name}' has invalid arguments: {arguments}")
+            })?;
+            arguments
+        } else {
+            bail!(
+                "The call '{call_name}' has invalid arguments: {}",
+                self.arguments
+            );
+        };
+
+        if builtin {
+            if *IS_STDOUT_TERMINAL && !quiet {
+                println!("{}", dimmed_text(&format!("Call {call_name} {json_data}")));
+            }
+            return match eval_builtin(config, &call_name, &json_data, abort_signal).await {
+                Err(err) if !abort_signal.aborted() => Ok(json!({ "error": format!("{err:#}") })),
+                ret => ret,
+            };
+        }
+
+        if let Some((client, tool_name)) = mcp {
+            if *IS_STDOUT_TERMINAL && !quiet {
+                println!("{}", dimmed_text(&format!("Call {call_name} {json_data}")));
+            }
+            let call = client.call_tool(&tool_name, json_data);
+            let timeout = config.read().tool_timeout(&self.name);
+            return tokio::select! {
+                ret = async {
+                    match timeout {
+                        Some(timeout) => tokio::time::timeout(Duration::from_secs(timeout), call)
+                            .await
+                            .map_err(|_| {
+                                anyhow!("Tool call {call_name} timed out after {timeout}s")
+                            })?,
+                        None => call.await,
+                    }
+                } => ret,
+                _ = wait_abort_signal(abort_signal) => bail!("Aborted."),
+            };
+        }
+
+        cmd_args.push(json_data.to_string());
+
+        let mut progress_rx = None;
+        let on_progress = match on_progress {
+            None if !quiet && *IS_STDOUT_TERMINAL => {
+                let (tx, rx) = unbounded_channel();
+                let _ = tx.send(SseEvent::Progress(format!(
+                    "Call {cmd_name} {}",
+                    cmd_args.join(" ")
+                )));
+                progress_rx = Some(rx);
+                let on_progress: ProgressFn = Arc::new(move |line: &str| {
+                    let _ = tx.send(SseEvent::Progress(format!("  {line}")));
+                });
+                Some(on_progress)
+            }
+            on_progress => on_progress,
+        };
+        let options = {
+            let config = config.read();
+            RunOptions {
+                quiet,
+                sandbox: config.tool_sandbox(&self.name),
+                timeout: config.tool_timeout(&self.name),
+                abort_signal: abort_signal.clone(),
+                on_progress,
+            }
+        };
+        let render = async {
+            match progress_rx {
+                Some(rx) => render_stream(rx, config, abort_signal.clone()).await,
+                None => Ok(()),
+            }
+        };
+        let run = async {
+            tokio::task::spawn_blocking(move || {
+                run_llm_function_ext(cmd_name, cmd_args, envs, options)
+            })
+            .await
+        };
+        // Polled first, the render prints the call before the function starts
+        let (rendered, output) = tokio::join!(render, run);
+        rendered?;
+        let output = output??;
+        let output = match output {
+            Some(contents) => serde_json::from_str(&contents)
+                .ok()
+                .unwrap_or_else(|| json!({"output": contents})),
+            None => Value::Null,
+        };
+
+        Ok(output)
+    }
+}
+
+pub fn run_llm_function(
+    cmd_name: String,
+    cmd_args: Vec<String>,
+    envs: HashMap<String, String>,
+    sandbox: Option<SandboxConfig>,
+) -> Result<Option<String>> {
+    let options = RunOptions {
+        quiet: false,
+        sandbox,
+        timeout: None,
+        abort_signal: create_abort_signal(),
+        on_progress: None,
+    };
+    run_llm_function_ext(cmd_name, cmd_args, envs, options)
+}
+
+struct RunOptions {
+    /// Run detached from the terminal and capture the output
+    quiet: bool,
+    sandbox: Option<SandboxConfig>,
+    /// In seconds, a timeout of the sandbox takes precedence
+    timeout: Option<u64>,
+    abort_signal: AbortSignal,
+    /// Receives the lines the function writes to `LLM_PROGRESS`
+    on_progress: Option<ProgressFn>,
+}
+
+fn run_llm_function_ext(
+    cmd_name: String,
+    cmd_args: Vec<String>,
+    mut envs: HashMap<String, String>,
+    options: RunOptions,
+) -> Result<Option<String>> {
+    let RunOptions {
+        quiet,
+        sandbox,
+        timeout,
+        abort_signal,
+        on_progress,
+    } = options;
+    let prompt = format!("Call {cmd_name} {}", cmd_args.join(" "));
+
+    let mut bin_dirs: Vec<PathBuf> = vec![];
+    if cmd_args.len() > 1 {
+        let dir = Config::agent_functions_dir(&cmd_name).join("bin");
+        if dir.exists() {
+            bin_dirs.push(dir);
+        }
+    }
+    bin_dirs.push(Config::functions_bin_dir());
+    let current_path = std::env::var("PATH").context("No PATH environment variable")?;
+    let prepend_path = bin_dirs
+        .iter()
+        .map(|v| format!("{}{PATH_SEP}", v.display()))
+        .collect::<Vec<_>>()
+        .join("");
+    envs.insert("PATH".into(), format!("{prepend_path}{current_path}"));
+
+    let workdir = match &sandbox {
+        Some(_) => {
+            let dir = temp_file("-sandbox-", "");
+            fs::create_dir_all(&dir).context("Failed to create the sandbox directory")?;
+            Some(dir)
+        }
+        None => None,
+    };
+    let (temp_file, progress_file) = match &workdir {
+        Some(dir) => (dir.join("output"), dir.join("progress")),
+        None => (temp_file("-eval-", ""), temp_file("-progress-", "")),
+    };
+    envs.insert("LLM_OUTPUT".into(), temp_file.display().to_string());
+    envs.insert("LLM_PROGRESS".into(), progress_file.display().to_string());
+
+    #[cfg(windows)]
+    let cmd_name = polyfill_cmd_name(&cmd_name, &bin_dirs);
+    let ret = (|| {
+        let mut command = match (&sandbox, &workdir) {
+            (Some(sandbox), Some(workdir)) => {
+                sandbox.command(&cmd_name, &cmd_args, envs, workdir)?
+            }
+            _ => {
+                let mut command = Command::new(&cmd_name);
+                command.args(&cmd_args).envs(envs);
+                command
+            }
+        };
+        let timeout = sandbox
+            .as_ref()
+            .and_then(|v| v.timeout)
+            .or(timeout)
+            .map(Duration::from_secs);
+        if !quiet && on_progress.is_none() && *IS_STDOUT_TERMINAL {
+            println!("{}", dimmed_text(&prompt));
+        }
+        let mut progress_reader = ProgressReader::new(&progress_file);
+        let on_tick = || {
+            for line in progress_reader.read_lines() {
+                match &on_progress {
+                    Some(on_progress) => on_progress(&line),
+                    None if !quiet && *IS_STDOUT_TERMINAL => {
+                        println!("{}", dimmed_text(&format!("  {line}")))
+                    }
+                    None => {}
+                }
+            }
+        };
+        let capture = quiet.then_some(MAX_CAPTURED_OUTPUT);
+        let status = run_command_abortable(&mut command, capture, timeout, &abort_signal, on_tick)
+            .map_err(|err| anyhow!("Unable to run {cmd_name}, {err}"))?;
+        let (exit_code, stderr) = match status {
+            CommandStatus::Exited(exit_code, _, stderr) => (exit_code, stderr),
+            CommandStatus::TimedOut => bail!(
+                "Tool call {cmd_name} timed out after {}s",
+                timeout.unwrap_or_default().as_secs()
+            ),
+            CommandStatus::Aborted => bail!("Aborted."),
+        };
+        if exit_code != 0 {
+            if quiet {
+                bail!("Tool call {cmd_name} failed, {}", stderr.trim());
+            }
+            bail!("Tool call exit with {exit_code}");
+        }
+        let mut output = None;
+        if temp_file.exists() {
+            let contents =
+                fs::read_to_string(&temp_file).context("Failed to retrieve tool call output")?;
+            if !contents.is_empty() {
+                output = Some(match &sandbox {
+                    Some(sandbox) => sandbox.truncate_output(contents),
+                    None => contents,
+                });
+            }
+        };
+        Ok(output)
+    })();
+    match workdir {
+        Some(workdir) => {
+            let _ = fs::remove_dir_all(workdir);
+        }
+        None => {
+            let _ = fs::remove_file(progress_file);
+        }
+    }
+    ret
+}
+
+/// Tails the progress file of a running function.
+struct ProgressReader<'a> {
+    path: &'a Path,
+    offset: u64,
+    pending: String,
+}
+
+impl<'a> ProgressReader<'a> {
+    fn new(path: &'a Path) -> Self {
+        Self {
+            path,
+            offset: 0,
+            pending: String::new(),
+        }
+    }
+
+    fn read_lines(&mut self) -> Vec<String> {
+        let Ok(mut file) = fs::File::open(self.path) else {
+            return vec![];
+        };
+        let mut buf = vec![];
+        if file.seek(SeekFrom::Start(self.offset)).is_err() || file.read_to_end(&mut buf).is_err() {
+            return vec![];
+        }
+        self.offset += buf.len() as u64;
+        self.pending.push_str(&String::from_utf8_lossy(&buf));
+        let Some(idx) = self.pending.rfind('\n') else {
+            return vec![];
+        };
+        let rest = self.pending.split_off(idx + 1);
+        let lines = std::mem::replace(&mut self.pending, rest);
+        lines
+            .lines()
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+            .map(|v| v.to_string())
+            .collect()
+    }
+}
+
+#[cfg(windows)]
+fn polyfill_cmd_name<T: AsRef<Path>>(cmd_name: &str, bin_dir: &[T]) -> String {
+    let cmd_name = cmd_name.to_string();
+    if let Ok(exts) = std::env::var("PATHEXT") {
+        for name in exts.split(';').map(|ext| format!("{cmd_name}{ext}")) {
+            for dir in bin_dir {
+                let path = dir.as_ref().join(&name);
+                if path.exists() {
+                    return name.to_string();
+                }
+            }
+        }
+    }
+    cmd_name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn declaration(name: &str, mcp: Option<&str>, builtin: bool) -> FunctionDeclaration {
+        FunctionDeclaration {
+            name: name.into(),
+            description: String::new(),
+            parameters: serde_json::from_value(json!({ "type": "object" })).unwrap(),
+            agent: false,
+            mcp: mcp.map(|v| (v.to_string(), name.to_string())),
+            builtin,
+        }
+    }
+
+    #[test]
+    fn test_select_names() {
+        let functions = Functions {
+            declarations: vec![
+                declaration("fs_cat", None, false),
+                declaration("fs_ls", None, false),
+                declaration("jira__search", Some("jira"), false),
+                declaration("jira__get", Some("jira"), false),
+                declaration("read_file", None, true),
+            ],
+            ..Default::default()
+        };
+        let mapping_tools: IndexMap<String, String> =
+            [("fs".to_string(), "fs_cat,fs_ls".to_string())].into();
+        let names = |use_tools: &str| {
+            let mut names: Vec<String> = functions
+                .select_names(use_tools, &mapping_tools)
+                .into_iter()
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names("fs"), ["fs_cat", "fs_ls"]);
+        assert_eq!(names("jira:*,missing"), ["jira__get", "jira__search"]);
+        assert_eq!(names("builtin,fs_ls"), ["fs_ls", "read_file"]);
+        assert_eq!(names("read_file"), Vec::<String>::new());
+        assert_eq!(names("builtin:read_file"), ["read_file"]);
+        assert_eq!(
+            names("all"),
+            ["fs_cat", "fs_ls", "jira__get", "jira__search"]
+        );
+    }
+
+    #[test]
+    fn test_pending_mcp_servers() {
+        let server = |url: &str| McpServerConfig::Http {
+            url: url.into(),
+            headers: Default::default(),
+        };
+        let servers: IndexMap<String, McpServerConfig> = [
+            ("jira".to_string(), server("http://jira")),
+            ("git".to_string(), server("http://git")),
+        ]
+        .into();
+        let mapping_tools: IndexMap<String, String> =
+            [("issues".to_string(), "fs_cat,jira:*".to_string())].into();
+        let mut functions = Functions::default();
+        let pending = |functions: &Functions, use_tools: &str| -> Vec<String> {
+            functions
+                .pending_mcp_servers(use_tools, &mapping_tools, &servers)
+                .into_keys()
+                .collect()
+        };
+        assert!(pending(&functions, "fs_cat").is_empty());
+        assert_eq!(pending(&functions, "issues"), ["jira"]);
+        assert_eq!(pending(&functions, "git__log"), ["git"]);
+        assert_eq!(pending(&functions, "all"), ["jira", "git"]);
+        functions.mcp_servers.insert("jira".into());
+        assert_eq!(pending(&functions, "all"), ["git"]);
+    }
+}
@@ -2,11 +2,13 @@ mod cli;
 mod client;
 mod config;
 mod function;
+mod hooks;
+mod mcp;
 mod rag;
 mod render;
 mod repl;
+mod sandbox;
 mod serve;
-mod hooks;
 #[macro_use]
 mod utils;
 
@@ -18,14 +20,15 @@ use crate::client::{
     call_chat_completions, call_chat_completions_streaming, list_models, ModelType,
 };
 use crate::config::{
-    ensure_parent_exists, list_agents, load_env_file, macro_execute, Config, GlobalConfig, Input,
-    WorkingMode, CODE_ROLE, EXPLAIN_SHELL_ROLE, SHELL_ROLE, TEMP_SESSION_NAME,
+    ensure_parent_exists, list_agents, load_env_file, macro_execute, render_history_hits,
+    search_history, select_history_session, Config, GlobalConfig, Input, WorkingMode, CODE_ROLE,
+    EXPLAIN_SHELL_ROLE, SHELL_ROLE, TEMP_SESSION_NAME,
 };
 use crate::render::render_error;
 use crate::repl::Repl;
 use crate::utils::*;
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 use inquire::validator::Validation;
 use inquire::Text;
@@ -39,7 +42,7 @@ async fn main() -> Result<()> {
     load_env_file()?;
     let cli = Cli::parse();
     let text = cli.text()?;
-    let working_mode = if cli.serve.is_some() {
+    let working_mode = if cli.serve.is_some() || cli.mcp {
         WorkingMode::Serve
     } else if text.is_none() && cli.file.is_empty() {
         WorkingMode::Repl
@@ -53,8 +56,9 @@ async fn main() -> Result<()> {
         || cli.list_agents
         || cli.list_rags
         || cli.list_macros
-        || cli.list_sessions;
-    setup_logger(working_mode.is_serve())?;
+        || cli.list_sessions
+        || cli.usage;
+    setup_logger(cli.serve.is_some())?;
     let config = Arc::new(RwLock::new(Config::init(working_mode, info_flag).await?));
     if let Err(err) = run(config, cli, text).await {
         render_error(err);
@@ -72,6 +76,10 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
     }
 
     if cli.list_models {
+        Config::load_ollama_models(&config, None).await;
+        for (name, _) in config.read().list_model_groups() {
+            println!("{name}");
+        }
         for model in list_models(&config.read(), ModelType::Chat) {
             println!("{}", model.id());
         }
@@ -97,6 +105,11 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
         println!("{macros}");
         return Ok(());
     }
+    if cli.usage {
+        let usage = config.read().usage_info()?;
+        print!("{usage}");
+        return Ok(());
+    }
 
     if cli.dry_run {
         config.write().dry_run = true;
@@ -143,9 +156,31 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
         println!("{sessions}");
         return Ok(());
     }
+    if let Some(query) = &cli.search_history {
+        let hits = search_history(&config.read(), query)?;
+        println!("{}", render_history_hits(&hits, query));
+        if *IS_STDOUT_TERMINAL && stdin().is_terminal() && config.read().session.is_none() {
+            if let Some(name) = select_history_session(&hits)? {
+                config.write().use_session(Some(&name))?;
+                return start_interactive(&config).await;
+            }
+        }
+        return Ok(());
+    }
     if let Some(model_id) = &cli.model {
+        Config::load_ollama_models(&config, Some(model_id)).await;
         config.write().set_model(model_id)?;
     }
+    if let Some(id) = &cli.replay {
+        return Config::replay_chat(&config, id, abort_signal.clone()).await;
+    }
+    if let Some(path) = &cli.schema {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read schema file '{path}'"))?;
+        let schema = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid schema file '{path}'"))?;
+        config.write().response_format = Some(schema);
+    }
     if cli.no_stream {
         config.write().stream = false;
     }
@@ -161,8 +196,12 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
         return Ok(());
     }
     if let Some(addr) = cli.serve {
+        Config::load_ollama_models(&config, None).await;
         return serve::run(config, addr).await;
     }
+    if cli.mcp {
+        return mcp::serve(config).await;
+    }
     let is_repl = config.read().working_mode.is_repl();
     if cli.rebuild_rag {
         Config::rebuild_rag(&config, abort_signal.clone()).await?;
@@ -201,16 +240,16 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
 #[async_recursion::async_recursion]
 async fn start_directive(
     config: &GlobalConfig,
-    input: Input,
+    mut input: Input,
     code_mode: bool,
     abort_signal: AbortSignal,
 ) -> Result<()> {
     let client = input.create_client()?;
     let extract_code = !*IS_STDOUT_TERMINAL && code_mode;
     config.write().before_chat_completion(&input)?;
-    let (output, tool_results) = if !input.stream() || extract_code {
+    let (output, thinking, tool_results) = if !input.stream() || extract_code {
         call_chat_completions(
-            &input,
+            &mut input,
             true,
             extract_code,
             client.as_ref(),
@@ -218,7 +257,7 @@ async fn start_directive(
         )
         .await?
     } else {
-        call_chat_completions_streaming(&input, client.as_ref(), abort_signal.clone()).await?
+        call_chat_completions_streaming(&mut input, client.as_ref(), abort_signal.clone()).await?
     };
     config
         .write()
@@ -227,7 +266,7 @@ async fn start_directive(
     if !tool_results.is_empty() {
         start_directive(
             config,
-            input.merge_tool_results(output, tool_results),
+            input.merge_tool_results(output, thinking, tool_results),
             code_mode,
             abort_signal,
         )
@@ -239,6 +278,7 @@ async fn start_directive(
 }
 
 async fn start_interactive(config: &GlobalConfig) -> Result<()> {
+    Config::load_ollama_models(config, None).await;
     let mut repl: Repl = Repl::init(config)?;
     repl.run().await
 }
@@ -252,8 +292,14 @@ async fn shell_execute(
 ) -> Result<()> {
     let client = input.create_client()?;
     config.write().before_chat_completion(&input)?;
-    let (eval_str, _) =
-        call_chat_completions(&input, false, true, client.as_ref(), abort_signal.clone()).await?;
+    let (eval_str, _, _) = call_chat_completions(
+        &mut input,
+        false,
+        true,
+        client.as_ref(),
+        abort_signal.clone(),
+    )
+    .await?;
 
     config
         .write()
@@ -305,17 +351,17 @@ async fn shell_execute(
                 }
                 "d" => {
                     let role = config.read().retrieve_role(EXPLAIN_SHELL_ROLE)?;
-                    let input = Input::from_str(config, &eval_str, Some(role));
+                    let mut input = Input::from_str(config, &eval_str, Some(role));
                     if input.stream() {
                         call_chat_completions_streaming(
-                            &input,
+                            &mut input,
                             client.as_ref(),
                             abort_signal.clone(),
                         )
                         .await?;
                     } else {
                         call_chat_completions(
-                            &input,
+                            &mut input,
                             true,
                             false,
                             client.as_ref(),
@@ -370,15 +416,22 @@ fn setup_logger(is_serve: bool) -> Result<()> {
         return Ok(());
     }
     let crate_name = env!("CARGO_CRATE_NAME");
-    let log_filter = match std::env::var(get_env_name("log_filter")) {
-        Ok(v) => v,
+    let log_filters = match std::env::var(get_env_name("log_filter")) {
+        Ok(v) => vec![v],
         Err(_) => match is_serve {
-            true => format!("{crate_name}::serve"),
-            false => crate_name.into(),
+            // The tools run by the server log their calls
+            true => vec![
+                format!("{crate_name}::serve"),
+                format!("{crate_name}::function"),
+            ],
+            false => vec![crate_name.into()],
         },
     };
-    let config = ConfigBuilder::new()
-        .add_filter_allow(log_filter)
+    let mut config = ConfigBuilder::new();
+    for log_filter in log_filters {
+        config.add_filter_allow(log_filter);
+    }
+    let config = config
         .set_time_format_custom(format_description!(
             "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]Z"
         ))
@@ -0,0 +1,305 @@
+use super::*;
+
+use anyhow::{anyhow, bail, Context, Result};
+use parking_lot::Mutex;
+use std::{
+    process::Stdio,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    sync::Mutex as AsyncMutex,
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const SESSION_ID_HEADER: &str = "mcp-session-id";
+
+#[derive(Debug, Clone)]
+pub struct McpTool {
+    pub name: String,
+    pub description: String,
+    pub input_schema: Value,
+}
+
+#[derive(Debug)]
+pub struct McpClient {
+    name: String,
+    transport: Transport,
+    next_id: AtomicU64,
+}
+
+#[derive(Debug)]
+enum Transport {
+    Stdio(AsyncMutex<StdioTransport>),
+    Http(HttpTransport),
+}
+
+#[derive(Debug)]
+struct StdioTransport {
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+#[derive(Debug)]
+struct HttpTransport {
+    client: reqwest::Client,
+    url: String,
+    headers: IndexMap<String, String>,
+    session_id: Mutex<Option<String>>,
+}
+
+impl McpClient {
+    pub async fn connect(name: &str, config: &McpServerConfig) -> Result<Self> {
+        let transport = match config {
+            McpServerConfig::Stdio {
+                command,
+                args,
+                env,
+                cwd,
+            } => {
+                let mut cmd = Command::new(command);
+                cmd.args(args)
+                    .envs(env)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::null())
+                    .kill_on_drop(true);
+                if let Some(cwd) = cwd {
+                    cmd.current_dir(cwd);
+                }
+                let mut child = cmd
+                    .spawn()
+                    .with_context(|| format!("Failed to spawn '{command}'"))?;
+                let stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin"))?;
+                let stdout = child.stdout.take().ok_or_else(|| anyhow!("No stdout"))?;
+                Transport::Stdio(AsyncMutex::new(StdioTransport {
+                    _child: child,
+                    stdin,
+                    stdout: BufReader::new(stdout),
+                }))
+            }
+            McpServerConfig::Http { url, headers } => {
+                let client = reqwest::Client::builder()
+                    .connect_timeout(CONNECT_TIMEOUT)
+                    .build()?;
+                Transport::Http(HttpTransport {
+                    client,
+                    url: url.clone(),
+                    headers: headers.clone(),
+                    session_id: Mutex::new(None),
+                })
+            }
+        };
+        let client = Self {
+            name: name.to_string(),
+            transport,
+            next_id: AtomicU64::new(1),
+        };
+        let params = json!({
+            "protocolVersion": MCP_PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": {
+                "name": env!("CARGO_CRATE_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+        });
+        tokio::time::timeout(CONNECT_TIMEOUT, client.request("initialize", params))
+            .await
+            .map_err(|_| anyhow!("Timeout while initializing"))??;
+        client.notify("notifications/initialized").await?;
+        Ok(client)
+    }
+
+    pub async fn list_tools(&self) -> Result<Vec<McpTool>> {
+        let mut tools = vec![];
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = self.request("tools/list", params).await?;
+            if let Some(list) = result["tools"].as_array() {
+                for tool in list {
+                    let Some(name) = tool["name"].as_str() else {
+                        continue;
+                    };
+                    tools.push(McpTool {
+                        name: name.to_string(),
+                        description: tool["description"].as_str().unwrap_or_default().to_string(),
+                        input_schema: tool["inputSchema"].clone(),
+                    });
+                }
+            }
+            match result["nextCursor"].as_str() {
+                Some(v) if !v.is_empty() => cursor = Some(v.to_string()),
+                _ => break,
+            }
+        }
+        Ok(tools)
+    }
+
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> Result<Value> {
+        let params = json!({
+            "name": name,
+            "arguments": arguments,
+        });
+        let result = self.request("tools/call", params).await?;
+        let texts: Vec<String> = result["content"]
+            .as_array()
+            .map(|list| {
+                list.iter()
+                    .filter_map(|item| match item["type"].as_str() {
+                        Some("text") => item["text"].as_str().map(|v| v.to_string()),
+                        Some("resource") => {
+                            item["resource"]["text"].as_str().map(|v| v.to_string())
+                        }
+                        Some(kind) => Some(format!("[{kind}]")),
+                        None => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let text = texts.join("\n");
+        if result["isError"].as_bool().unwrap_or_default() {
+            return Ok(json!({ "error": text }));
+        }
+        if let Some(value) = result.get("structuredContent").filter(|v| !v.is_null()) {
+            return Ok(value.clone());
+        }
+        if text.is_empty() {
+            return Ok(Value::Null);
+        }
+        Ok(serde_json::from_str(&text).unwrap_or_else(|_| json!({ "output": text })))
+    }
+
+    pub async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let message = jsonrpc_request(id, method, params);
+        debug!("mcp {} request: {message}", self.name);
+        let response = match &self.transport {
+            Transport::Stdio(transport) => transport.lock().await.request(id, &message).await,
+            Transport::Http(transport) => transport.request(id, &message).await,
+        }
+        .with_context(|| format!("MCP server '{}' failed on '{method}'", self.name))?;
+        debug!("mcp {} response: {response}", self.name);
+        if let Some(error) = response.get("error") {
+            let message = error["message"].as_str().unwrap_or("Unknown error");
+            match error["code"].as_i64() {
+                Some(code) => bail!("{message} (code: {code})"),
+                None => bail!("{message}"),
+            }
+        }
+        Ok(response["result"].clone())
+    }
+
+    async fn notify(&self, method: &str) -> Result<()> {
+        let message = jsonrpc_notification(method);
+        match &self.transport {
+            Transport::Stdio(transport) => transport.lock().await.send(&message).await,
+            Transport::Http(transport) => transport.post(&message).await.map(|_| ()),
+        }
+    }
+}
+
+impl StdioTransport {
+    async fn send(&mut self, message: &Value) -> Result<()> {
+        let data = format!("{message}\n");
+        self.stdin.write_all(data.as_bytes()).await?;
+        self.stdin.flush().await?;
+        Ok(())
+    }
+
+    async fn request(&mut self, id: u64, message: &Value) -> Result<Value> {
+        self.send(message).await?;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.stdout.read_line(&mut line).await? == 0 {
+                bail!("Server exited unexpectedly");
+            }
+            let Ok(value) = serde_json::from_str::<Value>(line.trim()) else {
+                continue;
+            };
+            if value.get("method").is_some() {
+                if let Some(request_id) = value.get("id") {
+                    // Server-initiated requests such as `ping`
+                    let reply = json!({
+                        "jsonrpc": "2.0",
+                        "id": request_id,
+                        "result": {},
+                    });
+                    self.send(&reply).await?;
+                }
+                continue;
+            }
+            if value["id"].as_u64() == Some(id) {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+impl HttpTransport {
+    async fn post(&self, message: &Value) -> Result<reqwest::Response> {
+        let mut builder = self
+            .client
+            .post(&self.url)
+            .header("Accept", "application/json, text/event-stream")
+            .json(message);
+        for (key, value) in &self.headers {
+            builder = builder.header(key, value);
+        }
+        if let Some(session_id) = self.session_id.lock().clone() {
+            builder = builder.header(SESSION_ID_HEADER, session_id);
+        }
+        let res = builder.send().await?;
+        let status = res.status();
+        if !status.is_success() {
+            let text = res.text().await.unwrap_or_default();
+            bail!(
+                "Invalid response, status: {}, data: {text}",
+                status.as_u16()
+            );
+        }
+        if let Some(session_id) = res
+            .headers()
+            .get(SESSION_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+        {
+            *self.session_id.lock() = Some(session_id.to_string());
+        }
+        Ok(res)
+    }
+
+    async fn request(&self, id: u64, message: &Value) -> Result<Value> {
+        let res = self.post(message).await?;
+        let is_sse = res
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.starts_with("text/event-stream"))
+            .unwrap_or_default();
+        let text = res.text().await?.replace("\r\n", "\n");
+        if !is_sse {
+            return Ok(serde_json::from_str(&text)?);
+        }
+        for event in text.split("\n\n") {
+            let data = event
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(|v| v.trim_start())
+                .collect::<Vec<_>>()
+                .join("\n");
+            if let Ok(value) = serde_json::from_str::<Value>(&data) {
+                if value["id"].as_u64() == Some(id) && value.get("method").is_none() {
+                    return Ok(value);
+                }
+            }
+        }
+        bail!("No response in event stream")
+    }
+}
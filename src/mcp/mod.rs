@@ -0,0 +1,117 @@
+mod client;
+mod server;
+
+pub use self::client::McpClient;
+pub use self::server::serve;
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+pub const MCP_PROTOCOL_VERSION: &str = "2025-03-26";
+pub const MCP_TOOL_SEPARATOR: &str = "__";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum McpServerConfig {
+    Stdio {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        env: IndexMap<String, String>,
+        cwd: Option<String>,
+    },
+    Http {
+        url: String,
+        #[serde(default)]
+        headers: IndexMap<String, String>,
+    },
+}
+
+pub fn mcp_tool_name(server: &str, tool: &str) -> String {
+    format!("{server}{MCP_TOOL_SEPARATOR}{tool}")
+}
+
+fn jsonrpc_request(id: u64, method: &str, params: Value) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": method,
+        "params": params,
+    })
+}
+
+fn jsonrpc_notification(method: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": method,
+    })
+}
+
+/// Convert a JSON schema from an MCP server into something `JsonSchema` can hold.
+pub fn normalize_json_schema(value: &mut Value) {
+    let Some(map) = value.as_object_mut() else {
+        return;
+    };
+    if let Some(types) = map.get("type").and_then(|v| v.as_array()) {
+        let type_value = types
+            .iter()
+            .filter_map(|v| v.as_str())
+            .find(|v| *v != "null")
+            .unwrap_or("string")
+            .to_string();
+        map.insert("type".into(), type_value.into());
+    }
+    if let Some(values) = map.get("enum").and_then(|v| v.as_array()) {
+        if values.iter().any(|v| !v.is_string()) {
+            map.remove("enum");
+        }
+    }
+    if let Some(properties) = map.get_mut("properties").and_then(|v| v.as_object_mut()) {
+        for value in properties.values_mut() {
+            normalize_json_schema(value);
+        }
+    }
+    if let Some(items) = map.get_mut("items") {
+        normalize_json_schema(items);
+    }
+    if let Some(list) = map.get_mut("anyOf").and_then(|v| v.as_array_mut()) {
+        for value in list {
+            normalize_json_schema(value);
+        }
+    }
+    if map.get("type").and_then(|v| v.as_str()) == Some("object") && !map.contains_key("properties")
+    {
+        map.insert("properties".into(), json!({}));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_json_schema() {
+        let mut value = json!({
+            "type": "object",
+            "properties": {
+                "limit": { "type": ["integer", "null"] },
+                "level": { "type": "integer", "enum": [1, 2] },
+                "tags": { "type": "array", "items": { "type": "object" } }
+            }
+        });
+        normalize_json_schema(&mut value);
+        assert_eq!(
+            value,
+            json!({
+                "type": "object",
+                "properties": {
+                    "limit": { "type": "integer" },
+                    "level": { "type": "integer" },
+                    "tags": { "type": "array", "items": { "type": "object", "properties": {} } }
+                }
+            })
+        );
+    }
+}
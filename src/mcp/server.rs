@@ -0,0 +1,440 @@
+use super::*;
+
+use crate::config::{macro_execute, Config, GlobalConfig, Input};
+use crate::function::Functions;
+use crate::rag::Rag;
+use crate::utils::{create_abort_signal, IS_STDOUT_TERMINAL};
+
+use anyhow::{anyhow, bail, Result};
+use parking_lot::RwLock;
+use std::{io::Write, sync::Arc};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, BufReader},
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+};
+
+const SUPPORTED_PROTOCOL_VERSIONS: [&str; 3] = ["2024-11-05", "2025-03-26", "2025-06-18"];
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+pub async fn serve(config: GlobalConfig) -> Result<()> {
+    let writer = take_stdout()?;
+    let server = Server::new(&config);
+    serve_io(server, BufReader::new(tokio::io::stdin()), writer).await?;
+    Ok(())
+}
+
+async fn serve_io<R, W>(server: Server, reader: R, mut writer: W) -> Result<W>
+where
+    R: AsyncBufRead + Unpin,
+    W: Write + Send + 'static,
+{
+    let server = Arc::new(server);
+    let (tx, mut rx) = unbounded_channel::<Value>();
+    let write_task = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            debug!("mcp serve response: {message}");
+            if writeln!(writer, "{message}")
+                .and_then(|_| writer.flush())
+                .is_err()
+            {
+                break;
+            }
+        }
+        writer
+    });
+
+    let mut lines = reader.lines();
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        debug!("mcp serve request: {line}");
+        let message: Value = match serde_json::from_str(line) {
+            Ok(v) => v,
+            Err(err) => {
+                let _ = tx.send(jsonrpc_error(Value::Null, PARSE_ERROR, &err.to_string()));
+                continue;
+            }
+        };
+        let (Some(id), Some(method)) = (message.get("id").cloned(), message["method"].as_str())
+        else {
+            continue;
+        };
+        let server = server.clone();
+        let method = method.to_string();
+        let params = message["params"].clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            server.handle(id, &method, params, &tx).await;
+        });
+    }
+    drop(tx);
+    let writer = write_task.await?;
+    Ok(writer)
+}
+
+#[derive(Debug, Clone)]
+enum Target {
+    Role(String),
+    Rag(String),
+    Macro(String),
+}
+
+struct Server {
+    config: Config,
+    tools: Vec<(String, Target, Value)>,
+}
+
+impl Server {
+    fn new(config: &GlobalConfig) -> Self {
+        let mut config = config.read().clone();
+        config.functions = Functions::default();
+        let mut tools = vec![];
+        for role in Config::all_roles() {
+            let description = match role.prompt().lines().find(|v| !v.trim().is_empty()) {
+                Some(line) => format!("Ask the role '{}': {}", role.name(), line.trim()),
+                None => format!("Ask the role '{}'", role.name()),
+            };
+            tools.push((
+                mcp_tool_name("role", &sanitize_name(role.name())),
+                Target::Role(role.name().to_string()),
+                json!({
+                    "description": description,
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "input": { "type": "string", "description": "The text to send to the role" }
+                        },
+                        "required": ["input"]
+                    }
+                }),
+            ));
+        }
+        for name in Config::list_rags() {
+            tools.push((
+                mcp_tool_name("rag", &sanitize_name(&name)),
+                Target::Rag(name.clone()),
+                json!({
+                    "description": format!("Search documents in the RAG '{name}'"),
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "query": { "type": "string", "description": "The search query" },
+                            "top_k": { "type": "integer", "description": "The number of documents to retrieve" }
+                        },
+                        "required": ["query"]
+                    }
+                }),
+            ));
+        }
+        for name in Config::list_macros() {
+            let Ok(macro_value) = Config::load_macro(&name) else {
+                continue;
+            };
+            tools.push((
+                mcp_tool_name("macro", &sanitize_name(&name)),
+                Target::Macro(name.clone()),
+                json!({
+                    "description": format!("Execute the macro: {}", macro_value.usage(&name)),
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "args": { "type": "string", "description": "The macro arguments" }
+                        }
+                    }
+                }),
+            ));
+        }
+        Self { config, tools }
+    }
+
+    async fn handle(&self, id: Value, method: &str, params: Value, tx: &UnboundedSender<Value>) {
+        let ret = match method {
+            "initialize" => Ok(self.initialize(&params)),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(self.list_tools()),
+            "tools/call" => self.call_tool(&params).await,
+            "prompts/list" => Ok(self.list_prompts()),
+            "prompts/get" => self.get_prompt(&params),
+            _ => {
+                let message = format!("Method not found: {method}");
+                let _ = tx.send(jsonrpc_error(id, METHOD_NOT_FOUND, &message));
+                return;
+            }
+        };
+        let message = match ret {
+            Ok(result) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "result": result,
+            }),
+            Err(err) => jsonrpc_error(id, INVALID_PARAMS, &err.to_string()),
+        };
+        let _ = tx.send(message);
+    }
+
+    fn initialize(&self, params: &Value) -> Value {
+        let protocol_version = params["protocolVersion"]
+            .as_str()
+            .filter(|v| SUPPORTED_PROTOCOL_VERSIONS.contains(v))
+            .unwrap_or(MCP_PROTOCOL_VERSION);
+        json!({
+            "protocolVersion": protocol_version,
+            "capabilities": {
+                "tools": {},
+                "prompts": {},
+            },
+            "serverInfo": {
+                "name": env!("CARGO_CRATE_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+        })
+    }
+
+    fn list_tools(&self) -> Value {
+        let tools: Vec<Value> = self
+            .tools
+            .iter()
+            .map(|(name, _, value)| {
+                json!({
+                    "name": name,
+                    "description": value["description"],
+                    "inputSchema": value["inputSchema"],
+                })
+            })
+            .collect();
+        json!({ "tools": tools })
+    }
+
+    async fn call_tool(&self, params: &Value) -> Result<Value> {
+        let name = params["name"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Missing tool name"))?;
+        let target = self
+            .tools
+            .iter()
+            .find(|(v, _, _)| v == name)
+            .map(|(_, target, _)| target.clone())
+            .ok_or_else(|| anyhow!("Unknown tool: {name}"))?;
+        let arguments = &params["arguments"];
+        let ret = match target {
+            Target::Role(name) => self.run_role(&name, arguments).await,
+            Target::Rag(name) => self.search_rag(&name, arguments).await,
+            Target::Macro(name) => self.run_macro(&name, arguments).await,
+        };
+        let (text, is_error) = match ret {
+            Ok(text) => (text, false),
+            Err(err) => (format!("{err:?}"), true),
+        };
+        Ok(json!({
+            "content": [{ "type": "text", "text": text }],
+            "isError": is_error,
+        }))
+    }
+
+    fn list_prompts(&self) -> Value {
+        let prompts: Vec<Value> = self
+            .tools
+            .iter()
+            .filter_map(|(name, target, value)| match target {
+                Target::Role(_) => Some(json!({
+                    "name": name,
+                    "description": value["description"],
+                    "arguments": [
+                        { "name": "input", "description": "The text to send to the role", "required": false }
+                    ],
+                })),
+                _ => None,
+            })
+            .collect();
+        json!({ "prompts": prompts })
+    }
+
+    fn get_prompt(&self, params: &Value) -> Result<Value> {
+        let name = params["name"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Missing prompt name"))?;
+        let role_name = match self.tools.iter().find(|(v, _, _)| v == name) {
+            Some((_, Target::Role(role_name), _)) => role_name,
+            _ => bail!("Unknown prompt: {name}"),
+        };
+        let text = params["arguments"]["input"].as_str().unwrap_or_default();
+        let config = self.create_config();
+        let role = config.read().retrieve_role(role_name)?;
+        let input = Input::from_str(&config, text, Some(role.clone()));
+        let messages: Vec<Value> = role
+            .build_messages(&input)
+            .into_iter()
+            .filter_map(|message| {
+                let text = message.content.to_text();
+                if text.is_empty() {
+                    return None;
+                }
+                let role = if message.role.is_assistant() {
+                    "assistant"
+                } else {
+                    "user"
+                };
+                Some(json!({
+                    "role": role,
+                    "content": { "type": "text", "text": text },
+                }))
+            })
+            .collect();
+        Ok(json!({
+            "description": format!("The role '{role_name}'"),
+            "messages": messages,
+        }))
+    }
+
+    async fn run_role(&self, name: &str, arguments: &Value) -> Result<String> {
+        let text = arguments["input"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Missing argument 'input'"))?;
+        let config = self.create_config();
+        let role = config.read().retrieve_role(name)?;
+        let input = Input::from_str(&config, text, Some(role));
+        input.fetch_chat_text().await
+    }
+
+    async fn search_rag(&self, name: &str, arguments: &Value) -> Result<String> {
+        let query = arguments["query"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Missing argument 'query'"))?;
+        let config = self.create_config();
+        let rag_path = config.read().rag_file(name);
+        let rag = Rag::load(&config, name, &rag_path)?;
+        let (reranker_model, top_k) = rag.get_config();
+        let top_k = arguments["top_k"]
+            .as_u64()
+            .map(|v| v as usize)
+            .unwrap_or(top_k);
+        let (embeddings, _) = rag
+            .search(
+                query,
+                top_k,
+                reranker_model.as_deref(),
+                create_abort_signal(),
+            )
+            .await?;
+        Ok(embeddings)
+    }
+
+    async fn run_macro(&self, name: &str, arguments: &Value) -> Result<String> {
+        let args = arguments["args"].as_str();
+        let config = self.create_config();
+        macro_execute(&config, name, args, create_abort_signal()).await
+    }
+
+    fn create_config(&self) -> GlobalConfig {
+        Arc::new(RwLock::new(self.config.clone()))
+    }
+}
+
+fn jsonrpc_error(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": code,
+            "message": message,
+        },
+    })
+}
+
+fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// The protocol owns stdout, everything else printed goes to stderr.
+#[cfg(unix)]
+fn take_stdout() -> Result<Box<dyn Write + Send>> {
+    use std::os::fd::FromRawFd;
+
+    let _ = *IS_STDOUT_TERMINAL;
+    let _ = std::io::stdout().flush();
+    unsafe {
+        let fd = libc::dup(libc::STDOUT_FILENO);
+        if fd < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            bail!("Failed to redirect stdout");
+        }
+        Ok(Box::new(std::fs::File::from_raw_fd(fd)))
+    }
+}
+
+#[cfg(windows)]
+fn take_stdout() -> Result<Box<dyn Write + Send>> {
+    use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
+
+    const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetStdHandle(std_handle: u32, handle: RawHandle) -> i32;
+    }
+
+    let _ = *IS_STDOUT_TERMINAL;
+    let _ = std::io::stdout().flush();
+    let stdout = std::io::stdout().as_raw_handle();
+    let stderr = std::io::stderr().as_raw_handle();
+    unsafe {
+        if stdout.is_null() || SetStdHandle(STD_OUTPUT_HANDLE, stderr) == 0 {
+            bail!("Failed to redirect stdout");
+        }
+        Ok(Box::new(std::fs::File::from_raw_handle(stdout)))
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn take_stdout() -> Result<Box<dyn Write + Send>> {
+    bail!("The MCP server is not supported on this platform")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_serve_io() {
+        let server = Server {
+            config: Config::default(),
+            tools: vec![(
+                "role__missing".into(),
+                Target::Role("missing".into()),
+                json!({ "description": "Ask the role 'missing'", "inputSchema": { "type": "object" } }),
+            )],
+        };
+        let requests = [
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": { "protocolVersion": "2024-11-05" } }),
+            json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "tools/call", "params": { "name": "role__missing", "arguments": { "input": "hi" } } }),
+            json!({ "jsonrpc": "2.0", "id": 4, "method": "tools/call", "params": { "name": "unknown" } }),
+        ]
+        .map(|v| format!("{v}\n"))
+        .concat();
+        let output = serve_io(server, requests.as_bytes(), vec![]).await.unwrap();
+        let mut responses: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|v| serde_json::from_str(v).unwrap())
+            .collect();
+        responses.sort_by_key(|v| v["id"].as_u64());
+        assert_eq!(responses.len(), 4);
+        assert_eq!(responses[0]["result"]["protocolVersion"], "2024-11-05");
+        assert_eq!(responses[1]["result"]["tools"][0]["name"], "role__missing");
+        assert_eq!(responses[2]["result"]["isError"], true);
+        assert_eq!(responses[3]["error"]["code"], INVALID_PARAMS);
+    }
+}
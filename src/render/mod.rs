@@ -2,6 +2,7 @@ mod markdown;
 mod stream;
 
 pub use self::markdown::{MarkdownRender, RenderOptions};
+pub use self::stream::thinking_summary;
 use self::stream::{markdown_stream, raw_stream};
 
 use crate::utils::{error_text, pretty_error, AbortSignal, IS_STDOUT_TERMINAL};
@@ -16,9 +17,12 @@ pub async fn render_stream(
     abort_signal: AbortSignal,
 ) -> Result<()> {
     let ret = if *IS_STDOUT_TERMINAL {
-        let render_options = config.read().render_options()?;
+        let (render_options, collapse_thinking) = {
+            let config = config.read();
+            (config.render_options()?, config.collapse_thinking)
+        };
         let mut render = MarkdownRender::init(render_options)?;
-        markdown_stream(rx, &mut render, &abort_signal).await
+        markdown_stream(rx, &mut render, &abort_signal, collapse_thinking).await
     } else {
         raw_stream(rx, &abort_signal).await
     };
@@ -1,6 +1,6 @@
 use super::{MarkdownRender, SseEvent};
 
-use crate::utils::{poll_abort_signal, spawn_spinner, AbortSignal};
+use crate::utils::{dimmed_text, poll_abort_signal, spawn_spinner, AbortSignal};
 
 use anyhow::Result;
 use crossterm::{
@@ -15,14 +15,30 @@ use textwrap::core::display_width;
 use tokio::sync::mpsc::UnboundedReceiver;
 
 pub async fn markdown_stream(
-    rx: UnboundedReceiver<SseEvent>,
+    mut rx: UnboundedReceiver<SseEvent>,
     render: &mut MarkdownRender,
     abort_signal: &AbortSignal,
+    collapse_thinking: bool,
 ) -> Result<()> {
+    // A tool queues its first progress line before rendering starts and shares the terminal,
+    // so leave the terminal mode alone and skip the spinner.
+    let first = rx.try_recv().ok();
+    if let Some(SseEvent::Progress(line)) = first {
+        return progress_stream(line, rx, abort_signal).await;
+    }
+
     enable_raw_mode()?;
     let mut stdout = io::stdout();
 
-    let ret = markdown_stream_inner(rx, render, abort_signal, &mut stdout).await;
+    let ret = markdown_stream_inner(
+        first,
+        rx,
+        render,
+        abort_signal,
+        collapse_thinking,
+        &mut stdout,
+    )
+    .await;
 
     disable_raw_mode()?;
 
@@ -36,49 +52,116 @@ pub async fn raw_stream(
     mut rx: UnboundedReceiver<SseEvent>,
     abort_signal: &AbortSignal,
 ) -> Result<()> {
+    let mut thinking = false;
     loop {
         if abort_signal.aborted() {
             return Ok(());
         }
         if let Some(evt) = rx.recv().await {
             match evt {
+                SseEvent::Thinking(text) => {
+                    if !thinking {
+                        println!("<think>");
+                        thinking = true;
+                    }
+                    print!("{}", text);
+                    stdout().flush()?;
+                }
                 SseEvent::Text(text) => {
+                    if thinking {
+                        print!("\n</think>\n\n");
+                        thinking = false;
+                    }
                     print!("{}", text);
                     stdout().flush()?;
                 }
+                SseEvent::Progress(line) => {
+                    println!("{line}");
+                }
                 SseEvent::Done => {
                     break;
                 }
             }
         }
     }
+    if thinking {
+        println!("\n</think>");
+    }
+    Ok(())
+}
+
+async fn progress_stream(
+    line: String,
+    mut rx: UnboundedReceiver<SseEvent>,
+    abort_signal: &AbortSignal,
+) -> Result<()> {
+    println!("{}", dimmed_text(&line));
+    while let Some(evt) = rx.recv().await {
+        if abort_signal.aborted() {
+            break;
+        }
+        match evt {
+            SseEvent::Progress(line) => println!("{}", dimmed_text(&line)),
+            SseEvent::Done => break,
+            _ => {}
+        }
+    }
     Ok(())
 }
 
+/// The one-line stand-in for thinking that has been collapsed.
+pub fn thinking_summary(text: &str) -> String {
+    let words = text.split_whitespace().count();
+    format!("▸ Thought ({words} words)")
+}
+
 async fn markdown_stream_inner(
+    first: Option<SseEvent>,
     mut rx: UnboundedReceiver<SseEvent>,
     render: &mut MarkdownRender,
     abort_signal: &AbortSignal,
+    collapse_thinking: bool,
     writer: &mut Stdout,
 ) -> Result<()> {
     let mut buffer = String::new();
     let mut buffer_rows = 1;
+    let mut thinking = String::new();
+    let mut thinking_done = false;
 
     let columns = terminal::size()?.0;
 
     let mut spinner = Some(spawn_spinner("Generating"));
+    let mut first = first;
 
     'outer: loop {
         if abort_signal.aborted() {
             return Ok(());
         }
-        for reply_event in gather_events(&mut rx).await {
+        let events = match first.take() {
+            Some(evt) => vec![evt],
+            None => gather_events(&mut rx).await,
+        };
+        for reply_event in events {
             if let Some(spinner) = spinner.take() {
                 spinner.stop();
             }
 
             match reply_event {
+                SseEvent::Thinking(text) => {
+                    let text = text.replace('\t', "    ");
+                    thinking.push_str(&text);
+                    queue!(
+                        writer,
+                        style::Print(dimmed_text(&text).replace('\n', "\r\n"))
+                    )?;
+                    writer.flush()?;
+                }
                 SseEvent::Text(mut text) => {
+                    if !thinking.is_empty() && !thinking_done {
+                        thinking_done = true;
+                        end_thinking(writer, &thinking, collapse_thinking, columns)?;
+                    }
+
                     // tab width hacking
                     text = text.replace('\t', "    ");
 
@@ -136,7 +219,18 @@ async fn markdown_stream_inner(
 
                     writer.flush()?;
                 }
+                SseEvent::Progress(line) => {
+                    queue!(
+                        writer,
+                        style::Print(dimmed_text(&line)),
+                        style::Print("\r\n")
+                    )?;
+                    writer.flush()?;
+                }
                 SseEvent::Done => {
+                    if !thinking.is_empty() && !thinking_done {
+                        end_thinking(writer, &thinking, collapse_thinking, columns)?;
+                    }
                     break 'outer;
                 }
             }
@@ -153,30 +247,49 @@ async fn markdown_stream_inner(
     Ok(())
 }
 
+/// Collapses the streamed thinking into a summary line while it is still on screen.
+fn end_thinking(
+    writer: &mut Stdout,
+    thinking: &str,
+    collapse_thinking: bool,
+    columns: u16,
+) -> Result<()> {
+    let rows: u16 = thinking.split('\n').map(|v| need_rows(v, columns)).sum();
+    let row = cursor::position()?.1;
+    if collapse_thinking && rows <= row + 1 {
+        queue!(
+            writer,
+            cursor::MoveTo(0, row + 1 - rows),
+            terminal::Clear(terminal::ClearType::FromCursorDown),
+            style::Print(dimmed_text(&thinking_summary(thinking))),
+            style::Print("\r\n\r\n"),
+        )?;
+    } else {
+        queue!(writer, style::Print("\r\n\r\n"))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Merges adjacent events of the same kind that arrived in one tick.
 async fn gather_events(rx: &mut UnboundedReceiver<SseEvent>) -> Vec<SseEvent> {
-    let mut texts = vec![];
-    let mut done = false;
+    let mut events = vec![];
     tokio::select! {
         _ = async {
             while let Some(reply_event) = rx.recv().await {
-                match reply_event {
-                    SseEvent::Text(v) => texts.push(v),
-                    SseEvent::Done => {
-                        done = true;
+                match (events.last_mut(), reply_event) {
+                    (Some(SseEvent::Text(v)), SseEvent::Text(text)) => v.push_str(&text),
+                    (Some(SseEvent::Thinking(v)), SseEvent::Thinking(text)) => v.push_str(&text),
+                    (_, SseEvent::Done) => {
+                        events.push(SseEvent::Done);
                         break;
                     }
+                    (_, reply_event) => events.push(reply_event),
                 }
             }
         } => {}
         _ = tokio::time::sleep(Duration::from_millis(50)) => {}
     };
-    let mut events = vec![];
-    if !texts.is_empty() {
-        events.push(SseEvent::Text(texts.join("")))
-    }
-    if done {
-        events.push(SseEvent::Done)
-    }
     events
 }
 
@@ -6,10 +6,10 @@ use self::completer::ReplCompleter;
 use self::highlighter::ReplHighlighter;
 use self::prompt::ReplPrompt;
 
-use crate::client::{call_chat_completions, call_chat_completions_streaming};
+use crate::client::{call_chat_completions, call_chat_completions_streaming, pull_ollama_model};
 use crate::config::{
-    macro_execute, AgentVariables, AssertState, Config, GlobalConfig, Input, LastMessage,
-    StateFlags,
+    macro_execute, render_history_hits, search_history, select_history_session, AgentVariables,
+    AssertState, Config, GlobalConfig, Input, LastMessage, StateFlags,
 };
 use crate::render::render_error;
 use crate::utils::{
@@ -29,10 +29,15 @@ use std::{env, process};
 
 const MENU_NAME: &str = "completion_menu";
 
-static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
+static REPL_COMMANDS: LazyLock<[ReplCommand; 43]> = LazyLock::new(|| {
     [
         ReplCommand::new(".help", "Show this help guide", AssertState::pass()),
         ReplCommand::new(".info", "Show system info", AssertState::pass()),
+        ReplCommand::new(
+            ".info usage",
+            "Show token usage and cost",
+            AssertState::pass(),
+        ),
         ReplCommand::new(
             ".edit config",
             "Modify configuration file",
@@ -82,6 +87,16 @@ static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
             "Clear session messages",
             AssertState::True(StateFlags::SESSION),
         ),
+        ReplCommand::new(
+            ".pin message",
+            "Keep a message verbatim when compressing",
+            AssertState::True(StateFlags::SESSION),
+        ),
+        ReplCommand::new(
+            ".unpin message",
+            "Allow a pinned message to be compressed",
+            AssertState::True(StateFlags::SESSION),
+        ),
         ReplCommand::new(
             ".compress session",
             "Compress session messages",
@@ -97,6 +112,16 @@ static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
             "Modify current session",
             AssertState::True(StateFlags::SESSION_EMPTY | StateFlags::SESSION),
         ),
+        ReplCommand::new(
+            ".search history",
+            "Search saved sessions and messages",
+            AssertState::pass(),
+        ),
+        ReplCommand::new(
+            ".edit message",
+            "Edit and resend an earlier message",
+            AssertState::True(StateFlags::SESSION),
+        ),
         ReplCommand::new(
             ".save session",
             "Save current session to file",
@@ -107,6 +132,11 @@ static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
             "Exit active session",
             AssertState::True(StateFlags::SESSION_EMPTY | StateFlags::SESSION),
         ),
+        ReplCommand::new(
+            ".branch",
+            "List, switch, diff or fork session branches",
+            AssertState::True(StateFlags::SESSION),
+        ),
         ReplCommand::new(".agent", "Use an agent", AssertState::bare()),
         ReplCommand::new(
             ".starter",
@@ -175,6 +205,11 @@ static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
             AssertState::pass(),
         ),
         ReplCommand::new(".copy", "Copy last response", AssertState::pass()),
+        ReplCommand::new(
+            ".clear tool-decisions",
+            "Forget 'always allow/deny' tool decisions",
+            AssertState::pass(),
+        ),
         ReplCommand::new(".set", "Modify runtime settings", AssertState::pass()),
         ReplCommand::new(
             ".delete",
@@ -392,6 +427,10 @@ pub async fn run_repl_command(
                     let info = config.read().agent_info()?;
                     print!("{}", info);
                 }
+                Some("usage") => {
+                    let info = config.read().usage_info()?;
+                    print!("{}", info);
+                }
                 Some(_) => unknown_command()?,
                 None => {
                     let output = config.read().sysinfo()?;
@@ -400,7 +439,10 @@ pub async fn run_repl_command(
             },
             ".model" => match args {
                 Some(name) => {
+                    Config::load_ollama_models(config, Some(name)).await;
                     config.write().set_model(name)?;
+                    let model = config.read().current_model().clone();
+                    pull_ollama_model(config, &model, abort_signal.clone()).await?;
                 }
                 None => println!("Usage: .model <name>"),
             },
@@ -435,6 +477,33 @@ pub async fn run_repl_command(
                 config.write().use_session(args)?;
                 Config::maybe_autoname_session(config.clone());
             }
+            ".branch" => match split_first_arg(args) {
+                None | Some(("list", None)) => {
+                    let output = config.read().list_session_branches()?;
+                    println!("{output}");
+                }
+                Some(("switch", Some(name))) => {
+                    config.write().switch_session_branch(name)?;
+                }
+                Some(("diff", Some(args))) => {
+                    let (name1, name2) = match args.split_once(' ') {
+                        Some((name1, name2)) => (name1, Some(name2.trim())),
+                        None => (args, None),
+                    };
+                    let output = config.read().diff_session_branches(name1, name2)?;
+                    println!("{output}");
+                }
+                Some(("fork", Some(name))) => {
+                    config.read().fork_session_branch(name)?;
+                }
+                _ => println!(
+                    r#"Usage:
+    .branch [list]                  # List the branches of the current session
+    .branch switch <name>           # Switch to another branch
+    .branch diff <name> [name]      # Show where two branches diverge (defaults to the current branch)
+    .branch fork <session-name>     # Save the current branch as a new session"#
+                ),
+            },
             ".rag" => {
                 Config::use_rag(config, args, abort_signal.clone()).await?;
             }
@@ -509,6 +578,11 @@ pub async fn run_repl_command(
                     bail!("Cannot perform this operation because you are in a macro")
                 }
                 match args {
+                    Some(args) if args.split(' ').next() == Some("message") => {
+                        let number = split_first_arg(Some(args)).and_then(|(_, v)| v);
+                        let input = Config::edit_session_message(config, number)?;
+                        ask(config, abort_signal.clone(), input, true).await?;
+                    }
                     Some("config") => {
                         config.read().edit_config()?;
                     }
@@ -525,7 +599,9 @@ pub async fn run_repl_command(
                         config.write().edit_agent_config()?;
                     }
                     _ => {
-                        println!(r#"Usage: .edit <config|role|session|rag-docs|agent-config>"#)
+                        println!(
+                            r#"Usage: .edit <config|role|session|message|rag-docs|agent-config>"#
+                        )
                     }
                 }
             }
@@ -543,6 +619,14 @@ pub async fn run_repl_command(
                     println!(r#"Usage: .compress session"#)
                 }
             },
+            ".pin" | ".unpin" => match split_first_arg(args) {
+                Some(("message", number)) => {
+                    config.write().pin_session_message(number, cmd == ".pin")?;
+                }
+                _ => {
+                    println!(r#"Usage: {cmd} message [number]"#)
+                }
+            },
             ".empty" => match args {
                 Some("session") => {
                     config.write().empty_session()?;
@@ -551,6 +635,20 @@ pub async fn run_repl_command(
                     println!(r#"Usage: .empty session"#)
                 }
             },
+            ".search" => match split_first_arg(args) {
+                Some(("history", Some(query))) => {
+                    let hits = search_history(&config.read(), query)?;
+                    println!("{}", render_history_hits(&hits, query));
+                    if config.read().session.is_none() {
+                        if let Some(name) = select_history_session(&hits)? {
+                            config.write().use_session(Some(&name))?;
+                        }
+                    }
+                }
+                _ => {
+                    println!(r#"Usage: .search history <query>"#)
+                }
+            },
             ".rebuild" => match args {
                 Some("rag") => {
                     Config::rebuild_rag(config, abort_signal.clone()).await?;
@@ -688,6 +786,10 @@ pub async fn run_repl_command(
                 Some("messages") => {
                     bail!("Use '.empty session' instead");
                 }
+                Some("tool-decisions") => {
+                    config.write().tool_policy_decisions.clear();
+                    println!("✓ Cleared tool decisions.");
+                }
                 _ => unknown_command()?,
             },
             _ => unknown_command()?,
@@ -724,10 +826,17 @@ async fn ask(
 
     let client = input.create_client()?;
     config.write().before_chat_completion(&input)?;
-    let (output, tool_results) = if input.stream() {
-        call_chat_completions_streaming(&input, client.as_ref(), abort_signal.clone()).await?
+    let (output, thinking, tool_results) = if input.stream() {
+        call_chat_completions_streaming(&mut input, client.as_ref(), abort_signal.clone()).await?
     } else {
-        call_chat_completions(&input, true, false, client.as_ref(), abort_signal.clone()).await?
+        call_chat_completions(
+            &mut input,
+            true,
+            false,
+            client.as_ref(),
+            abort_signal.clone(),
+        )
+        .await?
     };
     config
         .write()
@@ -736,7 +845,7 @@ async fn ask(
         ask(
             config,
             abort_signal,
-            input.merge_tool_results(output, tool_results),
+            input.merge_tool_results(output, thinking, tool_results),
             false,
         )
         .await
@@ -0,0 +1,137 @@
+use crate::utils::*;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, env, path::Path, process::Command};
+
+/// Environment variables always visible inside the sandbox.
+const BASE_ENV: [&str; 5] = ["HOME", "LANG", "LC_*", "TERM", "TZ"];
+
+/// Restrictions for running a tool binary, it always runs in its own temp dir.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    /// Names or globs of the extra environment variables passed to the tool
+    pub env: Vec<String>,
+    /// Wall-clock limit in seconds
+    pub timeout: Option<u64>,
+    /// Maximum size in bytes of the tool output
+    pub max_output: Option<usize>,
+    pub network: bool,
+    pub write: bool,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            env: vec![],
+            timeout: None,
+            max_output: None,
+            network: true,
+            write: true,
+        }
+    }
+}
+
+impl SandboxConfig {
+    /// Build the command, `workdir` stays writable even when `write` is off.
+    pub fn command(
+        &self,
+        cmd_name: &str,
+        cmd_args: &[String],
+        envs: HashMap<String, String>,
+        workdir: &Path,
+    ) -> Result<Command> {
+        let mut command = if self.network && self.write {
+            Command::new(cmd_name)
+        } else {
+            let mut command = Command::new(bwrap_path()?);
+            command
+                .args(self.bwrap_args(workdir))
+                .arg("--")
+                .arg(cmd_name);
+            command
+        };
+        command.args(cmd_args).current_dir(workdir).env_clear();
+        for (key, value) in env::vars() {
+            let visible = BASE_ENV
+                .iter()
+                .copied()
+                .chain(self.env.iter().map(|v| v.as_str()))
+                .any(|pattern| glob_match(pattern, &key));
+            if visible {
+                command.env(key, value);
+            }
+        }
+        command.envs(envs);
+        Ok(command)
+    }
+
+    pub fn truncate_output(&self, output: String) -> String {
+        match self.max_output {
+            Some(max_output) => truncate_output(output, max_output),
+            None => output,
+        }
+    }
+
+    fn bwrap_args(&self, workdir: &Path) -> Vec<String> {
+        let workdir = workdir.display().to_string();
+        let mut args: Vec<String> = vec![];
+        let root_bind = if self.write { "--bind" } else { "--ro-bind" };
+        args.extend([root_bind, "/", "/"].map(String::from));
+        args.extend(["--dev", "/dev", "--proc", "/proc"].map(String::from));
+        if !self.write {
+            args.extend(["--tmpfs", "/tmp"].map(String::from));
+        }
+        args.extend(["--bind".into(), workdir.clone(), workdir.clone()]);
+        args.extend(["--chdir".into(), workdir]);
+        args.extend(["--unshare-pid", "--die-with-parent"].map(String::from));
+        if !self.network {
+            args.push("--unshare-net".into());
+        }
+        args
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn bwrap_path() -> Result<std::path::PathBuf> {
+    which::which("bwrap").map_err(|_| {
+        anyhow::anyhow!("Blocking network or writes in the sandbox requires bubblewrap (bwrap)")
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bwrap_path() -> Result<std::path::PathBuf> {
+    anyhow::bail!("Blocking network or writes in the sandbox is only supported on Linux")
+}
+
+/// Cut `output` to at most `max_output` bytes, marking it as truncated.
+pub fn truncate_output(mut output: String, max_output: usize) -> String {
+    if output.len() > max_output {
+        let mut end = max_output;
+        while !output.is_char_boundary(end) {
+            end -= 1;
+        }
+        output.truncate(end);
+        output.push_str("\n[output truncated]");
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_output() {
+        let sandbox = SandboxConfig {
+            max_output: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(sandbox.truncate_output("abc".into()), "abc");
+        assert_eq!(
+            sandbox.truncate_output("ab你好".into()),
+            "ab\n[output truncated]"
+        );
+    }
+}
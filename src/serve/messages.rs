@@ -0,0 +1,379 @@
+use super::*;
+
+/// Converts an Anthropic Messages API request into a chat completions request.
+pub(super) fn parse_messages_req(body: Value) -> Result<ChatCompletionsReqBody> {
+    let model = body["model"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Missing 'model'"))?
+        .to_string();
+    let mut messages = vec![];
+    let system = match &body["system"] {
+        Value::String(text) => text.clone(),
+        Value::Array(blocks) => join_texts(blocks),
+        _ => String::new(),
+    };
+    if !system.is_empty() {
+        messages.push(json!({ "role": "system", "content": system }));
+    }
+    let list = body["messages"]
+        .as_array()
+        .ok_or_else(|| anyhow!("Missing 'messages'"))?;
+    for (i, message) in list.iter().enumerate() {
+        parse_message(message, &mut messages)
+            .ok_or_else(|| anyhow!("Failed to parse '.messages[{i}]'"))?;
+    }
+    let tools = match body["tools"].as_array() {
+        Some(list) => {
+            let mut tools = vec![];
+            for (i, tool) in list.iter().enumerate() {
+                if !tool["input_schema"].is_object() {
+                    bail!("Unsupported tool '.tools[{i}]', only custom tools are supported");
+                }
+                tools.push(json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool["name"],
+                        "description": tool["description"],
+                        "parameters": tool["input_schema"],
+                    }
+                }));
+            }
+            Some(tools)
+        }
+        None => None,
+    };
+    Ok(ChatCompletionsReqBody {
+        model,
+        messages,
+        temperature: body["temperature"].as_f64(),
+        top_p: body["top_p"].as_f64(),
+        reasoning_effort: match body["thinking"]["type"].as_str() {
+            Some("enabled") => body["thinking"]["budget_tokens"]
+                .as_u64()
+                .map(ReasoningEffort::Budget),
+            _ => None,
+        },
+        max_tokens: body["max_tokens"].as_i64().map(|v| v as isize),
+        stream: body["stream"].as_bool().unwrap_or_default(),
+        tools,
+        response_format: None,
+        use_tools: body["use_tools"].as_str().map(|v| v.to_string()),
+        tool_progress: body["tool_progress"].as_bool().unwrap_or_default(),
+    })
+}
+
+fn parse_message(message: &Value, messages: &mut Vec<Value>) -> Option<()> {
+    let role = message["role"].as_str()?;
+    let blocks = match &message["content"] {
+        Value::String(text) => {
+            messages.push(json!({ "role": role, "content": text }));
+            return Some(());
+        }
+        Value::Array(blocks) => blocks,
+        _ => return None,
+    };
+    let mut parts = vec![];
+    let mut tool_calls = vec![];
+    for block in blocks {
+        match block["type"].as_str()? {
+            "text" => parts.push(json!({ "type": "text", "text": block["text"] })),
+            "image" => {
+                let source = &block["source"];
+                let url = match source["type"].as_str()? {
+                    "base64" => format!(
+                        "data:{};base64,{}",
+                        source["media_type"].as_str()?,
+                        source["data"].as_str()?
+                    ),
+                    "url" => source["url"].as_str()?.to_string(),
+                    _ => return None,
+                };
+                parts.push(json!({ "type": "image_url", "image_url": { "url": url } }));
+            }
+            "tool_use" => tool_calls.push(json!({
+                "id": block["id"],
+                "type": "function",
+                "function": { "name": block["name"], "arguments": block["input"].to_string() },
+            })),
+            // Tool results answer the calls of the previous message, so they go first
+            "tool_result" => {
+                let content = match &block["content"] {
+                    Value::String(text) => text.clone(),
+                    Value::Array(blocks) => join_texts(blocks),
+                    _ => String::new(),
+                };
+                messages.push(json!({
+                    "role": "tool",
+                    "tool_call_id": block["tool_use_id"],
+                    "content": content,
+                }));
+            }
+            "thinking" | "redacted_thinking" => {}
+            _ => return None,
+        }
+    }
+    if role == "assistant" {
+        let mut message = json!({ "role": role, "content": join_texts(&parts) });
+        if !tool_calls.is_empty() {
+            message["tool_calls"] = tool_calls.into();
+        }
+        messages.push(message);
+    } else if !parts.is_empty() {
+        messages.push(json!({ "role": role, "content": parts }));
+    }
+    Some(())
+}
+
+fn join_texts(blocks: &[Value]) -> String {
+    let texts: Vec<&str> = blocks.iter().filter_map(|v| v["text"].as_str()).collect();
+    texts.join("\n\n")
+}
+
+pub(super) fn ret_messages_non_stream(
+    id: &str,
+    model: &str,
+    output: &ChatCompletionsOutput,
+) -> Bytes {
+    let mut content = vec![];
+    if !output.text.is_empty() {
+        content.push(json!({ "type": "text", "text": output.text }));
+    }
+    for (i, call) in output.tool_calls.iter().enumerate() {
+        content.push(tool_use_block(call, i));
+    }
+    let stop_reason = stop_reason(!output.tool_calls.is_empty());
+    let value = json!({
+        "id": id,
+        "type": "message",
+        "role": "assistant",
+        "model": model,
+        "content": content,
+        "stop_reason": stop_reason,
+        "stop_sequence": null,
+        "usage": {
+            "input_tokens": output.input_tokens.unwrap_or_default(),
+            "output_tokens": output.output_tokens.unwrap_or_default(),
+        },
+    });
+    Bytes::from(value.to_string())
+}
+
+pub(super) fn ret_messages_err<T: std::fmt::Display>(status: StatusCode, err: T) -> AppResponse {
+    let data = json!({
+        "type": "error",
+        "error": {
+            "type": error_type(status),
+            "message": err.to_string(),
+        },
+    });
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(data.to_string())).boxed())
+        .unwrap()
+}
+
+pub(super) fn messages_stream(
+    rx: UnboundedReceiver<ResEvent>,
+    id: String,
+    model: String,
+) -> UnboundedReceiver<Frame<Bytes>> {
+    let (tx, frame_rx) = unbounded_channel();
+    let stream = MessagesStream {
+        tx,
+        index: 0,
+        text_started: false,
+        has_tool_calls: false,
+        usage: (0, 0),
+    };
+    tokio::spawn(stream.run(rx, id, model));
+    frame_rx
+}
+
+struct MessagesStream {
+    tx: UnboundedSender<Frame<Bytes>>,
+    /// The index of the next content block
+    index: usize,
+    text_started: bool,
+    has_tool_calls: bool,
+    usage: (u64, u64),
+}
+
+impl MessagesStream {
+    async fn run(mut self, mut rx: UnboundedReceiver<ResEvent>, id: String, model: String) {
+        self.send(
+            "message_start",
+            json!({
+                "message": {
+                    "id": id,
+                    "type": "message",
+                    "role": "assistant",
+                    "model": model,
+                    "content": [],
+                    "stop_reason": null,
+                    "stop_sequence": null,
+                    "usage": { "input_tokens": 0, "output_tokens": 0 },
+                }
+            }),
+        );
+        while let Some(event) = rx.recv().await {
+            match event {
+                ResEvent::Text(text) => self.text_delta(&text),
+                ResEvent::ToolCalls(tool_calls) => {
+                    self.finish_text();
+                    for call in tool_calls {
+                        self.tool_use(&call);
+                    }
+                }
+                ResEvent::ToolProgress(event, data) => {
+                    let _ = self.tx.send(create_event_frame(event, &data));
+                }
+                ResEvent::Usage(input_tokens, output_tokens) => {
+                    self.usage = (input_tokens, output_tokens)
+                }
+                ResEvent::Error(message) => {
+                    self.send(
+                        "error",
+                        json!({ "error": { "type": "api_error", "message": message } }),
+                    );
+                    break;
+                }
+                ResEvent::Done => {
+                    self.finish_text();
+                    let (input_tokens, output_tokens) = self.usage;
+                    self.send(
+                        "message_delta",
+                        json!({
+                            "delta": {
+                                "stop_reason": stop_reason(self.has_tool_calls),
+                                "stop_sequence": null,
+                            },
+                            "usage": {
+                                "input_tokens": input_tokens,
+                                "output_tokens": output_tokens,
+                            },
+                        }),
+                    );
+                    self.send("message_stop", json!({}));
+                    break;
+                }
+                ResEvent::First(_) => {}
+            }
+        }
+    }
+
+    fn text_delta(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if !self.text_started {
+            self.send(
+                "content_block_start",
+                json!({
+                    "index": self.index,
+                    "content_block": { "type": "text", "text": "" },
+                }),
+            );
+            self.text_started = true;
+        }
+        self.send(
+            "content_block_delta",
+            json!({
+                "index": self.index,
+                "delta": { "type": "text_delta", "text": text },
+            }),
+        );
+    }
+
+    fn finish_text(&mut self) {
+        if self.text_started {
+            self.send("content_block_stop", json!({ "index": self.index }));
+            self.text_started = false;
+            self.index += 1;
+        }
+    }
+
+    fn tool_use(&mut self, call: &ToolCall) {
+        let mut block = tool_use_block(call, self.index);
+        let input = block["input"].take();
+        block["input"] = json!({});
+        self.send(
+            "content_block_start",
+            json!({ "index": self.index, "content_block": block }),
+        );
+        self.send(
+            "content_block_delta",
+            json!({
+                "index": self.index,
+                "delta": { "type": "input_json_delta", "partial_json": input.to_string() },
+            }),
+        );
+        self.send("content_block_stop", json!({ "index": self.index }));
+        self.index += 1;
+        self.has_tool_calls = true;
+    }
+
+    fn send(&mut self, event: &str, data: Value) {
+        let mut value = json!({ "type": event });
+        if let (Some(value), Value::Object(data)) = (value.as_object_mut(), data) {
+            value.extend(data);
+        }
+        let _ = self.tx.send(create_event_frame(event, &value));
+    }
+}
+
+fn tool_use_block(call: &ToolCall, index: usize) -> Value {
+    let input = match &call.arguments {
+        Value::String(v) => serde_json::from_str(v).unwrap_or_else(|_| json!({})),
+        v => v.clone(),
+    };
+    json!({
+        "type": "tool_use",
+        "id": call.id.clone().unwrap_or_else(|| format!("toolu_{index}")),
+        "name": call.name,
+        "input": input,
+    })
+}
+
+fn stop_reason(has_tool_calls: bool) -> &'static str {
+    if has_tool_calls {
+        "tool_use"
+    } else {
+        "end_turn"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_messages_req() {
+        let body = json!({
+            "model": "default",
+            "max_tokens": 1024,
+            "system": [{ "type": "text", "text": "Be brief" }],
+            "messages": [
+                { "role": "user", "content": "weather?" },
+                { "role": "assistant", "content": [
+                    { "type": "text", "text": "Let me check." },
+                    { "type": "tool_use", "id": "t1", "name": "get_weather", "input": { "city": "Paris" } },
+                ]},
+                { "role": "user", "content": [
+                    { "type": "tool_result", "tool_use_id": "t1", "content": "sunny" },
+                    { "type": "text", "text": "and tomorrow?" },
+                ]},
+            ],
+        });
+        let req = parse_messages_req(body).unwrap();
+        assert_eq!(req.max_tokens, Some(1024));
+        assert_eq!(req.messages[0]["content"], "Be brief");
+        assert_eq!(
+            req.messages[2]["tool_calls"][0]["function"]["arguments"],
+            r#"{"city":"Paris"}"#
+        );
+        assert_eq!(req.messages[3]["role"], "tool");
+        assert_eq!(req.messages[4]["content"][0]["text"], "and tomorrow?");
+        let messages = parse_messages(req.messages).unwrap();
+        assert_eq!(messages.len(), 4);
+    }
+}
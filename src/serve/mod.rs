@@ -0,0 +1,1855 @@
+mod messages;
+mod responses;
+mod sessions;
+
+use self::{messages::*, responses::*};
+
+use crate::{client::*, config::*, function::*, rag::*, utils::*};
+
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use chrono::{Timelike, Utc};
+use futures_util::StreamExt;
+use http::{HeaderValue, Method, Response, StatusCode};
+use http_body_util::{combinators::BoxBody, BodyExt, Full, StreamBody};
+use hyper::{
+    body::{Frame, Incoming},
+    service::service_fn,
+};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::TcpListener,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+};
+use tokio_graceful::Shutdown;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+const DEFAULT_MODEL_NAME: &str = "default";
+const PLAYGROUND_HTML: &[u8] = include_bytes!("../../assets/playground.html");
+const ARENA_HTML: &[u8] = include_bytes!("../../assets/arena.html");
+const PLAYGROUND_PATHS: [&str; 2] = ["/playground", "/playground.html"];
+const ARENA_PATHS: [&str; 2] = ["/arena", "/arena.html"];
+
+const TOOL_CALL_MAX_STEPS: usize = 32;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+type AppResponse = Response<BoxBody<Bytes, Infallible>>;
+
+/// An API key accepted by the server, see `serve_api_keys`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServeApiKey {
+    pub name: String,
+    pub key: String,
+    /// Model ids or globs the key may use, all models when empty
+    #[serde(default)]
+    pub models: Vec<String>,
+    /// Maximum requests per minute
+    #[serde(default)]
+    pub rate_limit: Option<usize>,
+    /// Maximum tokens per day, counted in the usage ledger
+    #[serde(default)]
+    pub token_quota: Option<u64>,
+    /// Tools the key may run with `use_tools`, overrides `serve_use_tools`
+    #[serde(default)]
+    pub use_tools: Option<String>,
+    /// Whether the key may use the sessions API, which reads and writes every session
+    #[serde(default)]
+    pub admin: bool,
+}
+
+pub async fn run(config: GlobalConfig, addr: Option<String>) -> Result<()> {
+    let addr = match addr {
+        Some(addr) => {
+            if let Ok(port) = addr.parse::<u16>() {
+                format!("127.0.0.1:{port}")
+            } else if let Ok(ip) = addr.parse::<IpAddr>() {
+                format!("{ip}:8000")
+            } else {
+                addr
+            }
+        }
+        None => config.read().serve_addr(),
+    };
+    // Only the tools the server allows can be used, so only their MCP servers are connected
+    let use_tools: Vec<String> = {
+        let config = config.read();
+        config
+            .serve_use_tools
+            .iter()
+            .chain(
+                config
+                    .serve_api_keys
+                    .iter()
+                    .filter_map(|v| v.use_tools.as_ref()),
+            )
+            .cloned()
+            .collect()
+    };
+    Config::load_mcp_servers(&config, Some(&use_tools.join(","))).await;
+    let server = Arc::new(Server::new(&config));
+    let listener = TcpListener::bind(&addr).await?;
+    let stop_server = server.run(listener).await?;
+    println!("Chat Completions API: http://{addr}/v1/chat/completions");
+    println!("Responses API:        http://{addr}/v1/responses");
+    println!("Messages API:         http://{addr}/v1/messages");
+    println!("Embeddings API:       http://{addr}/v1/embeddings");
+    println!("Rerank API:           http://{addr}/v1/rerank");
+    println!("LLM Playground:       http://{addr}/playground");
+    println!("LLM Arena:            http://{addr}/arena?num=2");
+    shutdown_signal().await;
+    let _ = stop_server.send(());
+    Ok(())
+}
+
+struct Server {
+    config: Config,
+    models: Vec<Value>,
+    roles: Vec<Role>,
+    rags: Vec<String>,
+    /// The sha256 hashes of `serve_api_keys`
+    api_keys: Vec<(String, ServeApiKey)>,
+    requests: Mutex<HashMap<String, VecDeque<Instant>>>,
+    /// Agents are initialized on first use and reused by later requests
+    agents: tokio::sync::Mutex<HashMap<String, Agent>>,
+    /// Serializes the writes to each session
+    session_locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+}
+
+impl Server {
+    fn new(config: &GlobalConfig) -> Self {
+        let config = config.read().clone();
+        let mut models = list_all_models(&config);
+        let mut default_model = config.model.clone();
+        default_model.data_mut().name = DEFAULT_MODEL_NAME.into();
+        models.insert(0, &default_model);
+        let mut models: Vec<Value> = models
+            .into_iter()
+            .enumerate()
+            .map(|(i, model)| {
+                let id = if i == 0 {
+                    DEFAULT_MODEL_NAME.into()
+                } else {
+                    model.id()
+                };
+                let mut value = json!(model.data());
+                if let Some(value_obj) = value.as_object_mut() {
+                    value_obj.insert("id".into(), id.into());
+                    value_obj.insert("object".into(), "model".into());
+                    value_obj.insert("owned_by".into(), model.client_name().into());
+                    value_obj.remove("name");
+                }
+                value
+            })
+            .collect();
+        let roles = Config::all_roles();
+        let rags = Config::list_rags();
+        let virtual_models = config
+            .list_model_groups()
+            .into_iter()
+            .map(|(name, _)| name.clone())
+            .chain(roles.iter().map(|v| format!("role:{}", v.name())))
+            .chain(rags.iter().map(|v| format!("rag:{v}")))
+            .chain(list_agents().into_iter().map(|v| format!("agent:{v}")));
+        models.extend(virtual_models.map(|id| {
+            json!({
+                "id": id,
+                "object": "model",
+                "owned_by": "aichat",
+            })
+        }));
+        let api_keys = config
+            .serve_api_keys
+            .iter()
+            .map(|v| (sha256(&v.key), v.clone()))
+            .collect();
+        Self {
+            config,
+            models,
+            roles,
+            rags,
+            api_keys,
+            requests: Default::default(),
+            agents: Default::default(),
+            session_locks: Default::default(),
+        }
+    }
+
+    async fn run(self: Arc<Self>, listener: TcpListener) -> Result<oneshot::Sender<()>> {
+        let (tx, rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let shutdown = Shutdown::new(async { rx.await.unwrap_or_default() });
+            let guard = shutdown.guard_weak();
+
+            loop {
+                tokio::select! {
+                    res = listener.accept() => {
+                        let Ok((cnx, _)) = res else {
+                            continue;
+                        };
+
+                        let stream = TokioIo::new(cnx);
+                        let server = self.clone();
+                        shutdown.spawn_task(async move {
+                            let hyper_service = service_fn(move |request: hyper::Request<Incoming>| {
+                                server.clone().handle(request)
+                            });
+                            let _ = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
+                                .serve_connection_with_upgrades(stream, hyper_service)
+                                .await;
+                        });
+                    }
+                    _ = guard.cancelled() => {
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(tx)
+    }
+
+    async fn handle(
+        self: Arc<Self>,
+        req: hyper::Request<Incoming>,
+    ) -> std::result::Result<AppResponse, hyper::Error> {
+        let method = req.method().clone();
+        let uri = req.uri().clone();
+        let path = uri.path();
+        // Keep the `api_key` query parameter of the pages out of the log
+        let log_uri = match is_page_path(path) {
+            true => path.to_string(),
+            false => uri.to_string(),
+        };
+        let origin = req
+            .headers()
+            .get(hyper::header::ORIGIN)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+
+        if method == Method::OPTIONS {
+            let mut res = Response::default();
+            *res.status_mut() = StatusCode::NO_CONTENT;
+            self.set_cors_header(&mut res, origin.as_deref(), is_sessions_path(path));
+            return Ok(res);
+        }
+
+        let mut status = StatusCode::OK;
+        let api_key = self.authorize(&req);
+        let res = match api_key {
+            Err(err) => Err(err),
+            Ok(api_key) => {
+                if path == "/v1/chat/completions" {
+                    self.chat_completions(req, api_key).await
+                } else if path == "/v1/responses" {
+                    self.responses(req, api_key).await
+                } else if path == "/v1/messages" {
+                    self.messages(req, api_key).await
+                } else if path == "/v1/embeddings" {
+                    self.embeddings(req, api_key).await
+                } else if path == "/v1/rerank" {
+                    self.rerank(req, api_key).await
+                } else if path == "/v1/models" {
+                    self.list_models(api_key.as_ref())
+                } else if path == "/v1/roles" {
+                    self.list_roles()
+                } else if path == "/v1/rags" {
+                    self.list_rags()
+                } else if path == "/v1/rags/search" {
+                    self.search_rag(req).await
+                } else if path == "/v1/sessions" {
+                    match self.check_admin(api_key.as_ref()) {
+                        Err(err) => Err(err),
+                        Ok(()) if method == Method::POST => self.create_session(req).await,
+                        Ok(()) => self.list_sessions(),
+                    }
+                } else if let Some(name) = path.strip_prefix("/v1/sessions/") {
+                    match self.check_admin(api_key.as_ref()) {
+                        Err(err) => Err(err),
+                        Ok(()) => self.session(req, name).await,
+                    }
+                } else if PLAYGROUND_PATHS.contains(&path) {
+                    self.playground_page()
+                } else if ARENA_PATHS.contains(&path) {
+                    self.arena_page()
+                } else {
+                    status = StatusCode::NOT_FOUND;
+                    Err(anyhow!("Not Found"))
+                }
+            }
+        };
+        let mut res = match res {
+            Ok(res) => {
+                info!("{method} {log_uri} {}", status.as_u16());
+                res
+            }
+            Err(err) => {
+                if let Some(err) = err.downcast_ref::<HttpError>() {
+                    status = err.status;
+                } else if status == StatusCode::OK {
+                    status = StatusCode::BAD_REQUEST;
+                }
+                error!("{method} {log_uri} {} {err}", status.as_u16());
+                match path == "/v1/messages" {
+                    true => ret_messages_err(status, err),
+                    false => ret_err(status, err),
+                }
+            }
+        };
+        *res.status_mut() = status;
+        self.set_cors_header(&mut res, origin.as_deref(), is_sessions_path(path));
+        Ok(res)
+    }
+
+    /// Checks the bearer token and the rate limit when `serve_api_keys` is configured.
+    /// The playground and arena pages take the key from the `api_key` query parameter.
+    fn authorize(&self, req: &hyper::Request<Incoming>) -> Result<Option<ServeApiKey>> {
+        if self.api_keys.is_empty() {
+            return Ok(None);
+        }
+        let token = req
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .or_else(|| req.headers().get("x-api-key").and_then(|v| v.to_str().ok()))
+            .map(|v| v.trim().to_string())
+            .or_else(|| match is_page_path(req.uri().path()) {
+                true => query_param(req.uri().query().unwrap_or_default(), "api_key"),
+                false => None,
+            })
+            .ok_or_else(|| {
+                http_error(
+                    StatusCode::UNAUTHORIZED,
+                    "Missing API key, use the header 'Authorization: Bearer <key>'",
+                )
+            })?;
+        // Every key is compared, so the time taken doesn't tell which one matched
+        let token_hash = sha256(&token);
+        let api_key = self
+            .api_keys
+            .iter()
+            .fold(None, |found, (hash, api_key)| {
+                match constant_time_eq(hash.as_bytes(), token_hash.as_bytes()) {
+                    true => Some(api_key),
+                    false => found,
+                }
+            })
+            .ok_or_else(|| http_error(StatusCode::UNAUTHORIZED, "Invalid API key"))?;
+        if let Some(limit) = api_key.rate_limit {
+            let mut requests = self.requests.lock();
+            let times = requests.entry(api_key.name.clone()).or_default();
+            if !check_rate_limit(times, Instant::now(), limit) {
+                return Err(http_error(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    format!("Rate limit of {limit} requests per minute exceeded"),
+                ));
+            }
+        }
+        Ok(Some(api_key.clone()))
+    }
+
+    /// Checks the allowed models and the daily token quota of the API key.
+    fn check_api_key(&self, api_key: &ServeApiKey, model_id: &str) -> Result<()> {
+        if !self.is_model_allowed(api_key, model_id) {
+            return Err(http_error(
+                StatusCode::FORBIDDEN,
+                format!("The API key is not allowed to use the model '{model_id}'"),
+            ));
+        }
+        if let Some(quota) = api_key.token_quota {
+            let usage = Config::serve_api_key_usage(&api_key.name);
+            if usage.input_tokens + usage.output_tokens >= quota {
+                return Err(http_error(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    format!("The daily quota of {quota} tokens has been used up"),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the model behind a role, RAG or agent, which the key must allow as well.
+    fn check_model(&self, api_key: Option<&ServeApiKey>, model: &Model) -> Result<()> {
+        match api_key {
+            Some(api_key) => self.check_api_key(api_key, &model.alias_or_id()),
+            None => Ok(()),
+        }
+    }
+
+    fn is_model_allowed(&self, api_key: &ServeApiKey, model_id: &str) -> bool {
+        if api_key.models.is_empty() {
+            return true;
+        }
+        let default_model_id = self.config.model.alias_or_id();
+        let model_id = match model_id == DEFAULT_MODEL_NAME {
+            true => default_model_id.as_str(),
+            false => model_id,
+        };
+        let allowed = |id: &str| api_key.models.iter().any(|v| glob_match(v, id));
+        if allowed(model_id) {
+            return true;
+        }
+        // A model group is allowed when all of its members are
+        match self.config.model_groups.get(model_id) {
+            Some(group) => group.models.iter().all(|v| allowed(v.id())),
+            None => false,
+        }
+    }
+
+    async fn agent(
+        &self,
+        config: &GlobalConfig,
+        name: &str,
+        abort_signal: &AbortSignal,
+    ) -> Result<Agent> {
+        let mut agents = self.agents.lock().await;
+        if let Some(agent) = agents.get(name) {
+            return Ok(agent.clone());
+        }
+        let agent = Agent::init(config, name, abort_signal.clone()).await?;
+        agents.insert(name.to_string(), agent.clone());
+        Ok(agent)
+    }
+
+    /// The sessions API needs an admin key, or `serve_sessions` when the server takes no keys.
+    fn check_admin(&self, api_key: Option<&ServeApiKey>) -> Result<()> {
+        match api_key {
+            Some(api_key) if api_key.admin => Ok(()),
+            Some(_) => Err(http_error(
+                StatusCode::FORBIDDEN,
+                "The API key is not allowed to access sessions",
+            )),
+            None if self.config.serve_sessions => Ok(()),
+            None => Err(http_error(
+                StatusCode::FORBIDDEN,
+                "The sessions API is off, set `serve_sessions: true` or use an admin API key",
+            )),
+        }
+    }
+
+    /// The sessions API is never opened to any origin, only to `serve_cors_origins`.
+    fn set_cors_header(&self, res: &mut AppResponse, origin: Option<&str>, no_wildcard: bool) {
+        let allow_origin = if self.config.serve_cors_origins.is_empty() {
+            (!no_wildcard).then_some("*")
+        } else {
+            origin.filter(|v| self.config.serve_cors_origins.iter().any(|o| o == v))
+        };
+        if let Some(value) = allow_origin.and_then(|v| HeaderValue::from_str(v).ok()) {
+            res.headers_mut()
+                .insert(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, value);
+        }
+        if !self.config.serve_cors_origins.is_empty() {
+            res.headers_mut()
+                .insert(hyper::header::VARY, HeaderValue::from_static("Origin"));
+        }
+        res.headers_mut().insert(
+            hyper::header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static("GET,POST,PUT,PATCH,DELETE"),
+        );
+        res.headers_mut().insert(
+            hyper::header::ACCESS_CONTROL_ALLOW_HEADERS,
+            HeaderValue::from_static("Content-Type,Authorization,x-api-key,anthropic-version"),
+        );
+    }
+
+    fn playground_page(&self) -> Result<AppResponse> {
+        let res = Response::builder()
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(Full::new(Bytes::from(PLAYGROUND_HTML)).boxed())?;
+        Ok(res)
+    }
+
+    fn arena_page(&self) -> Result<AppResponse> {
+        let res = Response::builder()
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(Full::new(Bytes::from(ARENA_HTML)).boxed())?;
+        Ok(res)
+    }
+
+    fn list_models(&self, api_key: Option<&ServeApiKey>) -> Result<AppResponse> {
+        let models: Vec<&Value> = self
+            .models
+            .iter()
+            .filter(|v| {
+                api_key.is_none_or(|api_key| {
+                    self.is_model_allowed(api_key, v["id"].as_str().unwrap_or_default())
+                })
+            })
+            .collect();
+        let data = json!({ "data": models });
+        let res = Response::builder()
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(Full::new(Bytes::from(data.to_string())).boxed())?;
+        Ok(res)
+    }
+
+    fn list_roles(&self) -> Result<AppResponse> {
+        let data = json!({ "data": self.roles });
+        let res = Response::builder()
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(Full::new(Bytes::from(data.to_string())).boxed())?;
+        Ok(res)
+    }
+
+    fn list_rags(&self) -> Result<AppResponse> {
+        let data = json!({ "data": self.rags });
+        let res = Response::builder()
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(Full::new(Bytes::from(data.to_string())).boxed())?;
+        Ok(res)
+    }
+
+    async fn search_rag(&self, req: hyper::Request<Incoming>) -> Result<AppResponse> {
+        let req_body = req.collect().await?.to_bytes();
+        let req_body: Value = serde_json::from_slice(&req_body)
+            .map_err(|err| anyhow!("Invalid request json, {err}"))?;
+
+        debug!("search rag request: {req_body}");
+        let SearchRagReqBody { name, input } = serde_json::from_value(req_body)
+            .map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        let config = Arc::new(RwLock::new(self.config.clone()));
+
+        let abort_signal = create_abort_signal();
+
+        let rag_path = config.read().rag_file(&name);
+        let rag = Rag::load(&config, &name, &rag_path)?;
+
+        let rag_result = Config::search_rag(&config, &rag, &input, abort_signal).await?;
+
+        let data = json!({ "data": rag_result });
+        let res = Response::builder()
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(Full::new(Bytes::from(data.to_string())).boxed())?;
+        Ok(res)
+    }
+
+    async fn chat_completions(
+        &self,
+        req: hyper::Request<Incoming>,
+        api_key: Option<ServeApiKey>,
+    ) -> Result<AppResponse> {
+        let req_body = req.collect().await?.to_bytes();
+        let req_body: Value = serde_json::from_slice(&req_body)
+            .map_err(|err| anyhow!("Invalid request json, {err}"))?;
+
+        debug!("chat completions request: {req_body}");
+        let req_body = serde_json::from_value(req_body)
+            .map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        let (model_name, reply) = self.chat(req_body, api_key).await?;
+
+        let completion_id = generate_id("chatcmpl-");
+        let created = Utc::now().timestamp();
+
+        match reply {
+            ChatReply::Output(output) => {
+                let res = Response::builder()
+                    .header("Content-Type", "application/json")
+                    .body(
+                        Full::new(ret_non_stream(
+                            &completion_id,
+                            &model_name,
+                            created,
+                            &output,
+                        ))
+                        .boxed(),
+                    )?;
+                Ok(res)
+            }
+            ChatReply::Stream(rx) => {
+                let shared: Arc<(String, String, i64, AtomicBool)> =
+                    Arc::new((completion_id, model_name, created, AtomicBool::new(false)));
+                let stream = UnboundedReceiverStream::new(rx);
+                let stream = stream.filter_map(move |res_event| {
+                    let shared = shared.clone();
+                    async move {
+                        let (completion_id, model, created, has_tool_calls) = shared.as_ref();
+                        match res_event {
+                            ResEvent::Text(text) => {
+                                Some(Ok(create_text_frame(completion_id, model, *created, &text)))
+                            }
+                            ResEvent::ToolCalls(tool_calls) => {
+                                has_tool_calls.store(true, Ordering::SeqCst);
+                                Some(Ok(create_tool_calls_frame(
+                                    completion_id,
+                                    model,
+                                    *created,
+                                    &tool_calls,
+                                )))
+                            }
+                            ResEvent::ToolProgress(event, data) => {
+                                Some(Ok(create_event_frame(event, &data)))
+                            }
+                            ResEvent::Error(message) => Some(Ok(create_error_frame(&message))),
+                            ResEvent::Done => Some(Ok(create_done_frame(
+                                completion_id,
+                                model,
+                                *created,
+                                has_tool_calls.load(Ordering::SeqCst),
+                            ))),
+                            _ => None,
+                        }
+                    }
+                });
+                Ok(create_sse_response(stream)?)
+            }
+        }
+    }
+
+    async fn responses(
+        &self,
+        req: hyper::Request<Incoming>,
+        api_key: Option<ServeApiKey>,
+    ) -> Result<AppResponse> {
+        let req_body = req.collect().await?.to_bytes();
+        let req_body: Value = serde_json::from_slice(&req_body)
+            .map_err(|err| anyhow!("Invalid request json, {err}"))?;
+
+        debug!("responses request: {req_body}");
+        let req_body = parse_responses_req(req_body)?;
+
+        let (model_name, reply) = self.chat(req_body, api_key).await?;
+
+        let id = generate_id("resp_");
+        let created = Utc::now().timestamp();
+
+        match reply {
+            ChatReply::Output(output) => {
+                let res = Response::builder()
+                    .header("Content-Type", "application/json")
+                    .body(
+                        Full::new(ret_responses_non_stream(&id, &model_name, created, &output))
+                            .boxed(),
+                    )?;
+                Ok(res)
+            }
+            ChatReply::Stream(rx) => {
+                let frames = responses_stream(rx, id, model_name, created);
+                create_sse_response(UnboundedReceiverStream::new(frames).map(Ok))
+            }
+        }
+    }
+
+    async fn messages(
+        &self,
+        req: hyper::Request<Incoming>,
+        api_key: Option<ServeApiKey>,
+    ) -> Result<AppResponse> {
+        let req_body = req.collect().await?.to_bytes();
+        let req_body: Value = serde_json::from_slice(&req_body)
+            .map_err(|err| anyhow!("Invalid request json, {err}"))?;
+
+        debug!("messages request: {req_body}");
+        let req_body = parse_messages_req(req_body)?;
+
+        let (model_name, reply) = self.chat(req_body, api_key).await?;
+
+        let id = generate_id("msg_");
+
+        match reply {
+            ChatReply::Output(output) => {
+                let res = Response::builder()
+                    .header("Content-Type", "application/json")
+                    .body(Full::new(ret_messages_non_stream(&id, &model_name, &output)).boxed())?;
+                Ok(res)
+            }
+            ChatReply::Stream(rx) => {
+                let frames = messages_stream(rx, id, model_name);
+                create_sse_response(UnboundedReceiverStream::new(frames).map(Ok))
+            }
+        }
+    }
+
+    /// Runs a chat request, each route renders the reply in its own protocol.
+    async fn chat(
+        &self,
+        req_body: ChatCompletionsReqBody,
+        api_key: Option<ServeApiKey>,
+    ) -> Result<(String, ChatReply)> {
+        let ChatCompletionsReqBody {
+            model,
+            messages,
+            mut temperature,
+            mut top_p,
+            mut reasoning_effort,
+            max_tokens,
+            stream,
+            tools,
+            response_format,
+            use_tools,
+            tool_progress,
+        } = req_body;
+
+        let mut messages =
+            parse_messages(messages).map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        let mut functions =
+            parse_tools(tools).map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        let mut response_format = parse_response_format(response_format)
+            .map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        // An agent is checked against the model it runs on
+        if let Some(api_key) = api_key.as_ref().filter(|_| !model.starts_with("agent:")) {
+            self.check_api_key(api_key, &model)?;
+        }
+
+        let mut config = self.config.clone();
+        config.serve_api_key = api_key.as_ref().map(|v| v.name.clone());
+
+        let default_model = config.model.clone();
+
+        let config = Arc::new(RwLock::new(config));
+
+        let abort_signal = create_abort_signal();
+
+        let mut role_model = None;
+        let mut fallback_models = vec![];
+        let mut run_tools = false;
+        let model_name = match model.split_once(':') {
+            Some(("role", name)) => {
+                let role = config.read().retrieve_role(name)?;
+                role.patch_messages(&mut messages);
+                temperature = temperature.or(role.temperature());
+                top_p = top_p.or(role.top_p());
+                reasoning_effort = reasoning_effort.or(role.reasoning_effort());
+                if response_format.is_none() {
+                    response_format = role.response_format().cloned();
+                }
+                self.check_model(api_key.as_ref(), role.model())?;
+                role_model = Some(role.model().clone());
+                fallback_models = role.fallback_models().to_vec();
+                model
+            }
+            Some(("rag", name)) => {
+                let rag_path = config.read().rag_file(name);
+                if !rag_path.exists() {
+                    bail!("Unknown RAG '{name}'");
+                }
+                self.check_model(api_key.as_ref(), &default_model)?;
+                let rag = Rag::load(&config, name, &rag_path)?;
+                patch_rag_messages(&config, &rag, &mut messages, abort_signal.clone()).await?;
+                model
+            }
+            Some(("agent", name)) => {
+                if functions.is_some() {
+                    bail!("The agent runs its own tools, 'tools' cannot be used with it");
+                }
+                let agent = self.agent(&config, name, &abort_signal).await?;
+                self.check_model(api_key.as_ref(), agent.model())?;
+                if let Some(rag) = agent.rag() {
+                    patch_rag_messages(&config, &rag, &mut messages, abort_signal.clone()).await?;
+                }
+                config.write().rag = agent.rag();
+                config.write().agent = Some(agent);
+                config.write().init_agent_shared_variables()?;
+                let role = config.read().extract_role();
+                check_role_tools(&config.read(), api_key.as_ref(), &role)?;
+                role.patch_messages(&mut messages);
+                temperature = temperature.or(role.temperature());
+                top_p = top_p.or(role.top_p());
+                reasoning_effort = reasoning_effort.or(role.reasoning_effort());
+                functions = config.read().select_functions(&role);
+                role_model = Some(role.model().clone());
+                fallback_models = role.fallback_models().to_vec();
+                run_tools = true;
+                model
+            }
+            _ => {
+                // The default model keeps its group, while a member id pins that member
+                if model == DEFAULT_MODEL_NAME {
+                    default_model.alias_or_id()
+                } else {
+                    if default_model.alias_or_id() != model {
+                        config.write().set_model(&model)?;
+                    }
+                    model
+                }
+            }
+        };
+
+        if let Some(use_tools) = use_tools {
+            if functions.is_some() && !run_tools {
+                bail!(
+                    "The tools run on the server with 'use_tools', 'tools' cannot be used with it"
+                );
+            }
+            check_use_tools(&config.read(), api_key.as_ref(), &use_tools)?;
+            let mut role = config.read().extract_role();
+            role.set_use_tools(Some(use_tools));
+            functions = config.read().select_functions(&role);
+            run_tools = true;
+        }
+
+        let mut client = init_client(&config, role_model)?;
+        if max_tokens.is_some() {
+            client.model_mut().set_max_tokens(max_tokens, true);
+        }
+
+        if let Some(schema) = &response_format {
+            patch_response_format(&mut messages, schema);
+        }
+        patch_messages(&mut messages, client.model());
+
+        let data: ChatCompletionsData = ChatCompletionsData {
+            messages,
+            temperature,
+            top_p,
+            functions,
+            stream,
+            response_format,
+            reasoning_effort,
+        };
+
+        let input_tokens = client.model().total_tokens(&data.messages);
+
+        if run_tools {
+            if stream {
+                let (tx, rx) = unbounded_channel();
+                tokio::spawn(async move {
+                    let _ = tx.send(ResEvent::Text(String::new()));
+                    let mut retry = RetryState::new(client.as_ref(), &fallback_models);
+                    let ret = run_tool_calls(
+                        &config,
+                        &mut retry,
+                        data,
+                        &abort_signal,
+                        Some(&tx),
+                        tool_progress,
+                    )
+                    .await;
+                    match ret {
+                        Ok(output) => {
+                            let _ = tx.send(create_usage_event(&output, input_tokens));
+                            let _ = tx.send(ResEvent::Done);
+                        }
+                        Err(err) => {
+                            let _ = tx.send(ResEvent::Error(format!("{err:#}")));
+                        }
+                    }
+                });
+                return Ok((model_name, ChatReply::Stream(rx)));
+            }
+            let mut retry = RetryState::new(client.as_ref(), &fallback_models);
+            let output =
+                run_tool_calls(&config, &mut retry, data, &abort_signal, None, false).await?;
+            return Ok((model_name, ChatReply::Output(fold_thinking(output))));
+        }
+
+        if stream {
+            let (tx, mut rx) = unbounded_channel();
+            tokio::spawn(async move {
+                // Errors before the first text are still returned as the status of the response
+                let (step_tx, mut step_rx) = unbounded_channel();
+                let relay = async {
+                    let mut is_first = true;
+                    while let Some(event) = step_rx.recv().await {
+                        if is_first {
+                            let _ = tx.send(ResEvent::First(None));
+                            is_first = false;
+                        }
+                        let _ = tx.send(event);
+                    }
+                    is_first
+                };
+                let run = async move {
+                    let mut retry = RetryState::new(client.as_ref(), &fallback_models);
+                    chat_step(&mut retry, data, &abort_signal, Some(&step_tx)).await
+                };
+                let (is_first, ret) = tokio::join!(relay, run);
+                match ret {
+                    Ok(output) => {
+                        if is_first {
+                            let _ = tx.send(ResEvent::First(None));
+                        }
+                        if !output.tool_calls.is_empty() {
+                            let _ = tx.send(ResEvent::ToolCalls(output.tool_calls.clone()));
+                        }
+                        let _ = tx.send(create_usage_event(&output, input_tokens));
+                        let _ = tx.send(ResEvent::Done);
+                    }
+                    Err(err) if is_first => {
+                        let _ = tx.send(ResEvent::First(Some(format!("{err:?}"))));
+                    }
+                    Err(err) => {
+                        let _ = tx.send(ResEvent::Error(format!("{err:#}")));
+                    }
+                }
+            });
+
+            let first_event = rx.recv().await;
+
+            if let Some(ResEvent::First(Some(err))) = first_event {
+                bail!("{err}");
+            }
+
+            Ok((model_name, ChatReply::Stream(rx)))
+        } else {
+            let mut retry = RetryState::new(client.as_ref(), &fallback_models);
+            let output = chat_step(&mut retry, data, &abort_signal, None).await?;
+            Ok((model_name, ChatReply::Output(fold_thinking(output))))
+        }
+    }
+
+    async fn embeddings(
+        &self,
+        req: hyper::Request<Incoming>,
+        api_key: Option<ServeApiKey>,
+    ) -> Result<AppResponse> {
+        let req_body = req.collect().await?.to_bytes();
+        let req_body: Value = serde_json::from_slice(&req_body)
+            .map_err(|err| anyhow!("Invalid request json, {err}"))?;
+
+        debug!("embeddings request: {req_body}");
+        let req_body = serde_json::from_value(req_body)
+            .map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        let EmbeddingsReqBody {
+            input,
+            model: embedding_model_id,
+        } = req_body;
+
+        if let Some(api_key) = &api_key {
+            self.check_api_key(api_key, &embedding_model_id)?;
+        }
+
+        let config = Arc::new(RwLock::new(self.config.clone()));
+
+        let embedding_model =
+            Model::retrieve_model(&config.read(), &embedding_model_id, ModelType::Embedding)?;
+
+        let texts = match input {
+            EmbeddingsReqBodyInput::Single(v) => vec![v],
+            EmbeddingsReqBodyInput::Multiple(v) => v,
+        };
+        let client = init_client(&config, Some(embedding_model))?;
+        let data = client
+            .embeddings(&EmbeddingsData {
+                query: false,
+                texts,
+            })
+            .await?;
+        let data: Vec<_> = data
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| {
+                json!({
+                        "object": "embedding",
+                        "embedding": v,
+                        "index": i,
+                })
+            })
+            .collect();
+        let output = json!({
+            "object": "list",
+            "data": data,
+            "model": embedding_model_id,
+            "usage": {
+                "prompt_tokens": 0,
+                "total_tokens": 0,
+            }
+        });
+        let res = Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(output.to_string())).boxed())?;
+        Ok(res)
+    }
+
+    async fn rerank(
+        &self,
+        req: hyper::Request<Incoming>,
+        api_key: Option<ServeApiKey>,
+    ) -> Result<AppResponse> {
+        let req_body = req.collect().await?.to_bytes();
+        let req_body: Value = serde_json::from_slice(&req_body)
+            .map_err(|err| anyhow!("Invalid request json, {err}"))?;
+
+        debug!("rerank request: {req_body}");
+        let req_body = serde_json::from_value(req_body)
+            .map_err(|err| anyhow!("Invalid request body, {err}"))?;
+
+        let RerankReqBody {
+            model: reranker_model_id,
+            documents,
+            query,
+            top_n,
+        } = req_body;
+
+        let top_n = top_n.unwrap_or(documents.len());
+
+        if let Some(api_key) = &api_key {
+            self.check_api_key(api_key, &reranker_model_id)?;
+        }
+
+        let config = Arc::new(RwLock::new(self.config.clone()));
+
+        let reranker_model =
+            Model::retrieve_model(&config.read(), &reranker_model_id, ModelType::Reranker)?;
+
+        let client = init_client(&config, Some(reranker_model))?;
+        let data = client
+            .rerank(&RerankData {
+                query,
+                documents: documents.clone(),
+                top_n,
+            })
+            .await?;
+
+        let results: Vec<_> = data
+            .into_iter()
+            .map(|v| {
+                json!({
+                    "index": v.index,
+                    "relevance_score": v.relevance_score,
+                    "document": documents.get(v.index).map(|v| json!(v)).unwrap_or_default(),
+                })
+            })
+            .collect();
+        let output = json!({
+            "id": uuid::Uuid::new_v4().to_string(),
+            "results": results,
+        });
+        let res = Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(output.to_string())).boxed())?;
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchRagReqBody {
+    name: String,
+    input: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionsReqBody {
+    model: String,
+    messages: Vec<Value>,
+    temperature: Option<f64>,
+    top_p: Option<f64>,
+    reasoning_effort: Option<ReasoningEffort>,
+    max_tokens: Option<isize>,
+    #[serde(default)]
+    stream: bool,
+    tools: Option<Vec<Value>>,
+    response_format: Option<Value>,
+    use_tools: Option<String>,
+    #[serde(default)]
+    tool_progress: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingsReqBody {
+    input: EmbeddingsReqBodyInput,
+    model: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum EmbeddingsReqBodyInput {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+struct RerankReqBody {
+    documents: Vec<String>,
+    query: String,
+    model: String,
+    top_n: Option<usize>,
+}
+
+#[derive(Debug)]
+struct HttpError {
+    status: StatusCode,
+    message: String,
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+fn http_error<T: Into<String>>(status: StatusCode, message: T) -> anyhow::Error {
+    anyhow::Error::new(HttpError {
+        status,
+        message: message.into(),
+    })
+}
+
+#[derive(Debug)]
+enum ResEvent {
+    First(Option<String>),
+    Text(String),
+    ToolCalls(Vec<ToolCall>),
+    ToolProgress(&'static str, Value),
+    Usage(u64, u64),
+    Error(String),
+    Done,
+}
+
+/// The reply of a chat request before it's rendered in the protocol of the route.
+enum ChatReply {
+    Output(ChatCompletionsOutput),
+    Stream(UnboundedReceiver<ResEvent>),
+}
+
+/// API clients get the thinking inline in `<think>` tags, as reasoning models emit it.
+fn fold_thinking(mut output: ChatCompletionsOutput) -> ChatCompletionsOutput {
+    let thinking: Vec<_> = output
+        .thinking
+        .iter()
+        .map(|v| v.text.as_str())
+        .filter(|v| !v.is_empty())
+        .collect();
+    if !thinking.is_empty() {
+        output.text = format!(
+            "<think>\n{}\n</think>\n\n{}",
+            thinking.join("\n\n"),
+            output.text
+        );
+    }
+    output
+}
+
+fn create_usage_event(output: &ChatCompletionsOutput, input_tokens: usize) -> ResEvent {
+    ResEvent::Usage(
+        output.input_tokens.unwrap_or(input_tokens as u64),
+        output
+            .output_tokens
+            .unwrap_or(estimate_token_length(&output.text) as u64),
+    )
+}
+
+fn create_sse_response<S>(stream: S) -> Result<AppResponse>
+where
+    S: futures_util::Stream<Item = Result<Frame<Bytes>, Infallible>> + Send + Sync + 'static,
+{
+    let res = Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .header("Connection", "keep-alive")
+        .body(BodyExt::boxed(StreamBody::new(stream)))?;
+    Ok(res)
+}
+
+async fn shutdown_signal() {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to install CTRL+C signal handler")
+}
+
+/// Runs the tool calls on the server until the model replies without any,
+/// streaming the text to `tx` when given.
+async fn run_tool_calls(
+    config: &GlobalConfig,
+    retry: &mut RetryState<'_>,
+    mut data: ChatCompletionsData,
+    abort_signal: &AbortSignal,
+    tx: Option<&UnboundedSender<ResEvent>>,
+    tool_progress: bool,
+) -> Result<ChatCompletionsOutput> {
+    let progress_tx = tx.filter(|_| tool_progress);
+    let mut input_tokens = 0;
+    let mut output_tokens = 0;
+    for _ in 0..TOOL_CALL_MAX_STEPS {
+        let estimate = retry.client().model().total_tokens(&data.messages);
+        let mut output = chat_step(retry, data.clone(), abort_signal, tx).await?;
+        input_tokens += output.input_tokens.unwrap_or(estimate as u64);
+        output_tokens += output
+            .output_tokens
+            .unwrap_or(estimate_token_length(&output.text) as u64);
+        let tool_calls = std::mem::take(&mut output.tool_calls);
+        if let Some(tx) = progress_tx {
+            for call in &tool_calls {
+                let _ = tx.send(ResEvent::ToolProgress(
+                    "tool_call",
+                    json!({ "id": call.id, "name": call.name, "arguments": call.arguments }),
+                ));
+            }
+        }
+        let tool_results = eval_tool_calls(config, tool_calls, abort_signal.clone()).await?;
+        if tool_results.is_empty() {
+            output.input_tokens = Some(input_tokens);
+            output.output_tokens = Some(output_tokens);
+            return Ok(output);
+        }
+        if let Some(tx) = progress_tx {
+            for result in &tool_results {
+                let _ = tx.send(ResEvent::ToolProgress(
+                    "tool_result",
+                    json!({ "id": result.call.id, "name": result.call.name, "output": result.output }),
+                ));
+            }
+        }
+        data.messages.push(Message::new(
+            MessageRole::Assistant,
+            MessageContent::ToolCalls(MessageContentToolCalls::new(
+                tool_results,
+                output.text,
+                output.thinking,
+            )),
+        ));
+    }
+    bail!("The tool calls did not finish within {TOOL_CALL_MAX_STEPS} steps")
+}
+
+/// Sends one request through the client's cache, budget, chat log and schema checks,
+/// retrying and falling back like the CLI, and forwards the streamed text to `tx` when given.
+/// The model that answered keeps answering the later steps of the request.
+async fn chat_step(
+    retry: &mut RetryState<'_>,
+    mut data: ChatCompletionsData,
+    abort_signal: &AbortSignal,
+    tx: Option<&UnboundedSender<ResEvent>>,
+) -> Result<ChatCompletionsOutput> {
+    loop {
+        let client = retry.client();
+        let (ret, emitted) = match tx.filter(|_| !client.model().no_stream()) {
+            Some(tx) => {
+                data.stream = true;
+                stream_step(client, data.clone(), abort_signal, tx).await
+            }
+            None => {
+                data.stream = false;
+                (
+                    client.send_chat_completions(None, data.clone()).await,
+                    false,
+                )
+            }
+        };
+        match ret {
+            Err(err) if !emitted && retry.next(&err, abort_signal).await? => {}
+            ret => return ret,
+        }
+    }
+}
+
+/// Returns whether any text was forwarded, after which a failure can't be retried.
+async fn stream_step(
+    client: &dyn Client,
+    data: ChatCompletionsData,
+    abort_signal: &AbortSignal,
+    tx: &UnboundedSender<ResEvent>,
+) -> (Result<ChatCompletionsOutput>, bool) {
+    let (sse_tx, mut sse_rx) = unbounded_channel();
+    let mut handler = SseHandler::new(sse_tx, abort_signal.clone());
+    let mut emitted = false;
+    let forward = async {
+        let mut thinking = false;
+        while let Some(event) = sse_rx.recv().await {
+            match event {
+                SseEvent::Thinking(text) => {
+                    if !thinking {
+                        let _ = tx.send(ResEvent::Text("<think>\n".into()));
+                        thinking = true;
+                    }
+                    let _ = tx.send(ResEvent::Text(text));
+                    emitted = true;
+                }
+                SseEvent::Text(text) => {
+                    if thinking {
+                        let _ = tx.send(ResEvent::Text("\n</think>\n\n".into()));
+                        thinking = false;
+                    }
+                    let _ = tx.send(ResEvent::Text(text));
+                    emitted = true;
+                }
+                SseEvent::Done => {
+                    if thinking {
+                        let _ = tx.send(ResEvent::Text("\n</think>\n\n".into()));
+                    }
+                    break;
+                }
+                SseEvent::Progress(_) => {}
+            }
+        }
+    };
+    let run = client.send_chat_completions_streaming(None, data, &mut handler);
+    let (_, ret) = tokio::join!(forward, run);
+    (ret.map(|_| handler.to_output()), emitted)
+}
+
+/// Replaces the last user message with the RAG template filled with the search results.
+async fn patch_rag_messages(
+    config: &GlobalConfig,
+    rag: &Rag,
+    messages: &mut [Message],
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let Some(message) = messages.iter_mut().rev().find(|v| v.role.is_user()) else {
+        return Ok(());
+    };
+    let text = message.content.to_text();
+    if text.is_empty() {
+        return Ok(());
+    }
+    let result = Config::search_rag(config, rag, &text, abort_signal).await?;
+    message.content.merge_prompt(|_| result.clone());
+    Ok(())
+}
+
+fn generate_id(prefix: &str) -> String {
+    let random_id = chrono::Utc::now().nanosecond();
+    format!("{prefix}{random_id}")
+}
+
+fn is_sessions_path(path: &str) -> bool {
+    path == "/v1/sessions" || path.starts_with("/v1/sessions/")
+}
+
+/// Refuses the tools that neither the key nor `serve_use_tools` allows, none by default.
+fn check_use_tools(config: &Config, api_key: Option<&ServeApiKey>, use_tools: &str) -> Result<()> {
+    let allowed = api_key
+        .and_then(|v| v.use_tools.as_deref())
+        .or(config.serve_use_tools.as_deref())
+        .map(|v| config.functions.select_names(v, &config.mapping_tools))
+        .unwrap_or_default();
+    let mut denied: Vec<String> = config
+        .functions
+        .select_names(use_tools, &config.mapping_tools)
+        .into_iter()
+        .filter(|v| !allowed.contains(v))
+        .collect();
+    if denied.is_empty() {
+        return Ok(());
+    }
+    denied.sort();
+    Err(http_error(
+        StatusCode::FORBIDDEN,
+        format!(
+            "The tools '{}' are not allowed on the server",
+            denied.join("', '")
+        ),
+    ))
+}
+
+/// The agent's own tools come with it, but the tools it uses from the server are checked.
+fn check_role_tools(config: &Config, api_key: Option<&ServeApiKey>, role: &Role) -> Result<()> {
+    match role.use_tools() {
+        Some(use_tools) => check_use_tools(config, api_key, &use_tools),
+        None => Ok(()),
+    }
+}
+
+fn is_page_path(path: &str) -> bool {
+    PLAYGROUND_PATHS.contains(&path) || ARENA_PATHS.contains(&path)
+}
+
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        match key == name {
+            true => urlencoding::decode(value).ok().map(|v| v.into_owned()),
+            false => None,
+        }
+    })
+}
+
+/// Keeps the request times of the last minute, returns false when the limit is reached.
+fn check_rate_limit(times: &mut VecDeque<Instant>, now: Instant, limit: usize) -> bool {
+    while times
+        .front()
+        .is_some_and(|v| now.duration_since(*v) >= RATE_LIMIT_WINDOW)
+    {
+        times.pop_front();
+    }
+    if times.len() >= limit {
+        return false;
+    }
+    times.push_back(now);
+    true
+}
+
+fn create_text_frame(id: &str, model: &str, created: i64, content: &str) -> Frame<Bytes> {
+    let delta = if content.is_empty() {
+        json!({ "role": "assistant", "content": content })
+    } else {
+        json!({ "content": content })
+    };
+    let choice = json!({
+        "index": 0,
+        "delta": delta,
+        "finish_reason": null,
+    });
+    let value = build_chat_completion_chunk_json(id, model, created, &choice);
+    Frame::data(Bytes::from(format!("data: {value}\n\n")))
+}
+
+fn create_tool_calls_frame(
+    id: &str,
+    model: &str,
+    created: i64,
+    tool_calls: &[ToolCall],
+) -> Frame<Bytes> {
+    let chunks = tool_calls
+        .iter()
+        .enumerate()
+        .flat_map(|(i, call)| {
+            let choice1 = json!({
+              "index": 0,
+              "delta": {
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [
+                  {
+                    "index": i,
+                    "id": call.id,
+                    "type": "function",
+                    "function": {
+                      "name": call.name,
+                      "arguments": ""
+                    }
+                  }
+                ]
+              },
+              "finish_reason": null
+            });
+            let choice2 = json!({
+              "index": 0,
+              "delta": {
+                "tool_calls": [
+                  {
+                    "index": i,
+                    "function": {
+                      "arguments": call.arguments.to_string(),
+                    }
+                  }
+                ]
+              },
+              "finish_reason": null
+            });
+            vec![
+                build_chat_completion_chunk_json(id, model, created, &choice1),
+                build_chat_completion_chunk_json(id, model, created, &choice2),
+            ]
+        })
+        .map(|v| format!("data: {v}\n\n"))
+        .collect::<Vec<String>>()
+        .join("");
+    Frame::data(Bytes::from(chunks))
+}
+
+fn create_done_frame(id: &str, model: &str, created: i64, has_tool_calls: bool) -> Frame<Bytes> {
+    let finish_reason = if has_tool_calls { "tool_calls" } else { "stop" };
+    let choice = json!({
+        "index": 0,
+        "delta": {},
+        "finish_reason": finish_reason,
+    });
+    let value = build_chat_completion_chunk_json(id, model, created, &choice);
+    Frame::data(Bytes::from(format!("data: {value}\n\ndata: [DONE]\n\n")))
+}
+
+fn create_event_frame(event: &str, data: &Value) -> Frame<Bytes> {
+    Frame::data(Bytes::from(format!("event: {event}\ndata: {data}\n\n")))
+}
+
+fn create_error_frame(message: &str) -> Frame<Bytes> {
+    let data = json!({
+        "error": {
+            "message": message,
+            "type": "server_error",
+        },
+    });
+    Frame::data(Bytes::from(format!("data: {data}\n\ndata: [DONE]\n\n")))
+}
+
+fn build_chat_completion_chunk_json(id: &str, model: &str, created: i64, choice: &Value) -> Value {
+    json!({
+        "id": id,
+        "object": "chat.completion.chunk",
+        "created": created,
+        "model": model,
+        "choices": [choice],
+    })
+}
+
+fn ret_non_stream(id: &str, model: &str, created: i64, output: &ChatCompletionsOutput) -> Bytes {
+    let id = output.id.as_deref().unwrap_or(id);
+    let input_tokens = output.input_tokens.unwrap_or_default();
+    let output_tokens = output.output_tokens.unwrap_or_default();
+    let total_tokens = input_tokens + output_tokens;
+    let choice = if output.tool_calls.is_empty() {
+        json!({
+            "index": 0,
+            "message": {
+                "role": "assistant",
+                "content": output.text,
+            },
+            "logprobs": null,
+            "finish_reason": "stop",
+        })
+    } else {
+        let content = if output.text.is_empty() {
+            Value::Null
+        } else {
+            output.text.clone().into()
+        };
+        let tool_calls: Vec<_> = output
+            .tool_calls
+            .iter()
+            .map(|call| {
+                json!({
+                    "id": call.id,
+                    "type": "function",
+                    "function": {
+                        "name": call.name,
+                        "arguments": call.arguments.to_string(),
+                    }
+                })
+            })
+            .collect();
+        json!({
+            "index": 0,
+            "message": {
+                "role": "assistant",
+                "content": content,
+                "tool_calls": tool_calls,
+            },
+            "logprobs": null,
+            "finish_reason": "tool_calls",
+        })
+    };
+    let res_body = json!({
+        "id": id,
+        "object": "chat.completion",
+        "created": created,
+        "model": model,
+        "choices": [choice],
+        "usage": {
+            "prompt_tokens": input_tokens,
+            "completion_tokens": output_tokens,
+            "total_tokens": total_tokens,
+        },
+    });
+    Bytes::from(res_body.to_string())
+}
+
+fn ret_err<T: std::fmt::Display>(status: StatusCode, err: T) -> AppResponse {
+    let data = json!({
+        "error": {
+            "message": err.to_string(),
+            "type": error_type(status),
+        },
+    });
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(data.to_string())).boxed())
+        .unwrap()
+}
+
+fn error_type(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::UNAUTHORIZED => "authentication_error",
+        StatusCode::FORBIDDEN => "permission_error",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limit_error",
+        _ => "invalid_request_error",
+    }
+}
+
+fn parse_messages(message: Vec<Value>) -> Result<Vec<Message>> {
+    let mut output = vec![];
+    let mut tool_results = None;
+    for (i, message) in message.into_iter().enumerate() {
+        let err = || anyhow!("Failed to parse '.messages[{i}]'");
+        let role = message["role"].as_str().ok_or_else(err)?;
+        let content = match message.get("content") {
+            Some(value) => {
+                if let Some(value) = value.as_str() {
+                    MessageContent::Text(value.to_string())
+                } else if value.is_array() {
+                    let value = serde_json::from_value(value.clone()).map_err(|_| err())?;
+                    MessageContent::Array(value)
+                } else if value.is_null() {
+                    MessageContent::Text(String::new())
+                } else {
+                    return Err(err());
+                }
+            }
+            None => MessageContent::Text(String::new()),
+        };
+        // Sessions round-trip the pinned turns and the signed thinking of tool calls
+        let pinned = message["pinned"].as_bool().unwrap_or_default();
+        match role {
+            "system" | "user" => {
+                let role = match role {
+                    "system" => MessageRole::System,
+                    "user" => MessageRole::User,
+                    _ => unreachable!(),
+                };
+                output.push(Message {
+                    pinned,
+                    ..Message::new(role, content)
+                })
+            }
+            "assistant" => {
+                let role = MessageRole::Assistant;
+                match message["tool_calls"].as_array() {
+                    Some(tool_calls) => {
+                        if tool_results.is_some() {
+                            return Err(err());
+                        }
+                        let mut list = vec![];
+                        for tool_call in tool_calls {
+                            if let (id, Some(name), Some(arguments)) = (
+                                tool_call["id"].as_str().map(|v| v.to_string()),
+                                tool_call["function"]["name"].as_str(),
+                                tool_call["function"]["arguments"].as_str(),
+                            ) {
+                                let arguments =
+                                    serde_json::from_str(arguments).map_err(|_| err())?;
+                                list.push((id, name.to_string(), arguments));
+                            } else {
+                                return Err(err());
+                            }
+                        }
+                        let thinking: Vec<ThinkingBlock> = match message.get("thinking") {
+                            Some(value) => {
+                                serde_json::from_value(value.clone()).map_err(|_| err())?
+                            }
+                            None => vec![],
+                        };
+                        tool_results = Some((content.to_text(), list, vec![], thinking, pinned));
+                    }
+                    None => output.push(Message {
+                        pinned,
+                        ..Message::new(role, content)
+                    }),
+                }
+            }
+            "tool" => match tool_results.take() {
+                Some((text, tool_calls, mut tool_values, thinking, pinned)) => {
+                    let tool_call_id = message["tool_call_id"].as_str().map(|v| v.to_string());
+                    let content = content.to_text();
+                    let value: Value = serde_json::from_str(&content)
+                        .ok()
+                        .unwrap_or_else(|| content.into());
+
+                    tool_values.push((value, tool_call_id));
+
+                    if tool_calls.len() == tool_values.len() {
+                        let mut list = vec![];
+                        for ((id, name, arguments), (value, tool_call_id)) in
+                            tool_calls.into_iter().zip(tool_values)
+                        {
+                            if id != tool_call_id {
+                                return Err(err());
+                            }
+                            list.push(ToolResult::new(ToolCall::new(name, arguments, id), value))
+                        }
+                        output.push(Message {
+                            pinned,
+                            ..Message::new(
+                                MessageRole::Assistant,
+                                MessageContent::ToolCalls(MessageContentToolCalls::new(
+                                    list, text, thinking,
+                                )),
+                            )
+                        });
+                        tool_results = None;
+                    } else {
+                        tool_results = Some((text, tool_calls, tool_values, thinking, pinned));
+                    }
+                }
+                None => return Err(err()),
+            },
+            _ => {
+                return Err(err());
+            }
+        }
+    }
+
+    if tool_results.is_some() {
+        bail!("Invalid messages");
+    }
+
+    Ok(output)
+}
+
+fn parse_tools(tools: Option<Vec<Value>>) -> Result<Option<Vec<FunctionDeclaration>>> {
+    let tools = match tools {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let mut functions = vec![];
+    for (i, tool) in tools.into_iter().enumerate() {
+        if let (Some("function"), Some(function)) = (
+            tool["type"].as_str(),
+            tool["function"]
+                .as_object()
+                .and_then(|v| serde_json::from_value(json!(v)).ok()),
+        ) {
+            functions.push(function);
+        } else {
+            bail!("Failed to parse '.tools[{i}]'")
+        }
+    }
+    Ok(Some(functions))
+}
+
+fn parse_response_format(response_format: Option<Value>) -> Result<Option<Value>> {
+    let response_format = match response_format {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    match response_format["type"].as_str() {
+        Some("text") => Ok(None),
+        Some("json_object") => Ok(Some(json!({ "type": "object" }))),
+        Some("json_schema") => match response_format["json_schema"].get("schema") {
+            Some(schema) => Ok(Some(schema.clone())),
+            None => bail!("Failed to parse '.response_format.json_schema.schema'"),
+        },
+        _ => bail!("Failed to parse '.response_format'"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_rate_limit() {
+        let mut times = VecDeque::new();
+        let now = Instant::now();
+        assert!(check_rate_limit(&mut times, now, 2));
+        assert!(check_rate_limit(&mut times, now, 2));
+        assert!(!check_rate_limit(
+            &mut times,
+            now + Duration::from_secs(30),
+            2
+        ));
+        assert!(check_rate_limit(&mut times, now + RATE_LIMIT_WINDOW, 2));
+        assert_eq!(times.len(), 1);
+    }
+
+    #[test]
+    fn test_query_param() {
+        assert_eq!(
+            query_param("num=2&api_key=a%2Bb", "api_key").as_deref(),
+            Some("a+b")
+        );
+        assert_eq!(query_param("num=2", "api_key"), None);
+        assert!(is_page_path("/arena"));
+        assert!(!is_page_path("/v1/models"));
+    }
+
+    #[test]
+    fn test_check_use_tools() {
+        let dir = std::env::temp_dir().join(format!("aichat-serve-tools-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("functions.json");
+        let declaration = |name: &str| json!({ "name": name, "description": name, "parameters": { "type": "object", "properties": {} } });
+        std::fs::write(
+            &path,
+            json!([declaration("fs_cat"), declaration("web_search")]).to_string(),
+        )
+        .unwrap();
+        let mut config = Config {
+            functions: Functions::init(&path).unwrap(),
+            ..Default::default()
+        };
+        std::fs::remove_dir_all(&dir).unwrap();
+        let api_key = |use_tools: Option<&str>| ServeApiKey {
+            name: "alice".into(),
+            key: "k".into(),
+            models: vec![],
+            rate_limit: None,
+            token_quota: None,
+            use_tools: use_tools.map(|v| v.into()),
+            admin: false,
+        };
+
+        assert!(check_use_tools(&config, None, "fs_cat").is_err());
+        config.serve_use_tools = Some("fs_cat".into());
+        assert!(check_use_tools(&config, None, "fs_cat").is_ok());
+        let err = check_use_tools(&config, None, "fs_cat,web_search").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The tools 'web_search' are not allowed on the server"
+        );
+        assert!(check_use_tools(&config, Some(&api_key(Some("web_search"))), "fs_cat").is_err());
+        assert!(check_use_tools(&config, Some(&api_key(None)), "fs_cat").is_ok());
+    }
+
+    #[test]
+    fn test_check_model() {
+        let config = Arc::new(RwLock::new(Config::default()));
+        let server = Server::new(&config);
+        let api_key = ServeApiKey {
+            name: "alice".into(),
+            key: "k".into(),
+            models: vec!["role:*".into(), "rag:*".into(), "oa:cheap".into()],
+            rate_limit: None,
+            token_quota: None,
+            use_tools: None,
+            admin: false,
+        };
+
+        assert!(server.check_api_key(&api_key, "role:coder").is_ok());
+        assert!(server
+            .check_model(Some(&api_key), &Model::new("oa", "cheap"))
+            .is_ok());
+        let err = server
+            .check_model(Some(&api_key), &Model::new("oa", "pricey"))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The API key is not allowed to use the model 'oa:pricey'"
+        );
+        assert!(server
+            .check_model(None, &Model::new("oa", "pricey"))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_check_role_tools() {
+        let dir = std::env::temp_dir().join(format!("aichat-serve-agent-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("functions.json");
+        let declaration = |name: &str| json!({ "name": name, "description": name, "parameters": { "type": "object", "properties": {} } });
+        std::fs::write(
+            &path,
+            json!([declaration("fs_cat"), declaration("execute_command")]).to_string(),
+        )
+        .unwrap();
+        let config = Config {
+            functions: Functions::init(&path).unwrap(),
+            serve_use_tools: Some("fs_cat".into()),
+            ..Default::default()
+        };
+        std::fs::remove_dir_all(&dir).unwrap();
+        let mut role = Role::new("agent", "");
+
+        assert!(check_role_tools(&config, None, &role).is_ok());
+        role.set_use_tools(Some("fs_cat".into()));
+        assert!(check_role_tools(&config, None, &role).is_ok());
+        role.set_use_tools(Some("fs_cat,execute_command".into()));
+        let err = check_role_tools(&config, None, &role).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The tools 'execute_command' are not allowed on the server"
+        );
+    }
+}
@@ -0,0 +1,425 @@
+use super::*;
+
+/// Converts a Responses API request into a chat completions request.
+pub(super) fn parse_responses_req(body: Value) -> Result<ChatCompletionsReqBody> {
+    let model = body["model"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Missing 'model'"))?
+        .to_string();
+    let mut messages = vec![];
+    if let Some(instructions) = body["instructions"].as_str() {
+        messages.push(json!({ "role": "system", "content": instructions }));
+    }
+    match &body["input"] {
+        Value::String(text) => messages.push(json!({ "role": "user", "content": text })),
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                parse_input_item(item, &mut messages)
+                    .ok_or_else(|| anyhow!("Failed to parse '.input[{i}]'"))?;
+            }
+        }
+        _ => bail!("Missing 'input'"),
+    }
+    let tools = match body["tools"].as_array() {
+        Some(list) => {
+            let mut tools = vec![];
+            for (i, tool) in list.iter().enumerate() {
+                if tool["type"].as_str() != Some("function") {
+                    bail!("Unsupported tool '.tools[{i}]', only function tools are supported");
+                }
+                tools.push(json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool["name"],
+                        "description": tool["description"],
+                        "parameters": tool["parameters"],
+                    }
+                }));
+            }
+            Some(tools)
+        }
+        None => None,
+    };
+    let format = &body["text"]["format"];
+    let response_format = match format["type"].as_str() {
+        Some("json_schema") => Some(json!({
+            "type": "json_schema",
+            "json_schema": { "name": format["name"], "schema": format["schema"] },
+        })),
+        Some("json_object") => Some(json!({ "type": "json_object" })),
+        _ => None,
+    };
+    Ok(ChatCompletionsReqBody {
+        model,
+        messages,
+        temperature: body["temperature"].as_f64(),
+        top_p: body["top_p"].as_f64(),
+        reasoning_effort: body["reasoning"]["effort"]
+            .as_str()
+            .and_then(|v| v.parse().ok()),
+        max_tokens: body["max_output_tokens"].as_i64().map(|v| v as isize),
+        stream: body["stream"].as_bool().unwrap_or_default(),
+        tools,
+        response_format,
+        use_tools: body["use_tools"].as_str().map(|v| v.to_string()),
+        tool_progress: body["tool_progress"].as_bool().unwrap_or_default(),
+    })
+}
+
+fn parse_input_item(item: &Value, messages: &mut Vec<Value>) -> Option<()> {
+    match item["type"].as_str() {
+        Some("function_call") => {
+            let tool_call = json!({
+                "id": item["call_id"],
+                "type": "function",
+                "function": { "name": item["name"], "arguments": item["arguments"] },
+            });
+            // Consecutive calls belong to one assistant message
+            match messages.last_mut() {
+                Some(message) if message["tool_calls"].is_array() => {
+                    message["tool_calls"].as_array_mut()?.push(tool_call)
+                }
+                _ => messages.push(json!({
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [tool_call],
+                })),
+            }
+        }
+        Some("function_call_output") => {
+            let output = match &item["output"] {
+                Value::String(v) => v.clone(),
+                v => v.to_string(),
+            };
+            messages.push(json!({
+                "role": "tool",
+                "tool_call_id": item["call_id"],
+                "content": output,
+            }));
+        }
+        Some("message") | None => {
+            let role = match item["role"].as_str()? {
+                "developer" => "system",
+                v => v,
+            };
+            let content = match &item["content"] {
+                Value::String(text) => json!(text),
+                Value::Array(parts) => {
+                    let mut list = vec![];
+                    for part in parts {
+                        match part["type"].as_str()? {
+                            "input_text" | "output_text" | "text" => {
+                                list.push(json!({ "type": "text", "text": part["text"] }))
+                            }
+                            "input_image" => list.push(json!({
+                                "type": "image_url",
+                                "image_url": { "url": part["image_url"] },
+                            })),
+                            _ => return None,
+                        }
+                    }
+                    if role == "user" {
+                        json!(list)
+                    } else {
+                        let texts: Vec<&str> =
+                            list.iter().filter_map(|v| v["text"].as_str()).collect();
+                        json!(texts.join("\n\n"))
+                    }
+                }
+                _ => return None,
+            };
+            messages.push(json!({ "role": role, "content": content }));
+        }
+        Some(_) => return None,
+    }
+    Some(())
+}
+
+pub(super) fn ret_responses_non_stream(
+    id: &str,
+    model: &str,
+    created: i64,
+    output: &ChatCompletionsOutput,
+) -> Bytes {
+    let mut items = vec![];
+    if !output.text.is_empty() || output.tool_calls.is_empty() {
+        items.push(message_item(id, "completed", &output.text));
+    }
+    for (i, call) in output.tool_calls.iter().enumerate() {
+        items.push(function_call_item(call, i, "completed"));
+    }
+    let usage = (
+        output.input_tokens.unwrap_or_default(),
+        output.output_tokens.unwrap_or_default(),
+    );
+    let value = build_response(id, model, created, "completed", items, Some(usage));
+    Bytes::from(value.to_string())
+}
+
+pub(super) fn responses_stream(
+    rx: UnboundedReceiver<ResEvent>,
+    id: String,
+    model: String,
+    created: i64,
+) -> UnboundedReceiver<Frame<Bytes>> {
+    let (tx, frame_rx) = unbounded_channel();
+    let stream = ResponsesStream {
+        tx,
+        id,
+        model,
+        created,
+        sequence: 0,
+        items: vec![],
+        text: None,
+        usage: None,
+    };
+    tokio::spawn(stream.run(rx));
+    frame_rx
+}
+
+struct ResponsesStream {
+    tx: UnboundedSender<Frame<Bytes>>,
+    id: String,
+    model: String,
+    created: i64,
+    sequence: usize,
+    items: Vec<Value>,
+    /// The output index and contents of the message being streamed
+    text: Option<(usize, String)>,
+    usage: Option<(u64, u64)>,
+}
+
+impl ResponsesStream {
+    async fn run(mut self, mut rx: UnboundedReceiver<ResEvent>) {
+        let response = self.response("in_progress");
+        self.send("response.created", json!({ "response": response }));
+        while let Some(event) = rx.recv().await {
+            match event {
+                ResEvent::Text(delta) => self.text_delta(&delta),
+                ResEvent::ToolCalls(tool_calls) => {
+                    self.finish_text();
+                    for call in tool_calls {
+                        self.function_call(&call);
+                    }
+                }
+                ResEvent::ToolProgress(event, data) => {
+                    let _ = self.tx.send(create_event_frame(event, &data));
+                }
+                ResEvent::Usage(input_tokens, output_tokens) => {
+                    self.usage = Some((input_tokens, output_tokens))
+                }
+                ResEvent::Error(message) => {
+                    self.send(
+                        "error",
+                        json!({ "code": "server_error", "message": message }),
+                    );
+                    break;
+                }
+                ResEvent::Done => {
+                    self.finish_text();
+                    let response = self.response("completed");
+                    self.send("response.completed", json!({ "response": response }));
+                    break;
+                }
+                ResEvent::First(_) => {}
+            }
+        }
+    }
+
+    fn text_delta(&mut self, delta: &str) {
+        if delta.is_empty() {
+            return;
+        }
+        let output_index = match &mut self.text {
+            Some((output_index, text)) => {
+                text.push_str(delta);
+                *output_index
+            }
+            None => {
+                let output_index = self.items.len();
+                let item = message_item(&self.id, "in_progress", "");
+                self.send(
+                    "response.output_item.added",
+                    json!({ "output_index": output_index, "item": item }),
+                );
+                self.send(
+                    "response.content_part.added",
+                    json!({
+                        "item_id": item["id"],
+                        "output_index": output_index,
+                        "content_index": 0,
+                        "part": { "type": "output_text", "text": "", "annotations": [] },
+                    }),
+                );
+                self.text = Some((output_index, delta.to_string()));
+                output_index
+            }
+        };
+        self.send(
+            "response.output_text.delta",
+            json!({
+                "item_id": message_id(&self.id),
+                "output_index": output_index,
+                "content_index": 0,
+                "delta": delta,
+            }),
+        );
+    }
+
+    fn finish_text(&mut self) {
+        let Some((output_index, text)) = self.text.take() else {
+            return;
+        };
+        let item = message_item(&self.id, "completed", &text);
+        self.send(
+            "response.output_text.done",
+            json!({
+                "item_id": item["id"],
+                "output_index": output_index,
+                "content_index": 0,
+                "text": text,
+            }),
+        );
+        self.send(
+            "response.content_part.done",
+            json!({
+                "item_id": item["id"],
+                "output_index": output_index,
+                "content_index": 0,
+                "part": item["content"][0],
+            }),
+        );
+        self.send(
+            "response.output_item.done",
+            json!({ "output_index": output_index, "item": item }),
+        );
+        self.items.push(item);
+    }
+
+    fn function_call(&mut self, call: &ToolCall) {
+        let output_index = self.items.len();
+        let item = function_call_item(call, output_index, "completed");
+        let mut added_item = item.clone();
+        added_item["arguments"] = "".into();
+        added_item["status"] = "in_progress".into();
+        self.send(
+            "response.output_item.added",
+            json!({ "output_index": output_index, "item": added_item }),
+        );
+        self.send(
+            "response.function_call_arguments.delta",
+            json!({ "item_id": item["id"], "output_index": output_index, "delta": item["arguments"] }),
+        );
+        self.send(
+            "response.function_call_arguments.done",
+            json!({ "item_id": item["id"], "output_index": output_index, "arguments": item["arguments"] }),
+        );
+        self.send(
+            "response.output_item.done",
+            json!({ "output_index": output_index, "item": item }),
+        );
+        self.items.push(item);
+    }
+
+    fn response(&self, status: &str) -> Value {
+        build_response(
+            &self.id,
+            &self.model,
+            self.created,
+            status,
+            self.items.clone(),
+            self.usage,
+        )
+    }
+
+    fn send(&mut self, event: &str, data: Value) {
+        let mut value = json!({ "type": event, "sequence_number": self.sequence });
+        if let (Some(value), Value::Object(data)) = (value.as_object_mut(), data) {
+            value.extend(data);
+        }
+        self.sequence += 1;
+        let _ = self.tx.send(create_event_frame(event, &value));
+    }
+}
+
+fn build_response(
+    id: &str,
+    model: &str,
+    created: i64,
+    status: &str,
+    output: Vec<Value>,
+    usage: Option<(u64, u64)>,
+) -> Value {
+    let usage = match usage {
+        Some((input_tokens, output_tokens)) => json!({
+            "input_tokens": input_tokens,
+            "output_tokens": output_tokens,
+            "total_tokens": input_tokens + output_tokens,
+        }),
+        None => Value::Null,
+    };
+    json!({
+        "id": id,
+        "object": "response",
+        "created_at": created,
+        "status": status,
+        "model": model,
+        "output": output,
+        "usage": usage,
+    })
+}
+
+fn message_id(id: &str) -> String {
+    format!("msg_{}", id.trim_start_matches("resp_"))
+}
+
+fn message_item(id: &str, status: &str, text: &str) -> Value {
+    json!({
+        "type": "message",
+        "id": message_id(id),
+        "status": status,
+        "role": "assistant",
+        "content": [{ "type": "output_text", "text": text, "annotations": [] }],
+    })
+}
+
+fn function_call_item(call: &ToolCall, index: usize, status: &str) -> Value {
+    let call_id = call.id.clone().unwrap_or_else(|| format!("call_{index}"));
+    json!({
+        "type": "function_call",
+        "id": format!("fc_{call_id}"),
+        "call_id": call_id,
+        "name": call.name,
+        "arguments": call.arguments.to_string(),
+        "status": status,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_responses_req() {
+        let body = json!({
+            "model": "default",
+            "instructions": "Be brief",
+            "input": [
+                { "role": "user", "content": [{ "type": "input_text", "text": "weather?" }] },
+                { "type": "function_call", "call_id": "c1", "name": "get_weather", "arguments": "{}" },
+                { "type": "function_call", "call_id": "c2", "name": "get_time", "arguments": "{}" },
+                { "type": "function_call_output", "call_id": "c1", "output": "sunny" },
+                { "type": "function_call_output", "call_id": "c2", "output": "noon" },
+            ],
+            "text": { "format": { "type": "json_object" } },
+        });
+        let req = parse_responses_req(body).unwrap();
+        assert_eq!(req.messages.len(), 5);
+        assert_eq!(req.messages[0]["role"], "system");
+        assert_eq!(req.messages[2]["tool_calls"].as_array().unwrap().len(), 2);
+        assert_eq!(req.messages[4]["tool_call_id"], "c2");
+        assert_eq!(req.response_format, Some(json!({ "type": "json_object" })));
+        let messages = parse_messages(req.messages).unwrap();
+        assert_eq!(messages.len(), 3);
+    }
+}
@@ -0,0 +1,300 @@
+use super::*;
+
+use anyhow::Context;
+use std::fs::remove_file;
+
+#[derive(Debug, Deserialize)]
+struct SessionReqBody {
+    name: Option<String>,
+    model: Option<String>,
+    temperature: Option<f64>,
+    top_p: Option<f64>,
+    messages: Option<Vec<Value>>,
+}
+
+impl Server {
+    pub(super) fn list_sessions(&self) -> Result<AppResponse> {
+        let names = self.config.list_sessions().into_iter().chain(
+            self.config
+                .list_autoname_sessions()
+                .into_iter()
+                .map(|v| format!("_/{v}")),
+        );
+        let sessions: Vec<Value> = names
+            .map(|name| {
+                let updated_at = std::fs::metadata(self.config.session_file(&name))
+                    .and_then(|v| v.modified())
+                    .ok()
+                    .map(|v| chrono::DateTime::<Utc>::from(v).timestamp());
+                json!({ "name": name, "updated_at": updated_at })
+            })
+            .collect();
+        let data = json!({ "data": sessions });
+        let res = Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(data.to_string())).boxed())?;
+        Ok(res)
+    }
+
+    pub(super) async fn create_session(
+        &self,
+        req: hyper::Request<Incoming>,
+    ) -> Result<AppResponse> {
+        let req_body = parse_session_req(req).await?;
+        let name = req_body
+            .name
+            .clone()
+            .ok_or_else(|| anyhow!("Missing 'name'"))?;
+        check_session_name(&name)?;
+        let lock = self.session_lock(&name);
+        let _guard = lock.lock();
+        let session_path = self.config.session_file(&name);
+        if session_path.exists() {
+            return Err(http_error(
+                StatusCode::CONFLICT,
+                format!("Session '{name}' already exists"),
+            ));
+        }
+        let mut session = Session::new(&self.config, &name);
+        self.save_session(&mut session, &name, req_body)
+    }
+
+    pub(super) async fn session(
+        &self,
+        req: hyper::Request<Incoming>,
+        name: &str,
+    ) -> Result<AppResponse> {
+        let name = urlencoding::decode(name)?;
+        match *req.method() {
+            Method::PUT => self.update_session(req, &name).await,
+            Method::DELETE => self.delete_session(&name),
+            _ => self.get_session(&name),
+        }
+    }
+
+    fn get_session(&self, name: &str) -> Result<AppResponse> {
+        let session = self.load_session(name)?;
+        ret_session(&session)
+    }
+
+    async fn update_session(
+        &self,
+        req: hyper::Request<Incoming>,
+        name: &str,
+    ) -> Result<AppResponse> {
+        let req_body = parse_session_req(req).await?;
+        // Another write between the load and the save would lose its messages
+        let lock = self.session_lock(name);
+        let _guard = lock.lock();
+        let mut session = self.load_session(name)?;
+        self.save_session(&mut session, name, req_body)
+    }
+
+    fn delete_session(&self, name: &str) -> Result<AppResponse> {
+        check_session_name(name)?;
+        let lock = self.session_lock(name);
+        let _guard = lock.lock();
+        let session_path = self.config.session_file(name);
+        if !session_path.exists() {
+            return Err(http_error(
+                StatusCode::NOT_FOUND,
+                format!("Session '{name}' not found"),
+            ));
+        }
+        remove_file(&session_path).with_context(|| format!("Failed to delete session '{name}'"))?;
+        let data = json!({ "name": name, "deleted": true });
+        let res = Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(data.to_string())).boxed())?;
+        Ok(res)
+    }
+
+    fn session_lock(&self, name: &str) -> Arc<Mutex<()>> {
+        self.session_locks
+            .lock()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    fn load_session(&self, name: &str) -> Result<Session> {
+        check_session_name(name)?;
+        let session_path = self.config.session_file(name);
+        if !session_path.exists() {
+            return Err(http_error(
+                StatusCode::NOT_FOUND,
+                format!("Session '{name}' not found"),
+            ));
+        }
+        Session::load(&self.config, name, &session_path)
+    }
+
+    fn save_session(
+        &self,
+        session: &mut Session,
+        name: &str,
+        req_body: SessionReqBody,
+    ) -> Result<AppResponse> {
+        let SessionReqBody {
+            model,
+            temperature,
+            top_p,
+            messages,
+            ..
+        } = req_body;
+        if let Some(model_id) = model.filter(|v| v != DEFAULT_MODEL_NAME) {
+            let model = Model::retrieve_model(&self.config, &model_id, ModelType::Chat)?;
+            session.set_model(&model);
+        }
+        if temperature.is_some() {
+            session.set_temperature(temperature);
+        }
+        if top_p.is_some() {
+            session.set_top_p(top_p);
+        }
+        if let Some(messages) = messages {
+            session.append_messages(parse_messages(messages)?);
+        }
+        session.save(name, &self.config.session_file(name), false)?;
+        ret_session(session)
+    }
+}
+
+async fn parse_session_req(req: hyper::Request<Incoming>) -> Result<SessionReqBody> {
+    let req_body = req.collect().await?.to_bytes();
+    let req_body: Value =
+        serde_json::from_slice(&req_body).map_err(|err| anyhow!("Invalid request json, {err}"))?;
+
+    debug!("session request: {req_body}");
+    serde_json::from_value(req_body).map_err(|err| anyhow!("Invalid request body, {err}"))
+}
+
+/// Session names map to files in the sessions dir, autonamed sessions live in `_/`.
+fn check_session_name(name: &str) -> Result<()> {
+    let base_name = name.strip_prefix("_/").unwrap_or(name);
+    if base_name.is_empty()
+        || base_name == TEMP_SESSION_NAME
+        || base_name.starts_with('.')
+        || base_name.contains(['/', '\\'])
+    {
+        bail!("Invalid session name '{name}'");
+    }
+    Ok(())
+}
+
+fn ret_session(session: &Session) -> Result<AppResponse> {
+    let data = json!({
+        "name": session.name(),
+        "model": session.model().id(),
+        "temperature": session.temperature(),
+        "top_p": session.top_p(),
+        "role": session.role_name(),
+        "messages": to_message_values(session.messages()),
+    });
+    let res = Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(data.to_string())).boxed())?;
+    Ok(res)
+}
+
+/// Renders session messages in the chat completions format, the reverse of `parse_messages`.
+fn to_message_values(messages: &[Message]) -> Vec<Value> {
+    let mut output = vec![];
+    for message in messages {
+        let index = output.len();
+        match &message.content {
+            MessageContent::Text(text) => {
+                output.push(json!({ "role": message.role, "content": text }))
+            }
+            MessageContent::Array(parts) => {
+                output.push(json!({ "role": message.role, "content": parts }))
+            }
+            MessageContent::ToolCalls(MessageContentToolCalls {
+                tool_results,
+                text,
+                thinking,
+                ..
+            }) => {
+                let tool_calls: Vec<Value> = tool_results
+                    .iter()
+                    .map(|tool_result| {
+                        json!({
+                            "id": tool_result.call.id,
+                            "type": "function",
+                            "function": {
+                                "name": tool_result.call.name,
+                                "arguments": tool_result.call.arguments.to_string(),
+                            },
+                        })
+                    })
+                    .collect();
+                let text = if text.is_empty() {
+                    Value::Null
+                } else {
+                    text.clone().into()
+                };
+                let mut value = json!({
+                    "role": MessageRole::Assistant,
+                    "content": text,
+                    "tool_calls": tool_calls,
+                });
+                if !thinking.is_empty() {
+                    value["thinking"] = json!(thinking);
+                }
+                output.push(value);
+                for tool_result in tool_results {
+                    output.push(json!({
+                        "role": "tool",
+                        "content": tool_result.output.to_string(),
+                        "tool_call_id": tool_result.call.id,
+                    }));
+                }
+            }
+        }
+        if message.pinned {
+            output[index]["pinned"] = true.into();
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_session_name() {
+        assert!(check_session_name("work").is_ok());
+        assert!(check_session_name("_/20250101T000000-notes").is_ok());
+        assert!(check_session_name("temp").is_err());
+        assert!(check_session_name("../roles/foo").is_err());
+        assert!(check_session_name("_/..").is_err());
+        assert!(check_session_name("").is_err());
+    }
+
+    #[test]
+    fn test_to_message_values() {
+        let messages = vec![
+            json!({ "role": "user", "content": "weather?" }),
+            json!({ "role": "assistant", "content": null, "tool_calls": [
+                { "id": "c1", "type": "function", "function": { "name": "get_weather", "arguments": "{}" } },
+            ]}),
+            json!({ "role": "tool", "tool_call_id": "c1", "content": "sunny" }),
+            json!({ "role": "assistant", "content": "It's sunny" }),
+        ];
+        let parsed = parse_messages(messages.clone()).unwrap();
+        let values = to_message_values(&parsed);
+        assert_eq!(values.len(), 4);
+        assert_eq!(values[1]["tool_calls"][0]["id"], "c1");
+        assert_eq!(values[3], messages[3]);
+        assert_eq!(parse_messages(values).unwrap().len(), parsed.len());
+
+        let mut messages = messages;
+        messages[0]["pinned"] = true.into();
+        messages[1]["thinking"] = json!([{ "text": "hmm", "signature": "sig" }]);
+        let values = to_message_values(&parse_messages(messages).unwrap());
+        assert_eq!(values[0]["pinned"], true);
+        assert_eq!(values[1]["thinking"][0]["signature"], "sig");
+        assert!(values[3].get("pinned").is_none());
+    }
+}
@@ -5,9 +5,10 @@ use std::{
     env,
     ffi::OsStr,
     fs::OpenOptions,
-    io::{self, Write},
+    io::{self, Read, Write},
     path::{Path, PathBuf},
-    process::Command,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, bail, Context, Result};
@@ -16,6 +17,9 @@ use std::sync::LazyLock;
 
 pub static SHELL: LazyLock<Shell> = LazyLock::new(detect_shell);
 
+/// The bytes of each stream that a captured command keeps by default.
+pub const MAX_CAPTURED_OUTPUT: usize = 1024 * 1024;
+
 pub struct Shell {
     pub name: String,
     pub cmd: String,
@@ -90,6 +94,198 @@ pub fn run_command<T: AsRef<OsStr>>(
     Ok(status.code().unwrap_or_default())
 }
 
+/// How a command run by `run_command_abortable` ended.
+pub enum CommandStatus {
+    /// The exit code and the captured stdout and stderr
+    Exited(i32, String, String),
+    TimedOut,
+    Aborted,
+}
+
+/// Run the command until it exits, times out or `abort_signal` fires, calling `on_tick` while waiting.
+/// The command runs in its own process group, which is killed as a whole.
+/// With `capture`, up to that many bytes of stdout and stderr are kept and the rest is drained,
+/// otherwise the command gets the terminal while it runs.
+pub fn run_command_abortable(
+    command: &mut Command,
+    capture: Option<usize>,
+    timeout: Option<Duration>,
+    abort_signal: &AbortSignal,
+    on_tick: impl FnMut(),
+) -> Result<CommandStatus> {
+    if capture.is_some() {
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+    }
+    let foreground = capture.is_none() && is_terminal_foreground();
+    set_process_group(command, foreground);
+    let mut child = command.spawn()?;
+    if foreground {
+        set_terminal_foreground(Some(child.id()));
+    }
+    let ret = wait_child(
+        &mut child,
+        capture.unwrap_or_default(),
+        timeout,
+        abort_signal,
+        on_tick,
+    );
+    if foreground {
+        set_terminal_foreground(None);
+    }
+    ret
+}
+
+fn wait_child(
+    child: &mut Child,
+    max_output: usize,
+    timeout: Option<Duration>,
+    abort_signal: &AbortSignal,
+    mut on_tick: impl FnMut(),
+) -> Result<CommandStatus> {
+    let stdout = child
+        .stdout
+        .take()
+        .map(|v| spawn_read_to_end(v, max_output));
+    let stderr = child
+        .stderr
+        .take()
+        .map(|v| spawn_read_to_end(v, max_output));
+    let start = Instant::now();
+    let status = loop {
+        let ret = match child.try_wait() {
+            Ok(Some(status)) => {
+                on_tick();
+                break status;
+            }
+            Ok(None) if abort_signal.aborted() => Ok(CommandStatus::Aborted),
+            Ok(None) if timeout.is_some_and(|v| start.elapsed() >= v) => {
+                Ok(CommandStatus::TimedOut)
+            }
+            Ok(None) => {
+                on_tick();
+                std::thread::sleep(Duration::from_millis(50));
+                continue;
+            }
+            Err(err) => Err(err.into()),
+        };
+        kill_child(child);
+        let _ = child.wait();
+        return ret;
+    };
+    // Ctrl-C goes to the command while it owns the terminal
+    #[cfg(unix)]
+    if std::os::unix::process::ExitStatusExt::signal(&status) == Some(libc::SIGINT) {
+        return Ok(CommandStatus::Aborted);
+    }
+    let join = |handle: Option<std::thread::JoinHandle<Vec<u8>>>| {
+        handle
+            .and_then(|v| v.join().ok())
+            .map(|v| String::from_utf8_lossy(&v).to_string())
+            .unwrap_or_default()
+    };
+    Ok(CommandStatus::Exited(
+        status.code().unwrap_or(-1),
+        join(stdout),
+        join(stderr),
+    ))
+}
+
+#[cfg(unix)]
+fn set_process_group(command: &mut Command, foreground: bool) {
+    use std::os::unix::process::CommandExt;
+
+    command.process_group(0);
+    if foreground {
+        // Also taken in the child so it can't read the terminal before the parent hands it over
+        unsafe {
+            command.pre_exec(|| {
+                let handler = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+                libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpid());
+                libc::signal(libc::SIGTTOU, handler);
+                Ok(())
+            });
+        }
+    }
+}
+
+#[cfg(windows)]
+fn set_process_group(command: &mut Command, _foreground: bool) {
+    use std::os::windows::process::CommandExt;
+
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+    command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+}
+
+#[cfg(not(any(unix, windows)))]
+fn set_process_group(_command: &mut Command, _foreground: bool) {}
+
+#[cfg(unix)]
+fn is_terminal_foreground() -> bool {
+    *IS_STDIN_TERMINAL && unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp() }
+}
+
+#[cfg(not(unix))]
+fn is_terminal_foreground() -> bool {
+    false
+}
+
+/// Give the terminal to the process group `pgid`, or take it back with `None`.
+#[cfg(unix)]
+fn set_terminal_foreground(pgid: Option<u32>) {
+    unsafe {
+        let pgid = match pgid {
+            Some(v) => v as libc::pid_t,
+            None => libc::getpgrp(),
+        };
+        let handler = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+        libc::signal(libc::SIGTTOU, handler);
+    }
+}
+
+#[cfg(not(unix))]
+fn set_terminal_foreground(_pgid: Option<u32>) {}
+
+#[cfg(unix)]
+fn kill_child(child: &mut Child) {
+    unsafe {
+        libc::kill(-(child.id() as i32), libc::SIGKILL);
+    }
+}
+
+#[cfg(windows)]
+fn kill_child(child: &mut Child) {
+    let ret = Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &child.id().to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    if !ret.is_ok_and(|v| v.success()) {
+        let _ = child.kill();
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn kill_child(child: &mut Child) {
+    let _ = child.kill();
+}
+
+/// Keeps at most `max_output` bytes and drains the rest, so the command never blocks on a full pipe.
+fn spawn_read_to_end<R: Read + Send + 'static>(
+    mut reader: R,
+    max_output: usize,
+) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = vec![];
+        let _ = (&mut reader).take(max_output as u64).read_to_end(&mut buf);
+        let _ = io::copy(&mut reader, &mut io::sink());
+        buf
+    })
+}
+
 pub fn run_command_with_output<T: AsRef<OsStr>>(
     cmd: &str,
     args: &[T],
@@ -14,6 +14,11 @@ pub fn hmac_sha256(key: &[u8], msg: &str) -> Vec<u8> {
     mac.finalize().into_bytes().to_vec()
 }
 
+/// Compares two byte strings in time that only depends on their length.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 pub fn hex_encode(bytes: &[u8]) -> String {
     bytes
         .iter()
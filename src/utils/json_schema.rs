@@ -0,0 +1,181 @@
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+/// Validates a value against the commonly used subset of JSON Schema.
+pub fn validate_json_schema(schema: &Value, value: &Value) -> Result<()> {
+    validate(schema, schema, value, "$")
+}
+
+fn validate(root: &Value, schema: &Value, value: &Value, path: &str) -> Result<()> {
+    let schema = match schema["$ref"].as_str() {
+        Some(reference) => resolve_ref(root, reference)?,
+        None => schema,
+    };
+    if schema.as_bool() == Some(false) {
+        bail!("{path} is not allowed");
+    }
+    if let Some(types) = schema.get("type") {
+        let matched = match types {
+            Value::Array(list) => list
+                .iter()
+                .any(|v| v.as_str().is_some_and(|v| is_type(value, v))),
+            _ => types.as_str().is_some_and(|v| is_type(value, v)),
+        };
+        if !matched {
+            bail!("{path} must be of type {types}, got {value}");
+        }
+    }
+    if let Some(list) = schema["enum"].as_array() {
+        if !list.contains(value) {
+            bail!("{path} must be one of {}", schema["enum"]);
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            bail!("{path} must be {expected}");
+        }
+    }
+    if let Some(list) = schema["anyOf"].as_array() {
+        if !list.iter().any(|v| validate(root, v, value, path).is_ok()) {
+            bail!("{path} does not match any of the schemas in anyOf");
+        }
+    }
+    if let Some(list) = schema["oneOf"].as_array() {
+        let count = list
+            .iter()
+            .filter(|v| validate(root, v, value, path).is_ok())
+            .count();
+        if count != 1 {
+            bail!("{path} must match exactly one of the schemas in oneOf");
+        }
+    }
+    if let Some(list) = schema["allOf"].as_array() {
+        for item in list {
+            validate(root, item, value, path)?;
+        }
+    }
+    match value {
+        Value::Object(map) => {
+            if let Some(required) = schema["required"].as_array() {
+                for key in required.iter().filter_map(|v| v.as_str()) {
+                    if !map.contains_key(key) {
+                        bail!("{path} is missing the required property '{key}'");
+                    }
+                }
+            }
+            let properties = schema["properties"].as_object();
+            for (key, item) in map {
+                let item_path = format!("{path}.{key}");
+                match properties.and_then(|v| v.get(key)) {
+                    Some(item_schema) => validate(root, item_schema, item, &item_path)?,
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            bail!("{path} has the unexpected property '{key}'")
+                        }
+                        Some(item_schema @ Value::Object(_)) => {
+                            validate(root, item_schema, item, &item_path)?
+                        }
+                        _ => {}
+                    },
+                }
+            }
+        }
+        Value::Array(list) => {
+            if let Some(min) = schema["minItems"].as_u64() {
+                if (list.len() as u64) < min {
+                    bail!("{path} must have at least {min} items");
+                }
+            }
+            if let Some(max) = schema["maxItems"].as_u64() {
+                if (list.len() as u64) > max {
+                    bail!("{path} must have at most {max} items");
+                }
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in list.iter().enumerate() {
+                    validate(root, item_schema, item, &format!("{path}[{i}]"))?;
+                }
+            }
+        }
+        Value::String(text) => {
+            let len = text.chars().count() as u64;
+            if let Some(min) = schema["minLength"].as_u64() {
+                if len < min {
+                    bail!("{path} must have at least {min} characters");
+                }
+            }
+            if let Some(max) = schema["maxLength"].as_u64() {
+                if len > max {
+                    bail!("{path} must have at most {max} characters");
+                }
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            if let Some(min) = schema["minimum"].as_f64() {
+                if number < min {
+                    bail!("{path} must be >= {min}");
+                }
+            }
+            if let Some(max) = schema["maximum"].as_f64() {
+                if number > max {
+                    bail!("{path} must be <= {max}");
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn is_type(value: &Value, name: &str) -> bool {
+    match name {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn resolve_ref<'a>(root: &'a Value, reference: &str) -> Result<&'a Value> {
+    let pointer = match reference.strip_prefix('#') {
+        Some(v) => v,
+        None => bail!("Unsupported schema reference '{reference}'"),
+    };
+    match root.pointer(pointer) {
+        Some(v) => Ok(v),
+        None => bail!("Unknown schema reference '{reference}'"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_json_schema() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "tags": { "type": "array", "items": { "$ref": "#/$defs/tag" } }
+            },
+            "required": ["name"],
+            "additionalProperties": false,
+            "$defs": { "tag": { "enum": ["a", "b"] } }
+        });
+        assert!(validate_json_schema(&schema, &json!({"name": "x", "tags": ["a"]})).is_ok());
+        let err = validate_json_schema(&schema, &json!({"tags": []})).unwrap_err();
+        assert_eq!(err.to_string(), "$ is missing the required property 'name'");
+        let err = validate_json_schema(&schema, &json!({"name": "x", "tags": ["c"]})).unwrap_err();
+        assert_eq!(err.to_string(), r#"$.tags[0] must be one of ["a","b"]"#);
+        let err = validate_json_schema(&schema, &json!({"name": 1})).unwrap_err();
+        assert_eq!(err.to_string(), r#"$.name must be of type "string", got 1"#);
+        assert!(validate_json_schema(&schema, &json!({"name": "x", "extra": 1})).is_err());
+    }
+}
@@ -3,8 +3,10 @@ mod clipboard;
 mod command;
 mod crypto;
 mod html_to_md;
+mod json_schema;
 mod loader;
 mod path;
+mod progress;
 mod render_prompt;
 mod request;
 mod spinner;
@@ -15,8 +17,10 @@ pub use self::clipboard::set_text;
 pub use self::command::*;
 pub use self::crypto::*;
 pub use self::html_to_md::*;
+pub use self::json_schema::validate_json_schema;
 pub use self::loader::*;
 pub use self::path::*;
+pub use self::progress::{ProgressFn, ProgressLines};
 pub use self::render_prompt::render_prompt;
 pub use self::request::*;
 pub use self::spinner::*;
@@ -36,6 +40,7 @@ pub static CODE_BLOCK_RE: LazyLock<Regex> =
 pub static THINK_TAG_RE: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"(?s)^\s*<think>.*?</think>(\s*|$)").unwrap());
 pub static IS_STDOUT_TERMINAL: LazyLock<bool> = LazyLock::new(|| std::io::stdout().is_terminal());
+pub static IS_STDIN_TERMINAL: LazyLock<bool> = LazyLock::new(|| std::io::stdin().is_terminal());
 pub static NO_COLOR: LazyLock<bool> = LazyLock::new(|| {
     env::var("NO_COLOR")
         .ok()
@@ -104,7 +109,7 @@ pub fn light_theme_from_colorfgbg(colorfgbg: &str) -> Option<bool> {
     Some(light)
 }
 
-pub fn strip_think_tag(text: &str) -> Cow<str> {
+pub fn strip_think_tag(text: &str) -> Cow<'_, str> {
     THINK_TAG_RE.replace_all(text, "")
 }
 
@@ -136,10 +141,34 @@ where
             Some((v, score))
         })
         .collect();
-    list.sort_unstable_by(|a, b| b.1.cmp(&a.1));
+    list.sort_unstable_by_key(|v| std::cmp::Reverse(v.1));
     list.into_iter().map(|(v, _)| v).collect()
 }
 
+/// Match `text` against a pattern where `*` matches any sequence and `?` any single char.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|v| *v == '*')
+}
+
 pub fn pretty_error(err: &anyhow::Error) -> String {
     let mut output = vec![];
     output.push(format!("Error: {err}"));
@@ -236,6 +265,17 @@ pub fn set_proxy(
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", "fs_write"));
+        assert!(glob_match("fs_*", "fs_write"));
+        assert!(glob_match("*__*", "github__create_issue"));
+        assert!(glob_match("fs_?rite", "fs_write"));
+        assert!(glob_match("execute_command", "execute_command"));
+        assert!(!glob_match("fs_*", "web_search"));
+        assert!(!glob_match("fs_?", "fs_write"));
+    }
+
     #[test]
     #[cfg(not(target_os = "windows"))]
     fn test_safe_join_path() {
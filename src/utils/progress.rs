@@ -0,0 +1,192 @@
+use super::{dimmed_text, IS_STDOUT_TERMINAL};
+
+use anyhow::Result;
+use crossterm::{cursor, queue, style, terminal};
+use parking_lot::Mutex;
+use std::{
+    io::{stdout, Write},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{sync::oneshot, task::JoinHandle, time::interval};
+use unicode_width::UnicodeWidthChar;
+
+/// Receives progress text from a running task.
+pub type ProgressFn = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Renders one live line per task, e.g. tool calls running concurrently.
+pub struct ProgressLines {
+    lines: Arc<Mutex<Vec<ProgressLine>>>,
+    stop_tx: Option<oneshot::Sender<()>>,
+    render_task: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+struct ProgressLine {
+    title: String,
+    detail: Option<String>,
+    state: ProgressState,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ProgressState {
+    Pending,
+    Running(Instant),
+    Done(Duration),
+    Failed(Duration),
+}
+
+impl ProgressLines {
+    pub fn new(titles: Vec<String>) -> Self {
+        let lines: Vec<ProgressLine> = titles
+            .into_iter()
+            .map(|title| ProgressLine {
+                title,
+                detail: None,
+                state: ProgressState::Pending,
+            })
+            .collect();
+        let lines = Arc::new(Mutex::new(lines));
+        let (stop_tx, render_task) = if *IS_STDOUT_TERMINAL {
+            let (stop_tx, stop_rx) = oneshot::channel();
+            let render_task = tokio::spawn(render_loop(lines.clone(), stop_rx));
+            (Some(stop_tx), Some(render_task))
+        } else {
+            (None, None)
+        };
+        Self {
+            lines,
+            stop_tx,
+            render_task,
+        }
+    }
+
+    pub fn start(&self, index: usize) {
+        if let Some(line) = self.lines.lock().get_mut(index) {
+            line.state = ProgressState::Running(Instant::now());
+        }
+    }
+
+    pub fn finish(&self, index: usize, success: bool) {
+        if let Some(line) = self.lines.lock().get_mut(index) {
+            let elapsed = match line.state {
+                ProgressState::Running(start) => start.elapsed(),
+                _ => Duration::default(),
+            };
+            line.state = match success {
+                true => ProgressState::Done(elapsed),
+                false => ProgressState::Failed(elapsed),
+            };
+        }
+    }
+
+    /// The latest progress text is shown next to the line while its task runs.
+    pub fn detail_fn(&self, index: usize) -> ProgressFn {
+        let lines = self.lines.clone();
+        Arc::new(move |detail: &str| {
+            if let Some(line) = lines.lock().get_mut(index) {
+                line.detail = Some(detail.to_string());
+            }
+        })
+    }
+
+    pub async fn stop(mut self) {
+        if let Some(stop_tx) = self.stop_tx.take() {
+            let _ = stop_tx.send(());
+        }
+        if let Some(render_task) = self.render_task.take() {
+            let _ = render_task.await;
+        }
+    }
+}
+
+async fn render_loop(lines: Arc<Mutex<Vec<ProgressLine>>>, mut stop_rx: oneshot::Receiver<()>) {
+    let mut interval = interval(Duration::from_millis(100));
+    let mut index = 0;
+    let mut drawn = 0;
+    loop {
+        tokio::select! {
+            _ = &mut stop_rx => {
+                let _ = render(&lines, index, &mut drawn, true);
+                break;
+            }
+            _ = interval.tick() => {
+                let _ = render(&lines, index, &mut drawn, false);
+                index += 1;
+            }
+        }
+    }
+}
+
+fn render(
+    lines: &Mutex<Vec<ProgressLine>>,
+    index: usize,
+    drawn: &mut u16,
+    last: bool,
+) -> Result<()> {
+    const FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+    let columns = match terminal::size() {
+        Ok((v, _)) if v > 0 => v as usize,
+        _ => 80,
+    };
+    let mut writer = stdout();
+    if *drawn > 0 {
+        queue!(writer, cursor::MoveToPreviousLine(*drawn))?;
+    } else {
+        queue!(writer, cursor::Hide)?;
+    }
+    let lines = lines.lock();
+    for line in lines.iter() {
+        let (icon, elapsed) = match line.state {
+            ProgressState::Pending => ("·".to_string(), None),
+            ProgressState::Running(start) => (
+                FRAMES[index % FRAMES.len()].to_string(),
+                Some(start.elapsed()),
+            ),
+            ProgressState::Done(elapsed) => ("✓".to_string(), Some(elapsed)),
+            ProgressState::Failed(elapsed) => ("✗".to_string(), Some(elapsed)),
+        };
+        let detail = match line.state {
+            ProgressState::Running(_) => line.detail.as_deref(),
+            _ => None,
+        };
+        let mut text = format!("{icon} {}", line.title);
+        if detail.is_some() {
+            text = truncate_text(&text, columns / 2);
+        }
+        if let Some(elapsed) = elapsed {
+            text.push_str(&format!(" ({:.1}s)", elapsed.as_secs_f64()));
+        }
+        if let Some(detail) = detail {
+            text.push_str(&format!(" {detail}"));
+        }
+        let text = truncate_text(&text, columns.saturating_sub(1));
+        queue!(
+            writer,
+            terminal::Clear(terminal::ClearType::CurrentLine),
+            style::Print(dimmed_text(&text)),
+            style::Print("\n"),
+        )?;
+    }
+    *drawn = lines.len() as u16;
+    if last {
+        queue!(writer, cursor::Show)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn truncate_text(text: &str, width: usize) -> String {
+    let mut output = String::new();
+    let mut used = 0;
+    for ch in text.chars() {
+        let ch = if ch == '\n' || ch == '\r' { ' ' } else { ch };
+        let ch_width = ch.width().unwrap_or(0);
+        if used + ch_width > width {
+            break;
+        }
+        used += ch_width;
+        output.push(ch);
+    }
+    output
+}